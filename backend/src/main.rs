@@ -4,38 +4,83 @@ mod services;
 use axum::extract::DefaultBodyLimit;
 use routing::main_route;
 use services::{
+    cli::{Command, USAGE},
     database::{migrator::Migrator, surreal::Database},
     file_storage::FileStorage,
-    repositories::user::UserRepository,
+    repositories::{sample::SampleRepository, user::UserRepository},
     runner::run,
 };
 
+use tracing::{info, warn};
+
 use crate::services::{app::AppState, config::setup_config, tracing::setup_tracing};
 
-// 64 MiB
+// 64 MiB, audio uploads have their own limit
 const REQUEST_SIZE_LIMIT: usize = 64 * 1024 * 1024;
 
 #[tokio::main]
 async fn main() {
+    let command = Command::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        std::process::exit(2);
+    });
     let config = setup_config();
     setup_tracing(&config.tracing);
     let auth_keys = (&config.auth_keys).try_into().expect("Missing PEMs");
     let database = Database::setup(&config.database)
         .await
         .expect("Failed to setup SurrealDB");
-    Migrator::new(&config.database.migrations)
-        .migrate(&database)
-        .await
-        .expect("Failed to migrate SurrealDB");
     let file_storage = FileStorage::setup(&config.file_storage)
         .await
         .expect("Failed to setup FileStorage");
+    let sample_repo = SampleRepository {
+        database: database.clone(),
+        file_storage: file_storage.clone(),
+    };
+
+    // A check without repair must leave the database and the storage untouched
+    let read_only = matches!(command, Command::Fsck(options) if !options.repair);
+    if !read_only {
+        Migrator::new(&config.database.migrations)
+            .migrate(&database)
+            .await
+            .expect("Failed to migrate SurrealDB");
+        sample_repo
+            .adopt_legacy_files()
+            .await
+            .expect("Failed to move legacy sample files");
+        sample_repo
+            .remove_released_files()
+            .await
+            .expect("Failed to remove files of deleted samples");
+    }
+
+    if let Command::Fsck(options) = command {
+        let report = sample_repo
+            .check_consistency(options)
+            .await
+            .expect("Failed to check consistency");
+        let json = serde_json::to_string_pretty(&report).expect("Report is serializable");
+        println!("{json}");
+        std::process::exit(if report.is_consistent() { 0 } else { 1 });
+    }
+    if config.consistency.check_at_startup {
+        let report = sample_repo
+            .check_consistency(config.consistency.options())
+            .await
+            .expect("Failed to check consistency");
+        if report.is_consistent() {
+            info!("Storage is consistent with the database");
+        } else {
+            warn!({report = ?report}, "Storage is inconsistent with the database");
+        }
+    }
+
     let state = AppState {
         auth_keys,
         database,
         file_storage,
     };
-
     let repo = UserRepository::new(state.database.clone());
     repo.try_create(&config.admin.username, &config.admin.password)
         .await
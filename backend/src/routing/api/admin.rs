@@ -0,0 +1,77 @@
+use axum::{
+    extract::{FromRef, Query},
+    routing::get,
+    Json, Router,
+};
+use hyper::StatusCode;
+use tracing::error;
+
+use crate::services::{
+    auth::{claims::Claims, AuthKeys},
+    database::surreal::Database,
+    file_storage::FileStorage,
+    repositories::{
+        consistency::{CheckOptions, ConsistencyReport},
+        sample::SampleRepository,
+    },
+    util::ResponseType,
+};
+
+pub fn admin_router<T>() -> Router<T>
+where
+    AuthKeys: FromRef<T>,
+    Database: FromRef<T>,
+    FileStorage: FromRef<T>,
+    T: 'static + Send + Sync + Clone,
+{
+    Router::new().route(
+        "/consistency",
+        get(check_consistency).post(repair_consistency),
+    )
+}
+
+/// Check storage consistency
+///
+/// Compare samples with the stored audio files and report rows with missing files, orphaned files and size or hash mismatches.
+/// Query parameter `verifyHashes` enables hashing every file. Nothing is changed, `repair` is ignored.
+async fn check_consistency(
+    audio_repo: SampleRepository,
+    claims: Claims,
+    Query(options): Query<CheckOptions>,
+) -> ResponseType<Json<ConsistencyReport>> {
+    let options = CheckOptions {
+        repair: false,
+        ..options
+    };
+    consistency_report(audio_repo, claims, options).await
+}
+
+/// Check and repair storage consistency
+///
+/// Like the check, but also quarantines orphaned files, removes stale cached files and finishes pending file removals.
+async fn repair_consistency(
+    audio_repo: SampleRepository,
+    claims: Claims,
+    Query(options): Query<CheckOptions>,
+) -> ResponseType<Json<ConsistencyReport>> {
+    let options = CheckOptions {
+        repair: true,
+        ..options
+    };
+    consistency_report(audio_repo, claims, options).await
+}
+
+async fn consistency_report(
+    audio_repo: SampleRepository,
+    _: Claims,
+    options: CheckOptions,
+) -> ResponseType<Json<ConsistencyReport>> {
+    let Ok(report) = audio_repo
+        .check_consistency(options)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while checking consistency"))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    ResponseType::Data(Json(report))
+}
@@ -1,25 +1,44 @@
 use axum::{
-    extract::{FromRef, Path},
-    routing::{delete, get, post},
-    Json, Router,
+    body::Body,
+    extract::{DefaultBodyLimit, FromRef, Path, Query},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post, put},
+    Extension, Json, Router,
 };
-use axum_extra::extract::Multipart;
+use axum_extra::extract::{multipart::Field, Multipart};
 use hyper::StatusCode;
-use tracing::error;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
+use tracing::{error, warn};
 use validator::Validate;
 
 use crate::services::{
+    audio::{probe::AudioFormat, AudioConfig, AudioError, Encoding},
     auth::{claims::Claims, AuthKeys},
-    database::{identified::StringIdentified, surreal::Database},
-    file_storage::FileStorage,
+    database::{error::DbError, identified::StringIdentified, surreal::Database},
+    file_response::FileRequest,
+    file_storage::{CachedFile, FileStorage, StagedFile},
     repositories::{
-        sample::{SampleInfo, SampleRepository},
-        IsViolatingUnique,
+        experiment::ExperimentReference,
+        sample::{
+            CreatedSample, SampleDeletion, SampleInfo, SampleInfoUpdate, SampleReplacement,
+            SampleRepository,
+        },
+        sample_ambisonics::{AmbisonicError, AmbisonicRender},
+        sample_binaural::{BinauralError, BinauralRender},
+        sample_export::{ExportError, ExportQuery},
+        sample_import::{ImportError, ImportOptions, ImportReport, ImportStatus},
+        sample_preview::{PeaksQuery, SpectrogramQuery},
+        sample_processing::{ProcessedSample, ProcessingError},
+        sample_search::{SamplePage, SampleQuery},
+        sample_synth::GeneratedSample,
+        IsViolatingUnique, RepoError,
     },
-    util::{ResponseType, ValidatedJsonRejection},
+    util::{ResponseType, ValidatedJson, ValidatedJsonRejection},
 };
 
-pub fn audio_router<T>() -> Router<T>
+pub fn audio_router<T>(config: &AudioConfig) -> Router<T>
 where
     AuthKeys: FromRef<T>,
     Database: FromRef<T>,
@@ -27,80 +46,430 @@ where
     T: 'static + Send + Sync + Clone,
 {
     Router::new()
-        .route("/", post(create_audio))
+        .route(
+            "/",
+            post(create_audio).layer(DefaultBodyLimit::max(config.upload_size_limit)),
+        )
+        .route(
+            "/import",
+            post(import_audio).layer(DefaultBodyLimit::max(config.upload_size_limit)),
+        )
+        .route("/generate", post(generate_audio))
+        .route("/:id/binaural", post(render_binaural))
+        .route("/:id/process", post(process_audio))
         .route("/:id", delete(delete_audio))
+        .route("/:id", patch(update_audio))
+        .route(
+            "/:id/data",
+            put(replace_audio).layer(DefaultBodyLimit::max(config.upload_size_limit)),
+        )
+        .route("/", get(search_audio))
         .route("/all", get(get_all))
+        .route("/export", get(export_audio))
         .route("/:id", get(get_audio))
+        .route("/:id/binaural", get(get_ambisonic_binaural))
+        .route("/:id/peaks", get(get_peaks))
+        .route("/:id/spectrogram", get(get_spectrogram))
+        .layer(Extension(config.clone()))
 }
 
 /// Create audio sample
 ///
 /// Upload an audio sample to file storage and insert metadata into the database. Return sample indentifier.
+/// Audio data is streamed to disk, unsupported or corrupt audio files are rejected.
+/// Lists names of existing samples with identical audio, which share the stored file.
 async fn create_audio(
     audio_repo: SampleRepository,
     _: Claims,
+    Extension(config): Extension<AudioConfig>,
     mut multipart: Multipart,
-) -> ResponseType<Json<StringIdentified<SampleInfo>>> {
-    let Ok(Some(info)) = multipart
-        .next_field()
-        .await
-        .map_err(|e| error!({error = ?e}, "Encountered an error while reading a sample"))
-    else {
-        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+) -> ResponseType<Json<CreatedSample>> {
+    let info = match next_field(&mut multipart).await {
+        Ok(info) => info,
+        Err(status) => return ResponseType::Status(status),
     };
-    let Ok(info_bytes) = info
-        .bytes()
-        .await
-        .map_err(|e| error!({error = ?e}, "Encountered an error while reading a sample"))
-    else {
-        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    let info_bytes = match info.bytes().await {
+        Ok(info_bytes) => info_bytes,
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while reading a sample");
+            return ResponseType::Status(e.status());
+        }
     };
     let Json(info): Json<SampleInfo> = match Json::from_bytes(&info_bytes) {
         Ok(info) => info,
         Err(err) => return ResponseType::JsonErr(ValidatedJsonRejection::Json(err)),
     };
-    let Ok(Some(data)) = multipart
-        .next_field()
-        .await
-        .map_err(|e| error!({error = ?e}, "Encountered an error while reading a sample"))
-    else {
-        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
-    };
-    let Ok(data) = data
-        .bytes()
-        .await
-        .map_err(|e| error!({error = ?e}, "Encountered an error while reading a sample"))
-    else {
-        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
-    };
     if let Err(err) = info.validate().map_err(|e| {
         error!({error = ?e}, "Invalid sample info");
         e
     }) {
         return ResponseType::JsonErr(ValidatedJsonRejection::Validation(err));
     }
-    let result = audio_repo.create(info, data).await.map_err(|e| {
+    let file = match stage_next_field(&audio_repo.file_storage, &mut multipart).await {
+        Ok(file) => file,
+        Err(status) => return ResponseType::Status(status),
+    };
+    let result = audio_repo.create_staged(info, file).await.map_err(|e| {
         error!({error = ?e}, "Encountered an error while adding a sample");
         e
     });
     if result.is_violating_unique() {
-        ResponseType::Status(StatusCode::CONFLICT)
-    } else if let Ok(result) = result {
-        ResponseType::Data(Json(result))
-    } else {
-        ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(result) => {
+            prepare_in_background(&audio_repo, &config, vec![result.sample.id.clone()]);
+            ResponseType::Data(Json(result))
+        }
+        Err(RepoError::Audio(e)) => ResponseType::Error(audio_error_status(&e), e.to_string()),
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Generate audio sample
+///
+/// Render a synthetic stimulus (noise, tone, chirp or click train) as a WAV file and create a sample from it.
+/// The stimulus parameters are stored with the sample as `generator`.
+/// Stimuli larger than the upload size limit are rejected.
+async fn generate_audio(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Extension(config): Extension<AudioConfig>,
+    ValidatedJson(sample): ValidatedJson<GeneratedSample>,
+) -> ResponseType<Json<CreatedSample>> {
+    if sample.stimulus.wav_size() > config.upload_size_limit as u64 {
+        return ResponseType::Error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Stimulus exceeds the upload size limit".to_owned(),
+        );
+    }
+    let result = audio_repo.generate(sample).await.map_err(|e| {
+        error!({error = ?e}, "Encountered an error while generating a sample");
+        e
+    });
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(result) => {
+            prepare_in_background(&audio_repo, &config, vec![result.sample.id.clone()]);
+            ResponseType::Data(Json(result))
+        }
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Render audio sample binaurally
+///
+/// Convolve a mono sample with head-related impulse responses of an uploaded HRTF set and create a stereo sample for each target direction.
+/// All samples are created or none, the source and HRTF set are stored with each one as `binaural`.
+async fn render_binaural(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Extension(config): Extension<AudioConfig>,
+    Path(id): Path<String>,
+    ValidatedJson(render): ValidatedJson<BinauralRender>,
+) -> ResponseType<Json<Vec<CreatedSample>>> {
+    let result = audio_repo.render_binaural(id, render).await.map_err(|e| {
+        error!({error = ?e}, "Encountered an error while rendering a sample");
+        e
+    });
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(samples) => {
+            let ids = samples
+                .iter()
+                .map(|sample| sample.sample.id.clone())
+                .collect();
+            prepare_in_background(&audio_repo, &config, ids);
+            ResponseType::Data(Json(samples))
+        }
+        Err(e @ (BinauralError::NotMono(_) | BinauralError::Clipping { .. })) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        }
+        Err(BinauralError::Repo(RepoError::Database(DbError::NotFound))) => {
+            ResponseType::Status(StatusCode::NOT_FOUND)
+        }
+        Err(BinauralError::Repo(RepoError::Audio(e))) => {
+            ResponseType::Error(audio_error_status(&e), e.to_string())
+        }
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Process audio sample
+///
+/// Apply a chain of operations to a sample and create a new sample from the result, the original is left untouched.
+/// Operations are `trim`, `trimSilence`, `ramps`, `gain` and `resample`, applied in the order given.
+/// The new sample copies the position, tags and description of its parent and records the parent and operations as `derivation`.
+async fn process_audio(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Extension(config): Extension<AudioConfig>,
+    Path(id): Path<String>,
+    ValidatedJson(processed): ValidatedJson<ProcessedSample>,
+) -> ResponseType<Json<CreatedSample>> {
+    let result = audio_repo.process(id, processed).await.map_err(|e| {
+        error!({error = ?e}, "Encountered an error while processing a sample");
+        e
+    });
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(result) => {
+            prepare_in_background(&audio_repo, &config, vec![result.sample.id.clone()]);
+            ResponseType::Data(Json(result))
+        }
+        Err(e @ (ProcessingError::Empty | ProcessingError::Clipping { .. })) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        }
+        Err(ProcessingError::Repo(RepoError::Database(DbError::NotFound))) => {
+            ResponseType::Status(StatusCode::NOT_FOUND)
+        }
+        Err(ProcessingError::Repo(RepoError::Audio(e))) => {
+            ResponseType::Error(audio_error_status(&e), e.to_string())
+        }
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Import audio samples
+///
+/// Upload a ZIP archive with audio files and a `manifest.csv` or `manifest.json` as a multipart form with a single field.
+/// Samples are created only if every manifest row is valid, the report lists the outcome of each row.
+/// Query parameter `onConflict` decides what happens to rows with taken names: `skip`, `fail` (default) or `rename`.
+async fn import_audio(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Extension(config): Extension<AudioConfig>,
+    Query(mut options): Query<ImportOptions>,
+    mut multipart: Multipart,
+) -> ResponseType<(StatusCode, Json<ImportReport>)> {
+    let file = match stage_next_field(&audio_repo.file_storage, &mut multipart).await {
+        Ok(file) => file,
+        Err(status) => return ResponseType::Status(status),
+    };
+    // Audio compresses poorly, so the extracted files get some headroom over the archive
+    options.extracted_size_limit = Some(4 * config.upload_size_limit as u64);
+    let result = audio_repo.import(file, options).await;
+    // A sample with the same name was created during the import
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(report) if report.imported => {
+            let ids = report
+                .rows
+                .iter()
+                .filter_map(|row| match &row.status {
+                    ImportStatus::Created { id } => Some(id.clone()),
+                    _ => None,
+                })
+                .collect();
+            prepare_in_background(&audio_repo, &config, ids);
+            ResponseType::Data((StatusCode::OK, Json(report)))
+        }
+        Ok(report) => ResponseType::Data((StatusCode::UNPROCESSABLE_ENTITY, Json(report))),
+        Err(e @ (ImportError::TooLarge | ImportError::ManifestTooLarge)) => {
+            ResponseType::Error(StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+        }
+        Err(ImportError::Repo(e)) => {
+            error!({error = ?e}, "Encountered an error while importing samples");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(e) => ResponseType::Error(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+/// Export audio samples
+///
+/// Send a ZIP archive with audio files and a manifest in the format accepted by the import.
+/// Query parameter `ids` takes comma separated sample identifiers, `experiment` an experiment identifier, and `manifest` either `csv` (default) or `json`.
+async fn export_audio(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Query(query): Query<ExportQuery>,
+) -> ResponseType<Response> {
+    if query.ids.is_some() && query.experiment.is_some() {
+        return ResponseType::Error(
+            StatusCode::BAD_REQUEST,
+            "Filter by either ids or experiment".to_owned(),
+        );
+    }
+    let export = match audio_repo.export(query).await {
+        Ok(export) => export,
+        Err(ExportError::Repo(RepoError::Database(DbError::NotFound))) => {
+            return ResponseType::Status(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while exporting samples");
+            return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let archive = match export.build(&audio_repo.file_storage).await {
+        Ok(archive) => archive,
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while writing a sample archive");
+            return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    ResponseType::Data(
+        (
+            [
+                (header::CONTENT_TYPE, "application/zip".to_owned()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"samples.zip\"".to_owned(),
+                ),
+                (header::CONTENT_LENGTH, archive.size.to_string()),
+            ],
+            Body::from_stream(ReaderStream::new(archive)),
+        )
+            .into_response(),
+    )
+}
+
+pub(super) async fn next_field(multipart: &mut Multipart) -> Result<Field, StatusCode> {
+    match multipart.next_field().await {
+        Ok(Some(field)) => Ok(field),
+        Ok(None) => Err(StatusCode::BAD_REQUEST),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while reading a sample");
+            Err(e.status())
+        }
+    }
+}
+
+/// Stream the next multipart field into a staged file
+pub(super) async fn stage_next_field(
+    file_storage: &FileStorage,
+    multipart: &mut Multipart,
+) -> Result<StagedFile, StatusCode> {
+    let mut data = next_field(multipart).await?;
+    let mut file = file_storage.stage().await.map_err(|e| {
+        error!({error = ?e}, "Encountered an error while staging a sample");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    loop {
+        let chunk = match data.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                error!({error = ?e}, "Encountered an error while reading a sample");
+                return Err(e.status());
+            }
+        };
+        if let Err(e) = file.write(&chunk).await {
+            error!({error = ?e}, "Encountered an error while staging a sample");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    Ok(file)
+}
+
+pub(super) fn audio_error_status(error: &AudioError) -> StatusCode {
+    match error {
+        AudioError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        AudioError::Malformed(_) | AudioError::NoTrack | AudioError::Channels(_) => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        AudioError::TooLong(_) => StatusCode::PAYLOAD_TOO_LARGE,
+    }
+}
+
+/// Update an audio sample
+///
+/// Change name, position, tags, description, Ambisonic order or speaker of the sample with given identifier, omitted fields are kept.
+/// Binding the sample to a speaker of a layout moves it to the speaker's position.
+async fn update_audio(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Path(id): Path<String>,
+    ValidatedJson(update): ValidatedJson<SampleInfoUpdate>,
+) -> ResponseType<Json<StringIdentified<SampleInfo>>> {
+    let result = audio_repo.update_info(id, update).await;
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(sample) => ResponseType::Data(Json(sample)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(RepoError::Audio(e)) => ResponseType::Error(audio_error_status(&e), e.to_string()),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while updating a sample");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Replace audio of a sample
+///
+/// Upload new audio data for the sample with given identifier as a multipart form with a single `data` field.
+/// Samples with collected results keep their audio, the conflict response lists experiments with those results.
+async fn replace_audio(
+    audio_repo: SampleRepository,
+    _: Claims,
+    Extension(config): Extension<AudioConfig>,
+    Path(id): Path<String>,
+    mut multipart: Multipart,
+) -> ResponseType<(StatusCode, Json<SampleReplacementResponse>)> {
+    let file = match stage_next_field(&audio_repo.file_storage, &mut multipart).await {
+        Ok(file) => file,
+        Err(status) => return ResponseType::Status(status),
+    };
+    match audio_repo.replace_data(id, file).await {
+        Ok(SampleReplacement::Replaced(sample)) => {
+            prepare_in_background(&audio_repo, &config, vec![sample.sample.id.clone()]);
+            ResponseType::Data((
+                StatusCode::OK,
+                Json(SampleReplacementResponse::Replaced(sample)),
+            ))
+        }
+        Ok(SampleReplacement::HasResults(experiments)) => ResponseType::Data((
+            StatusCode::CONFLICT,
+            Json(SampleReplacementResponse::InUse(SampleInUse {
+                message: "Sample has results in experiments",
+                experiments,
+            })),
+        )),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Audio(e)) => ResponseType::Error(audio_error_status(&e), e.to_string()),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while replacing a sample");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum SampleReplacementResponse {
+    Replaced(Box<CreatedSample>),
+    InUse(SampleInUse),
+}
+
 /// Delete an audio sample
 ///
 /// Delete an audio sample with given identifier.
+/// Samples used by experiments are kept, the conflict response lists those experiments.
 async fn delete_audio(
     audio_repo: SampleRepository,
     _: Claims,
     Path(id): Path<String>,
-) -> ResponseType<()> {
-    let Ok(deleted) = audio_repo
+) -> ResponseType<(StatusCode, Json<SampleInUse>)> {
+    let Ok(deletion) = audio_repo
         .delete(id)
         .await
         .map_err(|e| error!({error = ?e}, "Encountered an error while deleting a sample"))
@@ -108,13 +477,25 @@ async fn delete_audio(
         return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
     };
 
-    if deleted {
-        ResponseType::Status(StatusCode::OK)
-    } else {
-        ResponseType::Status(StatusCode::CONFLICT)
+    match deletion {
+        SampleDeletion::Deleted => ResponseType::Status(StatusCode::OK),
+        SampleDeletion::InUse(experiments) => ResponseType::Data((
+            StatusCode::CONFLICT,
+            Json(SampleInUse {
+                message: "Sample is used by experiments",
+                experiments,
+            }),
+        )),
     }
 }
 
+/// Body of a refused sample change
+#[derive(Debug, Serialize)]
+struct SampleInUse {
+    message: &'static str,
+    experiments: Vec<StringIdentified<ExperimentReference>>,
+}
+
 /// List all audio samples
 ///
 /// List all available audio sample identifiers
@@ -132,20 +513,312 @@ async fn get_all(
     ResponseType::Data(Json(samples))
 }
 
+/// Search audio samples
+///
+/// Return a page of samples filtered by name, tags, azimuth and elevation ranges, and sorted by name, azimuth or elevation.
+/// The `nextCursor` of a page is passed as `cursor` to get the following page.
+/// Samples of the page further than `loudnessThreshold` LU from the median loudness of all matches are listed as `loudnessOutliers`.
+async fn search_audio(
+    audio_repo: SampleRepository,
+    Extension(config): Extension<AudioConfig>,
+    Query(mut query): Query<SampleQuery>,
+) -> ResponseType<Json<SamplePage>> {
+    if let Err(err) = query.validate() {
+        return ResponseType::JsonErr(ValidatedJsonRejection::Validation(err));
+    }
+    query
+        .loudness_threshold
+        .get_or_insert(config.loudness.outlier_threshold);
+    let Ok(page) = audio_repo
+        .search(query)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while searching samples"))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    ResponseType::Data(Json(page))
+}
+
 /// Get audio sample data
 ///
-/// Get raw data of an audio sample with given identifier.
+/// Stream data of an audio sample with given identifier.
+/// Supports range requests and conditional requests for browser caching.
+/// Query parameter `format` picks the uploaded file (`original`), a canonical PCM WAV (`wav`) or a FLAC copy (`flac`).
+/// Without it the `Accept` header decides, wildcards get the uploaded file.
+/// Copies hold every sample of the upload, lossy, floating point and 32-bit samples are always streamed as uploaded.
+/// With `normalized=true`, or by default when normalization is configured, a copy at the target loudness is streamed as WAV or FLAC.
+/// Samples of unknown loudness are streamed without normalization.
 async fn get_audio(
     audio_repo: SampleRepository,
+    Extension(config): Extension<AudioConfig>,
     Path(id): Path<String>,
-) -> ResponseType<bytes::Bytes> {
-    let Ok(sample) = audio_repo
-        .data(id)
+    Query(query): Query<AudioQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let mut response = deliver_audio(audio_repo, config, id, query, &headers)
         .await
-        .map_err(|e| error!({error = ?e}, "Encountered an error while deleting a sample"))
+        .into_response();
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+async fn deliver_audio(
+    audio_repo: SampleRepository,
+    config: AudioConfig,
+    id: String,
+    query: AudioQuery,
+    headers: &HeaderMap,
+) -> ResponseType<Response> {
+    let file = match audio_repo.file(id.clone()).await {
+        Ok(file) => file,
+        Err(RepoError::Database(DbError::NotFound)) => {
+            return ResponseType::Status(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while getting a sample");
+            return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let encoding = query
+        .format
+        .unwrap_or_else(|| negotiate_delivery(headers))
+        .encoding();
+    if query.normalized.unwrap_or(config.loudness.normalize) {
+        let channels = file
+            .sample
+            .format
+            .as_ref()
+            .map_or(0, |format| format.channels as usize);
+        let encoding = encoding
+            .filter(|encoding| encoding.holds(channels))
+            .unwrap_or(Encoding::Wav);
+        match audio_repo
+            .normalized(id.clone(), config.loudness.target, encoding)
+            .await
+        {
+            Ok(Some(normalized)) => {
+                return respond_cached(&audio_repo, headers, encoding.mime_type(), normalized).await
+            }
+            Ok(None) => {}
+            Err(e) => return derived_error(e),
+        }
+    }
+    if let Some(encoding) = encoding {
+        match audio_repo.variant(id, encoding).await {
+            Ok(Some(variant)) => {
+                return respond_cached(&audio_repo, headers, encoding.mime_type(), variant).await
+            }
+            Ok(None) => {}
+            Err(e) => return derived_error(e),
+        }
+    }
+    let content_type = file
+        .sample
+        .format
+        .as_ref()
+        .map_or("application/octet-stream", AudioFormat::mime_type);
+    let etag = format!("\"{}\"", file.blob).parse().ok();
+    let request = FileRequest::evaluate(headers, content_type, file.metadata, etag);
+    let Ok(response) = request
+        .respond(&audio_repo.file_storage, &file.blob)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while reading a sample"))
     else {
         return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
     };
 
-    ResponseType::Data(sample)
+    ResponseType::Data(response)
+}
+
+#[derive(Debug, Deserialize)]
+struct AudioQuery {
+    normalized: Option<bool>,
+    format: Option<Delivery>,
+}
+
+/// Encoding in which a sample is streamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Delivery {
+    Original,
+    Wav,
+    Flac,
+}
+
+impl Delivery {
+    fn encoding(self) -> Option<Encoding> {
+        match self {
+            Self::Original => None,
+            Self::Wav => Some(Encoding::Wav),
+            Self::Flac => Some(Encoding::Flac),
+        }
+    }
+}
+
+/// Pick the delivery with the highest quality named in the `Accept` header, FLAC wins ties
+///
+/// Wildcards get the uploaded file, browsers send them for media elements whatever they can play.
+fn negotiate_delivery(headers: &HeaderMap) -> Delivery {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let delivery = match parts.next()?.to_ascii_lowercase().as_str() {
+                "audio/flac" | "audio/x-flac" => Delivery::Flac,
+                "audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" => Delivery::Wav,
+                _ => return None,
+            };
+            let quality = match parts.find_map(|param| param.strip_prefix("q=")) {
+                Some(quality) => quality.parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            Some((delivery, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold((Delivery::Original, 0.0), |best, (delivery, quality)| {
+            if quality > best.1 || (quality == best.1 && delivery == Delivery::Flac) {
+                (delivery, quality)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+/// Get binaural rendering of an Ambisonic sample
+///
+/// Decode an Ambisonic sample for a head orientation given by `yaw`, `pitch` and `roll` query parameters with the HRTF set `hrtfId`.
+/// The virtual loudspeaker decoder is set by `speakers`, `weighting` (`basic` or `maxRe`) and `interpolation`.
+/// Renderings are cached, repeated requests stream the stored file with range and conditional request support.
+async fn get_ambisonic_binaural(
+    audio_repo: SampleRepository,
+    Path(id): Path<String>,
+    Query(render): Query<AmbisonicRender>,
+    headers: HeaderMap,
+) -> ResponseType<Response> {
+    if let Err(err) = render.validate() {
+        return ResponseType::JsonErr(ValidatedJsonRejection::Validation(err));
+    }
+    let rendered = match audio_repo.render_ambisonics(id, render).await {
+        Ok(rendered) => rendered,
+        Err(e @ (AmbisonicError::NotAmbisonic | AmbisonicError::Clipping { .. })) => {
+            return ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        }
+        Err(AmbisonicError::Repo(RepoError::Database(DbError::NotFound))) => {
+            return ResponseType::Status(StatusCode::NOT_FOUND)
+        }
+        Err(AmbisonicError::Repo(RepoError::Audio(e))) => {
+            return ResponseType::Error(audio_error_status(&e), e.to_string())
+        }
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while rendering a sample");
+            return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    respond_cached(&audio_repo, &headers, "audio/wav", rendered).await
+}
+
+/// Get waveform peaks of an audio sample
+///
+/// Return the minimum and maximum of consecutive frames of each channel as `[min, max]` pairs, `resolution` pairs per channel at most.
+/// Peaks are cached until the audio of the sample is replaced, repeated requests support conditional requests.
+async fn get_peaks(
+    audio_repo: SampleRepository,
+    Path(id): Path<String>,
+    Query(query): Query<PeaksQuery>,
+    headers: HeaderMap,
+) -> ResponseType<Response> {
+    if let Err(err) = query.validate() {
+        return ResponseType::JsonErr(ValidatedJsonRejection::Validation(err));
+    }
+    match audio_repo.peaks(id, query).await {
+        Ok(peaks) => respond_cached(&audio_repo, &headers, "application/json", peaks).await,
+        Err(e) => derived_error(e),
+    }
+}
+
+/// Get spectrogram of an audio sample
+///
+/// Return a PNG image `width` by `height` pixels with time on the horizontal and frequency up to half the sample rate on the vertical axis.
+/// Images are cached until the audio of the sample is replaced, repeated requests support conditional requests.
+async fn get_spectrogram(
+    audio_repo: SampleRepository,
+    Path(id): Path<String>,
+    Query(query): Query<SpectrogramQuery>,
+    headers: HeaderMap,
+) -> ResponseType<Response> {
+    if let Err(err) = query.validate() {
+        return ResponseType::JsonErr(ValidatedJsonRejection::Validation(err));
+    }
+    match audio_repo.spectrogram(id, query).await {
+        Ok(image) => respond_cached(&audio_repo, &headers, "image/png", image).await,
+        Err(e) => derived_error(e),
+    }
+}
+
+fn derived_error(error: RepoError) -> ResponseType<Response> {
+    match error {
+        RepoError::Database(DbError::NotFound) => ResponseType::Status(StatusCode::NOT_FOUND),
+        RepoError::Audio(e) => ResponseType::Error(audio_error_status(&e), e.to_string()),
+        e => {
+            error!({error = ?e}, "Encountered an error while deriving a file from a sample");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Stream a file derived from a sample, its name changes with the contents so it doubles as the entity tag
+async fn respond_cached(
+    audio_repo: &SampleRepository,
+    headers: &HeaderMap,
+    content_type: &'static str,
+    cached: CachedFile,
+) -> ResponseType<Response> {
+    let etag = format!("\"{}\"", cached.name).parse().ok();
+    let request = FileRequest::evaluate(headers, content_type, cached.metadata, etag);
+    let Ok(response) = request
+        .respond(&audio_repo.file_storage, &cached.name)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while reading a cached file"))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    ResponseType::Data(response)
+}
+
+/// Write delivered copies of new samples in the background
+///
+/// WAV and FLAC copies are always written, normalized ones when normalization is configured.
+/// Failures are only logged, a missing copy is written on the first request for it.
+fn prepare_in_background(audio_repo: &SampleRepository, config: &AudioConfig, ids: Vec<String>) {
+    let audio_repo = SampleRepository {
+        database: audio_repo.database.clone(),
+        file_storage: audio_repo.file_storage.clone(),
+    };
+    let loudness = config.loudness;
+    tokio::spawn(async move {
+        for id in ids {
+            for encoding in [Encoding::Wav, Encoding::Flac] {
+                if let Err(e) = audio_repo.variant(id.clone(), encoding).await {
+                    warn!({error = ?e}, "Failed to write a copy of a sample");
+                }
+                if !loudness.normalize {
+                    continue;
+                }
+                let normalized = audio_repo
+                    .normalized(id.clone(), loudness.target, encoding)
+                    .await;
+                if let Err(e) = normalized {
+                    warn!({error = ?e}, "Failed to write a normalized copy of a sample");
+                }
+            }
+        }
+    });
 }
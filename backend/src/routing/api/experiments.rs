@@ -1,9 +1,10 @@
 use axum::{
-    extract::{FromRef, Path},
-    routing::{delete, get, post},
+    extract::{FromRef, Path, Query},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use crate::services::{
@@ -11,11 +12,16 @@ use crate::services::{
         claims::{Claims, OptClaims},
         AuthKeys,
     },
-    database::{identified::StringIdentified, surreal::Database},
+    database::{error::DbError, identified::StringIdentified, surreal::Database},
     file_storage::FileStorage,
     repositories::{
-        experiment::{Experiment, ExperimentRepository, ExperimentResult},
-        IsViolatingUnique,
+        experiment::{
+            Experiment, ExperimentChange, ExperimentRepository, ExperimentResult,
+            ExperimentSamples, ExperimentTransition, ExperimentUpdate, SampleReference,
+        },
+        experiment_sessions::{SessionChange, SessionProgress, SessionRequest, TrialAnswer},
+        experiment_trials::{TrialRequest, TrialSequence},
+        IsViolatingUnique, RepoError,
     },
     util::{ResponseType, ValidatedJson},
 };
@@ -31,14 +37,25 @@ where
         .route("/", post(create_experiment))
         .route("/", get(list_experiments))
         .route("/:id", get(get_experiment))
+        .route("/:id", patch(update_experiment))
         .route("/:id", delete(delete_experiment))
+        .route("/:id/samples", put(replace_samples))
+        .route("/:id/state", put(transition_experiment))
+        .route("/:id/trials", post(create_trials))
+        .route("/:id/trials", get(get_trials))
+        .route("/:id/sessions", post(create_session))
+        .route("/:id/sessions", get(list_sessions))
+        .route("/sessions/:id", get(get_session))
+        .route("/sessions/:id/trials/:trial", put(answer_trial))
+        .route("/sessions/:id/finish", post(finish_session))
         .route("/results/:id", get(get_results))
         .route("/results/:id", post(post_result))
 }
 
 /// Create experiment
 ///
-/// Create an experiment.
+/// New experiments are open unless `state` is `draft`, `opens_at` and `closes_at` limit when an open experiment takes results.
+/// New experiments are drafts unless `state` is `open`, `opens_at` and `closes_at` limit when an open experiment takes results.
 async fn create_experiment(
     repo: ExperimentRepository,
     _: Claims,
@@ -49,14 +66,131 @@ async fn create_experiment(
         e
     });
     if result.is_violating_unique() {
-        ResponseType::Status(StatusCode::CONFLICT)
-    } else if let Ok(result) = result {
-        ResponseType::Data(Json(result))
-    } else {
-        ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(result) => ResponseType::Data(Json(result)),
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Update experiment
+///
+/// Change name, visibility, schedule or speaker layout of the experiment with given identifier, omitted fields are kept.
+/// Changing the speaker layout of an experiment with results is refused unless `force=true` or the experiment is a draft,
+/// the conflict response lists samples with results.
+async fn update_experiment(
+    repo: ExperimentRepository,
+    _: Claims,
+    Path(id): Path<String>,
+    Query(ForceQuery { force }): Query<ForceQuery>,
+    ValidatedJson(update): ValidatedJson<ExperimentUpdate>,
+) -> ResponseType<(StatusCode, Json<ExperimentChangeResponse>)> {
+    let result = repo.update_info(id, update, force).await;
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    change_response(result)
+}
+
+/// Replace experiment samples
+///
+/// Set the samples of the experiment with given identifier, kept samples keep their results.
+/// Removing samples with results is refused unless `force=true` or the experiment is a draft, which deletes those results;
+/// the conflict response lists the samples.
+async fn replace_samples(
+    repo: ExperimentRepository,
+    _: Claims,
+    Path(id): Path<String>,
+    Query(ForceQuery { force }): Query<ForceQuery>,
+    ValidatedJson(samples): ValidatedJson<ExperimentSamples>,
+) -> ResponseType<(StatusCode, Json<ExperimentChangeResponse>)> {
+    change_response(repo.replace_samples(id, samples.sample_ids, force).await)
+}
+
+/// Change experiment state
+///
+/// Move the experiment with given identifier to `draft`, `open`, `closed` or `archived`.
+/// Drafts are opened or archived, open experiments are closed, closed ones are reopened or archived and archived ones are closed again.
+async fn transition_experiment(
+    repo: ExperimentRepository,
+    _: Claims,
+    Path(id): Path<String>,
+    ValidatedJson(transition): ValidatedJson<ExperimentTransition>,
+) -> ResponseType<(StatusCode, Json<ExperimentChangeResponse>)> {
+    change_response(repo.transition(id, transition.state).await)
+}
+
+fn change_response(
+    result: Result<ExperimentChange, RepoError>,
+) -> ResponseType<(StatusCode, Json<ExperimentChangeResponse>)> {
+    match result {
+        Ok(ExperimentChange::Changed(experiment)) => ResponseType::Data((
+            StatusCode::OK,
+            Json(ExperimentChangeResponse::Changed(experiment)),
+        )),
+        Ok(ExperimentChange::HasResults(samples)) => ResponseType::Data((
+            StatusCode::CONFLICT,
+            Json(ExperimentChangeResponse::HasResults(ExperimentHasResults {
+                message:
+                    "The change invalidates results, repeat it with force=true to apply it anyway",
+                samples,
+            })),
+        )),
+        Ok(ExperimentChange::MissingSamples(ids)) => ResponseType::Error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Samples do not exist: {}", ids.join(", ")),
+        ),
+        Ok(ExperimentChange::InvalidSchedule) => ResponseType::Error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The experiment closes before it opens".to_owned(),
+        ),
+        Ok(ExperimentChange::Transition { from, to }) => ResponseType::Error(
+            StatusCode::CONFLICT,
+            format!("An experiment which is {from} cannot become {to}"),
+        ),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while updating an experiment.");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct ForceQuery {
+    /// Apply a change even though it invalidates results
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    /// Include archived experiments
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum ExperimentChangeResponse {
+    Changed(Box<StringIdentified<Experiment>>),
+    HasResults(ExperimentHasResults),
+}
+
+/// Body of a refused experiment change
+#[derive(Debug, Serialize)]
+struct ExperimentHasResults {
+    message: &'static str,
+    samples: Vec<StringIdentified<SampleReference>>,
+}
+
 /// Delete experiment
 ///
 /// Delete the experiment and all of its results.
@@ -78,14 +212,15 @@ async fn delete_experiment(
 /// List experiments
 ///
 /// List existing experiments.
-/// If user is logged in lists all experiments.
-/// If user is not logged in, lists only public experiments.
+/// If user is logged in lists all experiments, archived ones only with `archived=true`.
+/// If user is not logged in, lists only public experiments which are neither drafts nor archived.
 async fn list_experiments(
     repo: ExperimentRepository,
     claims: OptClaims,
+    Query(ListQuery { archived }): Query<ListQuery>,
 ) -> ResponseType<Json<Vec<StringIdentified<Experiment>>>> {
     let result = if claims.logged_in() {
-        repo.infos().await
+        repo.infos(archived).await
     } else {
         repo.public_infos().await
     }
@@ -114,6 +249,149 @@ async fn get_experiment(
     ResponseType::Data(Json(result))
 }
 
+/// Draw trial sequence
+///
+/// Draw and store the trial sequence of a new participant from the design of the experiment.
+/// Only experiments taking results hand out sequences, results name the sequence with `sequence_id`.
+async fn create_trials(
+    repo: ExperimentRepository,
+    Path(id): Path<String>,
+    ValidatedJson(request): ValidatedJson<TrialRequest>,
+) -> ResponseType<Json<StringIdentified<TrialSequence>>> {
+    match repo.create_trials(id, request).await {
+        Ok(sequence) => ResponseType::Data(Json(sequence)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Closed(message)) => ResponseType::Error(StatusCode::CONFLICT, message),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while drawing a trial sequence.");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get trial sequences
+///
+/// Get the trial sequences drawn for the experiment in the order they were drawn.
+async fn get_trials(
+    repo: ExperimentRepository,
+    _: Claims,
+    Path(id): Path<String>,
+) -> ResponseType<Json<Vec<StringIdentified<TrialSequence>>>> {
+    let Ok(result) = repo
+        .trials(id)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while getting trial sequences."))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    ResponseType::Data(Json(result))
+}
+
+/// Start session
+///
+/// Start a participant session of the experiment with a newly drawn trial sequence.
+/// The response holds the session identifier, the trials and the trial answered next.
+async fn create_session(
+    repo: ExperimentRepository,
+    Path(id): Path<String>,
+    ValidatedJson(request): ValidatedJson<SessionRequest>,
+) -> ResponseType<Json<SessionProgress>> {
+    match repo.create_session(id, request).await {
+        Ok(session) => ResponseType::Data(Json(session)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Closed(message)) => ResponseType::Error(StatusCode::CONFLICT, message),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while starting a session.");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// List sessions
+///
+/// List sessions of the experiment with their status, abandoned sessions included.
+async fn list_sessions(
+    repo: ExperimentRepository,
+    _: Claims,
+    Path(id): Path<String>,
+) -> ResponseType<Json<Vec<SessionProgress>>> {
+    let Ok(result) = repo
+        .sessions(id)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while listing sessions."))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    ResponseType::Data(Json(result))
+}
+
+/// Get session
+///
+/// Get a session with its trials and answers, an interrupted session resumes from `next_trial`.
+async fn get_session(
+    repo: ExperimentRepository,
+    Path(id): Path<String>,
+) -> ResponseType<Json<SessionProgress>> {
+    match repo.session(id).await {
+        Ok(session) => ResponseType::Data(Json(session)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while getting a session.");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Answer trial
+///
+/// Store the answer to a trial of the session, counted from 0. Trials are answered in order,
+/// answering a trial again leaves the first answer.
+async fn answer_trial(
+    repo: ExperimentRepository,
+    Path((id, trial)): Path<(String, usize)>,
+    ValidatedJson(answer): ValidatedJson<TrialAnswer>,
+) -> ResponseType<Json<SessionProgress>> {
+    session_response(repo.answer(id, trial, answer).await)
+}
+
+/// Finish session
+///
+/// Finish the session once all trials are answered, its answers are stored as an experiment result.
+async fn finish_session(
+    repo: ExperimentRepository,
+    Path(id): Path<String>,
+) -> ResponseType<Json<SessionProgress>> {
+    session_response(repo.finish_session(id).await)
+}
+
+fn session_response(
+    result: Result<SessionChange, RepoError>,
+) -> ResponseType<Json<SessionProgress>> {
+    match result {
+        Ok(SessionChange::Changed(session)) => ResponseType::Data(Json(*session)),
+        Ok(SessionChange::Finished) => {
+            ResponseType::Error(StatusCode::CONFLICT, "The session is finished".to_owned())
+        }
+        Ok(SessionChange::OutOfOrder { next }) => ResponseType::Error(
+            StatusCode::CONFLICT,
+            format!("Trials are answered in order, trial {next} is next"),
+        ),
+        Ok(SessionChange::Incomplete { next }) => ResponseType::Error(
+            StatusCode::CONFLICT,
+            format!("The session has unanswered trials, trial {next} is next"),
+        ),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Closed(message)) => ResponseType::Error(StatusCode::CONFLICT, message),
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while updating a session.");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 /// Get experiment results
 ///
 /// Get all experiment results for the experiment.
@@ -132,16 +410,26 @@ async fn get_results(
 
 /// Create experiment result
 ///
-/// Create an experiment result for the experiment.
+/// Create an experiment result for the experiment, only open experiments within their schedule take results.
+/// Experiments using a speaker layout take a `speaker_id` for every sample, the speaker's position is stored with it.
 async fn post_result(
     repo: ExperimentRepository,
     Path(id): Path<String>,
     ValidatedJson(expr): ValidatedJson<ExperimentResult>,
 ) -> ResponseType<Json<StringIdentified<ExperimentResult>>> {
-    let Ok(result) = repo.create_result(id, expr).await.map_err(
-        |e| error!({error = ?e}, "Encountered an error while creating experiment results."),
-    ) else {
-        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
-    };
-    ResponseType::Data(Json(result))
+    match repo.create_result(id, expr).await {
+        Ok(result) => ResponseType::Data(Json(result)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(RepoError::Speaker(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(RepoError::Closed(message)) => ResponseType::Error(StatusCode::CONFLICT, message),
+        Err(RepoError::Trials(message)) => {
+            ResponseType::Error(StatusCode::UNPROCESSABLE_ENTITY, message)
+        }
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while creating experiment results.");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
@@ -0,0 +1,163 @@
+use axum::{
+    extract::{DefaultBodyLimit, FromRef, Path},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_extra::extract::Multipart;
+use hyper::StatusCode;
+use tracing::error;
+use validator::Validate;
+
+use crate::services::{
+    audio::AudioConfig,
+    auth::{claims::Claims, AuthKeys},
+    database::{error::DbError, identified::StringIdentified, surreal::Database},
+    file_storage::FileStorage,
+    repositories::{
+        hrtf::{HrtfInfo, HrtfRepository},
+        IsViolatingUnique, RepoError,
+    },
+    util::{ResponseType, ValidatedJsonRejection},
+};
+
+use super::audio::{audio_error_status, next_field, stage_next_field};
+
+pub fn hrtf_router<T>(config: &AudioConfig) -> Router<T>
+where
+    AuthKeys: FromRef<T>,
+    Database: FromRef<T>,
+    FileStorage: FromRef<T>,
+    T: 'static + Send + Sync + Clone,
+{
+    Router::new()
+        .route(
+            "/",
+            post(create_hrtf).layer(DefaultBodyLimit::max(config.upload_size_limit)),
+        )
+        .route("/", get(get_all))
+        .route("/:id", get(get_hrtf))
+        .route("/:id/data", get(get_data))
+        .route("/:id", delete(delete_hrtf))
+}
+
+/// Create HRTF set
+///
+/// Upload a SOFA file as a multipart form with `info` and `data` fields. Return the created set.
+/// Files without two receivers or which cannot be parsed are rejected.
+async fn create_hrtf(
+    hrtf_repo: HrtfRepository,
+    _: Claims,
+    mut multipart: Multipart,
+) -> ResponseType<Json<StringIdentified<HrtfInfo>>> {
+    let info = match next_field(&mut multipart).await {
+        Ok(info) => info,
+        Err(status) => return ResponseType::Status(status),
+    };
+    let info_bytes = match info.bytes().await {
+        Ok(info_bytes) => info_bytes,
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while reading an HRTF set");
+            return ResponseType::Status(e.status());
+        }
+    };
+    let Json(info): Json<HrtfInfo> = match Json::from_bytes(&info_bytes) {
+        Ok(info) => info,
+        Err(err) => return ResponseType::JsonErr(ValidatedJsonRejection::Json(err)),
+    };
+    if let Err(err) = info.validate() {
+        return ResponseType::JsonErr(ValidatedJsonRejection::Validation(err));
+    }
+    let file = match stage_next_field(&hrtf_repo.file_storage, &mut multipart).await {
+        Ok(file) => file,
+        Err(status) => return ResponseType::Status(status),
+    };
+    let result = hrtf_repo.create_staged(info, file).await.map_err(|e| {
+        error!({error = ?e}, "Encountered an error while adding an HRTF set");
+        e
+    });
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(hrtf) => ResponseType::Data(Json(hrtf)),
+        Err(RepoError::Audio(e)) => ResponseType::Error(audio_error_status(&e), e.to_string()),
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// List HRTF sets
+///
+/// List all uploaded HRTF sets ordered by name
+async fn get_all(hrtf_repo: HrtfRepository) -> ResponseType<Json<Vec<StringIdentified<HrtfInfo>>>> {
+    let Ok(sets) = hrtf_repo
+        .infos()
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while getting HRTF sets"))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    ResponseType::Data(Json(sets))
+}
+
+/// Get HRTF set
+///
+/// Get info of an HRTF set with given identifier
+async fn get_hrtf(
+    hrtf_repo: HrtfRepository,
+    Path(id): Path<String>,
+) -> ResponseType<Json<StringIdentified<HrtfInfo>>> {
+    match hrtf_repo.info(id).await {
+        Ok(hrtf) => ResponseType::Data(Json(hrtf)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while getting an HRTF set");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Download HRTF set
+///
+/// Get the SOFA file of an HRTF set with given identifier
+async fn get_data(hrtf_repo: HrtfRepository, Path(id): Path<String>) -> ResponseType<Response> {
+    match hrtf_repo.data(id).await {
+        Ok((hrtf, data)) => ResponseType::Data(
+            (
+                [
+                    (header::CONTENT_TYPE, "application/x-netcdf".to_owned()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}.sofa\"", hrtf.id),
+                    ),
+                ],
+                data,
+            )
+                .into_response(),
+        ),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while reading an HRTF set");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Delete HRTF set
+///
+/// Delete an HRTF set with given identifier. Samples rendered with it are kept.
+async fn delete_hrtf(
+    hrtf_repo: HrtfRepository,
+    _: Claims,
+    Path(id): Path<String>,
+) -> ResponseType<()> {
+    match hrtf_repo.delete(id).await {
+        Ok(()) => ResponseType::Status(StatusCode::OK),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while deleting an HRTF set");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
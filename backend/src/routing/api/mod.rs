@@ -1,18 +1,26 @@
 //! Api server
 
+pub mod admin;
 pub mod audio;
 pub mod auth;
 pub mod experiments;
+pub mod hrtf;
+pub mod speaker_layouts;
 
 use axum::{extract::FromRef, http::StatusCode, response::IntoResponse, Router};
 
-use crate::services::{auth::AuthKeys, database::surreal::Database, file_storage::FileStorage};
+use crate::services::{
+    auth::AuthKeys, config::Config, database::surreal::Database, file_storage::FileStorage,
+};
 
+use self::admin::admin_router;
 use self::audio::audio_router;
 use self::auth::auth_router;
 use self::experiments::router;
+use self::hrtf::hrtf_router;
+use self::speaker_layouts::speaker_layout_router;
 
-pub fn api_router<T>() -> Router<T>
+pub fn api_router<T>(config: &Config) -> Router<T>
 where
     AuthKeys: FromRef<T>,
     Database: FromRef<T>,
@@ -20,9 +28,12 @@ where
     T: 'static + Send + Sync + Clone,
 {
     Router::new()
+        .nest("/admin", admin_router())
         .nest("/auth", auth_router())
-        .nest("/audio", audio_router())
+        .nest("/audio", audio_router(&config.audio))
         .nest("/experiments", router())
+        .nest("/hrtf", hrtf_router(&config.audio))
+        .nest("/speaker-layouts", speaker_layout_router())
         .fallback(handler_404)
 }
 
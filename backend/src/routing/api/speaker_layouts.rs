@@ -0,0 +1,123 @@
+use axum::{
+    extract::{FromRef, Path},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use hyper::StatusCode;
+use serde::Serialize;
+use tracing::error;
+
+use crate::services::{
+    auth::{claims::Claims, AuthKeys},
+    database::{error::DbError, identified::StringIdentified, surreal::Database},
+    repositories::{
+        speaker_layout::{LayoutDeletion, LayoutUsers, SpeakerLayout, SpeakerLayoutRepository},
+        IsViolatingUnique, RepoError,
+    },
+    util::{ResponseType, ValidatedJson},
+};
+
+pub fn speaker_layout_router<T>() -> Router<T>
+where
+    AuthKeys: FromRef<T>,
+    Database: FromRef<T>,
+    T: 'static + Send + Sync + Clone,
+{
+    Router::new()
+        .route("/", post(create_layout))
+        .route("/", get(get_all))
+        .route("/:id", get(get_layout))
+        .route("/:id", delete(delete_layout))
+}
+
+/// Create speaker layout
+///
+/// Create a named set of loudspeakers with unique identifiers and output channels. Return the created layout.
+async fn create_layout(
+    layout_repo: SpeakerLayoutRepository,
+    _: Claims,
+    ValidatedJson(layout): ValidatedJson<SpeakerLayout>,
+) -> ResponseType<Json<StringIdentified<SpeakerLayout>>> {
+    let result = layout_repo.create(layout).await.map_err(|e| {
+        error!({error = ?e}, "Encountered an error while creating a speaker layout");
+        e
+    });
+    if result.is_violating_unique() {
+        return ResponseType::Status(StatusCode::CONFLICT);
+    }
+    match result {
+        Ok(layout) => ResponseType::Data(Json(layout)),
+        Err(_) => ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// List speaker layouts
+///
+/// List all speaker layouts ordered by name
+async fn get_all(
+    layout_repo: SpeakerLayoutRepository,
+) -> ResponseType<Json<Vec<StringIdentified<SpeakerLayout>>>> {
+    let Ok(layouts) = layout_repo
+        .infos()
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while getting speaker layouts"))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    ResponseType::Data(Json(layouts))
+}
+
+/// Get speaker layout
+///
+/// Get a speaker layout with given identifier
+async fn get_layout(
+    layout_repo: SpeakerLayoutRepository,
+    Path(id): Path<String>,
+) -> ResponseType<Json<StringIdentified<SpeakerLayout>>> {
+    match layout_repo.info(id).await {
+        Ok(layout) => ResponseType::Data(Json(layout)),
+        Err(RepoError::Database(DbError::NotFound)) => ResponseType::Status(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!({error = ?e}, "Encountered an error while getting a speaker layout");
+            ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Delete speaker layout
+///
+/// Delete a speaker layout with given identifier.
+/// Layouts used by samples or experiments are kept, the conflict response lists them.
+async fn delete_layout(
+    layout_repo: SpeakerLayoutRepository,
+    _: Claims,
+    Path(id): Path<String>,
+) -> ResponseType<(StatusCode, Json<LayoutInUse>)> {
+    let Ok(deletion) = layout_repo
+        .delete(id)
+        .await
+        .map_err(|e| error!({error = ?e}, "Encountered an error while deleting a speaker layout"))
+    else {
+        return ResponseType::Status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    match deletion {
+        LayoutDeletion::Deleted => ResponseType::Status(StatusCode::OK),
+        LayoutDeletion::InUse(users) => ResponseType::Data((
+            StatusCode::CONFLICT,
+            Json(LayoutInUse {
+                message: "Speaker layout is used by samples or experiments",
+                users,
+            }),
+        )),
+    }
+}
+
+/// Body of a refused layout deletion
+#[derive(Debug, Serialize)]
+struct LayoutInUse {
+    message: &'static str,
+    #[serde(flatten)]
+    users: LayoutUsers,
+}
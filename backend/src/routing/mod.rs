@@ -23,7 +23,7 @@ where
 {
     let mut router = Router::new()
         .merge(healthcheck_router())
-        .nest(MAIN_ROUTE_PATH, api_router())
+        .nest(MAIN_ROUTE_PATH, api_router(config))
         .fallback_service(static_files_service())
         .layer(TraceLayer::new_for_http());
 
@@ -0,0 +1,387 @@
+//! Binaural decoding of Ambisonic recordings in the AmbiX format (ACN channel order, SN3D normalization)
+//!
+//! The sound field is decoded to virtual loudspeakers spread evenly around the listener,
+//! each of them is rendered with the impulse responses of its direction and the ears are summed.
+//! Head rotation turns the virtual loudspeakers, so the field needs no rotation of its own.
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::binaural::{HrtfSet, Interpolation};
+
+/// Highest supported order, with 64 channels
+pub const MAX_ORDER: u8 = 7;
+
+/// Ambisonic recording in the AmbiX format
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct Ambisonics {
+    #[validate(range(min = 1, max = "MAX_ORDER"))]
+    pub order: u8,
+}
+
+impl Ambisonics {
+    /// Number of channels of a full-sphere recording of this order
+    pub fn channels(&self) -> usize {
+        (self.order as usize + 1).pow(2)
+    }
+
+    /// Order matching a channel count, if it is the count of a full-sphere recording
+    pub fn from_channels(channels: usize) -> Option<Self> {
+        (1..=MAX_ORDER)
+            .map(|order| Self { order })
+            .find(|ambisonics| ambisonics.channels() == channels)
+    }
+}
+
+/// Weighting of the orders in the decoder
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Weighting {
+    /// All orders weighted equally, sharpest image at the cost of side lobes
+    Basic,
+    /// Weights maximizing the energy vector, suppressing side lobes
+    #[default]
+    MaxRe,
+}
+
+/// Virtual loudspeaker decoder
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Decoder {
+    /// Number of virtual loudspeakers, at least the number of channels are used, twice that by default
+    pub speakers: Option<u16>,
+    pub weighting: Weighting,
+    pub interpolation: Interpolation,
+}
+
+/// Head orientation in degrees, following the sample [`position`](crate::services::repositories::position) conventions
+///
+/// Yaw turns the head to the right, pitch raises the nose and roll lowers the right ear, applied in that order.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rotation {
+    pub yaw: f64,
+    pub pitch: f64,
+    pub roll: f64,
+}
+
+impl Rotation {
+    /// Direction in the room of a direction relative to the head, both as unit vectors in SOFA coordinates
+    fn apply(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let (sin_roll, cos_roll) = self.roll.to_radians().sin_cos();
+        let (y, z) = (y * cos_roll - z * sin_roll, y * sin_roll + z * cos_roll);
+        let (sin_pitch, cos_pitch) = self.pitch.to_radians().sin_cos();
+        let (x, z) = (x * cos_pitch - z * sin_pitch, x * sin_pitch + z * cos_pitch);
+        let (sin_yaw, cos_yaw) = self.yaw.to_radians().sin_cos();
+        let (x, y) = (x * cos_yaw + y * sin_yaw, y * cos_yaw - x * sin_yaw);
+        [x, y, z]
+    }
+}
+
+/// Real spherical harmonics with SN3D normalization in ACN order for a unit vector
+///
+/// Azimuth runs counterclockwise in AmbiX, the same as in SOFA coordinates.
+pub fn spherical_harmonics(order: u8, [x, y, z]: [f64; 3]) -> Vec<f64> {
+    let order = order as usize;
+    let azimuth = y.atan2(x);
+    let sin_elevation = z.clamp(-1.0, 1.0);
+    let cos_elevation = (1.0 - sin_elevation * sin_elevation).sqrt();
+    // Associated Legendre functions without the Condon-Shortley phase, indexed by degree and order
+    let mut legendre = vec![vec![0.0; order + 1]; order + 1];
+    legendre[0][0] = 1.0;
+    for m in 1..=order {
+        legendre[m][m] = (2 * m - 1) as f64 * cos_elevation * legendre[m - 1][m - 1];
+    }
+    for m in 0..order {
+        legendre[m + 1][m] = (2 * m + 1) as f64 * sin_elevation * legendre[m][m];
+    }
+    for l in 2..=order {
+        let (lower, higher) = legendre.split_at_mut(l);
+        for (m, value) in higher[0].iter_mut().enumerate().take(l - 1) {
+            *value = ((2 * l - 1) as f64 * sin_elevation * lower[l - 1][m]
+                - (l + m - 1) as f64 * lower[l - 2][m])
+                / (l - m) as f64;
+        }
+    }
+    let mut harmonics = Vec::with_capacity((order + 1).pow(2));
+    for (l, row) in legendre.iter().enumerate() {
+        for m in -(l as i64)..=l as i64 {
+            let m_abs = m.unsigned_abs() as usize;
+            let factorial_ratio =
+                (l - m_abs + 1..=l + m_abs).fold(1.0, |ratio, k| ratio / k as f64);
+            let delta = if m == 0 { 1.0 } else { 2.0 };
+            let normalization = (delta * factorial_ratio).sqrt();
+            let angular = if m >= 0 {
+                (m as f64 * azimuth).cos()
+            } else {
+                (m_abs as f64 * azimuth).sin()
+            };
+            harmonics.push(normalization * row[m_abs] * angular);
+        }
+    }
+    harmonics
+}
+
+/// Nearly uniform directions on the sphere from a spherical Fibonacci lattice
+fn fibonacci_sphere(count: usize) -> Vec<[f64; 3]> {
+    let golden_angle = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+    (0..count)
+        .map(|i| {
+            let z = 1.0 - (2 * i + 1) as f64 / count as f64;
+            let radius = (1.0 - z * z).sqrt();
+            let (sin, cos) = (i as f64 * golden_angle).sin_cos();
+            [radius * cos, radius * sin, z]
+        })
+        .collect()
+}
+
+/// Weights of each order
+fn order_weights(order: u8, weighting: Weighting) -> Vec<f64> {
+    let order = order as usize;
+    match weighting {
+        Weighting::Basic => vec![1.0; order + 1],
+        Weighting::MaxRe => {
+            // Approximation of the largest root of the Legendre polynomial of degree order + 1
+            let x = (137.9f64.to_radians() / (order as f64 + 1.51)).cos();
+            let mut weights = vec![1.0, x];
+            for n in 2..=order {
+                let next = ((2 * n - 1) as f64 * x * weights[n - 1]
+                    - (n - 1) as f64 * weights[n - 2])
+                    / n as f64;
+                weights.push(next);
+            }
+            weights.truncate(order + 1);
+            weights
+        }
+    }
+}
+
+/// Directions of the virtual loudspeakers relative to the head with the gain of each channel
+///
+/// Gains are normalized so that a plane wave keeps its energy regardless of the number of loudspeakers.
+pub fn decoder_gains(
+    order: u8,
+    decoder: &Decoder,
+    rotation: &Rotation,
+) -> Vec<([f64; 3], Vec<f64>)> {
+    let channels = (order as usize + 1).pow(2);
+    let count = decoder
+        .speakers
+        .map_or(2 * channels, |speakers| speakers as usize)
+        .max(channels);
+    let weights = order_weights(order, decoder.weighting);
+    let energy = weights
+        .iter()
+        .enumerate()
+        .map(|(n, w)| (2 * n + 1) as f64 * w * w)
+        .sum::<f64>();
+    let scale = 1.0 / (count as f64 * energy).sqrt();
+    fibonacci_sphere(count)
+        .into_iter()
+        .map(|direction| {
+            let harmonics = spherical_harmonics(order, rotation.apply(direction));
+            let gains = harmonics
+                .into_iter()
+                .enumerate()
+                .map(|(acn, harmonic)| {
+                    let n = (acn as f64).sqrt() as usize;
+                    scale * (2 * n + 1) as f64 * weights[n] * harmonic
+                })
+                .collect();
+            (direction, gains)
+        })
+        .collect()
+}
+
+/// Decode an Ambisonic recording for both ears
+///
+/// Each channel is convolved with the sum of the loudspeaker impulse responses weighted by its gains,
+/// both channels have the length of the recording followed by the impulse response tail.
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+pub fn render_ambisonics(
+    channels: &[Vec<f64>],
+    order: u8,
+    set: &HrtfSet,
+    decoder: &Decoder,
+    rotation: &Rotation,
+) -> [Vec<f64>; 2] {
+    let speakers = decoder_gains(order, decoder, rotation)
+        .into_iter()
+        .map(|(direction, gains)| {
+            (
+                set.impulse_responses_toward(direction, decoder.interpolation),
+                gains,
+            )
+        })
+        .collect::<Vec<_>>();
+    let filter_length = speakers
+        .iter()
+        .flat_map(|(impulse_responses, _)| impulse_responses.iter().map(Vec::len))
+        .max()
+        .unwrap_or(1);
+    let frames = channels.first().map_or(0, Vec::len);
+    let length = frames + filter_length - 1;
+    let size = length.next_power_of_two();
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(size);
+    let spectrum = |values: &mut dyn Iterator<Item = f64>| {
+        let mut buffer = values.map(|x| Complex::new(x, 0.0)).collect::<Vec<_>>();
+        buffer.resize(size, Complex::new(0.0, 0.0));
+        forward.process(&mut buffer);
+        buffer
+    };
+    let mut ears = [
+        vec![Complex::new(0.0, 0.0); size],
+        vec![Complex::new(0.0, 0.0); size],
+    ];
+    for (channel, samples) in channels.iter().enumerate() {
+        let signal = spectrum(&mut samples.iter().copied());
+        for (ear, output) in ears.iter_mut().enumerate() {
+            let mut filter = vec![0.0; filter_length];
+            for (impulse_responses, gains) in speakers.iter() {
+                for (tap, x) in filter.iter_mut().zip(&impulse_responses[ear]) {
+                    *tap += gains[channel] * x;
+                }
+            }
+            let filter = spectrum(&mut filter.into_iter());
+            for ((y, x), h) in output.iter_mut().zip(&signal).zip(filter) {
+                *y += x * h;
+            }
+        }
+    }
+    let inverse = planner.plan_fft_inverse(size);
+    ears.map(|mut output| {
+        inverse.process(&mut output);
+        output
+            .into_iter()
+            .take(length)
+            .map(|x| x.re / size as f64)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::audio::binaural::{tests::SOFA, HrtfSet};
+
+    use super::{
+        decoder_gains, render_ambisonics, spherical_harmonics, Ambisonics, Decoder, Rotation,
+        Weighting,
+    };
+
+    /// Direction relative to the head of the loudest virtual loudspeaker for a plane wave
+    fn loudest(order: u8, source: [f64; 3], rotation: Rotation) -> [f64; 3] {
+        let field = spherical_harmonics(order, source);
+        decoder_gains(order, &Decoder::default(), &rotation)
+            .into_iter()
+            .map(|(direction, gains)| {
+                let gain = gains.iter().zip(&field).map(|(g, b)| g * b).sum::<f64>();
+                (direction, gain)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(Ambisonics { order: 1 }.channels(), 4);
+        assert_eq!(Ambisonics::from_channels(16), Some(Ambisonics { order: 3 }));
+        assert_eq!(Ambisonics::from_channels(2), None);
+        assert_eq!(Ambisonics::from_channels(1), None);
+    }
+
+    #[test]
+    fn first_order() {
+        let [x, y, z] = [0.48, 0.6, 0.64];
+
+        let harmonics = spherical_harmonics(1, [x, y, z]);
+
+        let expected = [1.0, y, z, x];
+        for (actual, expected) in harmonics.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn sn3d_normalized() {
+        let direction = [0.36, -0.48, 0.8];
+
+        let harmonics = spherical_harmonics(7, direction);
+
+        // With SN3D the squares of each order sum to one in any direction
+        assert_eq!(harmonics.len(), 64);
+        for n in 0..=7 {
+            let sum = harmonics[n * n..(n + 1) * (n + 1)]
+                .iter()
+                .map(|y| y * y)
+                .sum::<f64>();
+            assert!((sum - 1.0).abs() < 1e-9, "order {n}: {sum}");
+        }
+    }
+
+    #[test]
+    fn rotated() {
+        let front = [1.0, 0.0, 0.0];
+        let up = [0.0, 0.0, 1.0];
+        let cases = [
+            (front, Rotation::default(), front),
+            // Turning right puts a source ahead on the left
+            (
+                front,
+                Rotation {
+                    yaw: 90.0,
+                    ..Default::default()
+                },
+                [0.0, 1.0, 0.0],
+            ),
+            // Looking up puts a source above ahead
+            (
+                up,
+                Rotation {
+                    pitch: 90.0,
+                    ..Default::default()
+                },
+                front,
+            ),
+            // Lowering the right ear puts a source above on the left
+            (
+                up,
+                Rotation {
+                    roll: 90.0,
+                    ..Default::default()
+                },
+                [0.0, 1.0, 0.0],
+            ),
+        ];
+
+        for (source, rotation, expected) in cases {
+            let direction = loudest(3, source, rotation);
+
+            let cosine = direction
+                .iter()
+                .zip(expected)
+                .map(|(a, b)| a * b)
+                .sum::<f64>();
+            assert!(cosine > 0.9, "{rotation:?}: {direction:?}");
+        }
+    }
+
+    #[test]
+    fn rendered() {
+        let set = HrtfSet::open(SOFA, 48000.0).unwrap();
+        let field = spherical_harmonics(1, [0.0, 1.0, 0.0]);
+        let channels = field.iter().map(|b| vec![0.5 * b; 100]).collect::<Vec<_>>();
+        let decoder = Decoder {
+            speakers: Some(12),
+            weighting: Weighting::Basic,
+            ..Default::default()
+        };
+
+        let [left, right] = render_ambisonics(&channels, 1, &set, &decoder, &Rotation::default());
+
+        assert_eq!(left.len(), right.len());
+        assert!(left.len() >= 100);
+        assert!(left.iter().chain(right.iter()).any(|x| x.abs() > 0.01));
+    }
+}
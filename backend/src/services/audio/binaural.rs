@@ -0,0 +1,232 @@
+//! Binaural rendering with head-related impulse responses from SOFA files
+//!
+//! SOFA positions have the x axis pointing ahead, y to the left and z up.
+//! Directions are converted from the sample [`position`](crate::services::repositories::position) conventions,
+//! where azimuth increases clockwise.
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use sofar::reader::{Filter, OpenOptions, Sofar};
+
+use super::{AudioError, AudioResult};
+
+/// Sample rate the impulse responses of uploaded files are checked at
+const PROBE_SAMPLE_RATE: f32 = 48000.0;
+
+/// Properties of a SOFA file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HrtfFormat {
+    pub measurements: u32,
+    /// Samples per impulse response at 48 kHz
+    pub filter_length: u32,
+    /// Median distance of the measured positions in meters
+    pub radius: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Interpolation {
+    /// Impulse responses of the closest measured position
+    Nearest,
+    /// Weighted mix of the closest measured positions
+    #[default]
+    Interpolated,
+}
+
+/// Read a SOFA file and check that it can be used for rendering
+///
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+pub fn probe_sofa(data: &[u8]) -> AudioResult<HrtfFormat> {
+    let set = HrtfSet::open(data, PROBE_SAMPLE_RATE)?;
+    let hrtf = set.sofa.hrtf();
+    if hrtf.r() != 2 {
+        Err(AudioError::Unsupported(
+            "SOFA file must have two receivers".to_owned(),
+        ))?
+    }
+    Ok(HrtfFormat {
+        measurements: hrtf.m(),
+        filter_length: hrtf.n(),
+        radius: set.radius,
+    })
+}
+
+fn sofa_error(error: impl std::fmt::Display) -> AudioError {
+    AudioError::Malformed(format!("invalid SOFA file: {error}"))
+}
+
+/// HRTF set resampled to the rate of the rendered audio
+pub struct HrtfSet {
+    sofa: Sofar,
+    sample_rate: f32,
+    radius: f32,
+}
+
+impl HrtfSet {
+    pub fn open(data: &[u8], sample_rate: f32) -> AudioResult<Self> {
+        let sofa = OpenOptions::new()
+            .sample_rate(sample_rate)
+            .open_data(data)
+            .map_err(sofa_error)?;
+        let mut distances = sofa
+            .hrtf()
+            .source_position
+            .values
+            .chunks_exact(3)
+            .map(|position| position.iter().map(|x| x * x).sum::<f32>().sqrt())
+            .collect::<Vec<_>>();
+        if distances.is_empty() {
+            Err(sofa_error("no source positions"))?
+        }
+        distances.sort_by(f32::total_cmp);
+        let radius = distances[distances.len() / 2];
+        Ok(Self {
+            sofa,
+            sample_rate,
+            radius,
+        })
+    }
+
+    /// Left and right impulse responses for a direction, with the interaural delays applied
+    ///
+    /// Positions without a distance use the median distance of the measurements.
+    pub fn impulse_responses(
+        &self,
+        azimuth: f32,
+        elevation: f32,
+        distance: Option<f32>,
+        interpolation: Interpolation,
+    ) -> [Vec<f64>; 2] {
+        let distance = distance.unwrap_or(self.radius);
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        let x = distance * elevation.cos() * azimuth.cos();
+        let y = -distance * elevation.cos() * azimuth.sin();
+        let z = distance * elevation.sin();
+        self.impulse_responses_at([x, y, z], interpolation)
+    }
+
+    /// Left and right impulse responses for a unit vector in SOFA coordinates, at the median distance of the measurements
+    pub fn impulse_responses_toward(
+        &self,
+        direction: [f64; 3],
+        interpolation: Interpolation,
+    ) -> [Vec<f64>; 2] {
+        self.impulse_responses_at(direction.map(|x| x as f32 * self.radius), interpolation)
+    }
+
+    fn impulse_responses_at(
+        &self,
+        [x, y, z]: [f32; 3],
+        interpolation: Interpolation,
+    ) -> [Vec<f64>; 2] {
+        let mut filter = Filter::new(self.sofa.filter_len());
+        match interpolation {
+            Interpolation::Nearest => self.sofa.filter_nointerp(x, y, z, &mut filter),
+            Interpolation::Interpolated => self.sofa.filter(x, y, z, &mut filter),
+        }
+        let delayed = |ir: &[f32], delay: f32| {
+            let delay = (delay * self.sample_rate).round().max(0.0) as usize;
+            std::iter::repeat_n(0.0, delay)
+                .chain(ir.iter().map(|x| *x as f64))
+                .collect::<Vec<_>>()
+        };
+        [
+            delayed(&filter.left, filter.ldelay),
+            delayed(&filter.right, filter.rdelay),
+        ]
+    }
+}
+
+/// Render a mono signal for both ears
+///
+/// Both channels have the length of the signal followed by the longer impulse response tail.
+pub fn render_binaural(signal: &[f64], impulse_responses: &[Vec<f64>; 2]) -> [Vec<f64>; 2] {
+    let length = signal.len() + impulse_responses.iter().map(Vec::len).max().unwrap_or(1) - 1;
+    impulse_responses.clone().map(|ir| {
+        let mut output = convolve(signal, &ir);
+        output.resize(length, 0.0);
+        output
+    })
+}
+
+/// Linear convolution through the FFT
+pub fn convolve(signal: &[f64], impulse_response: &[f64]) -> Vec<f64> {
+    if signal.is_empty() || impulse_response.is_empty() {
+        return Vec::new();
+    }
+    let length = signal.len() + impulse_response.len() - 1;
+    let size = length.next_power_of_two();
+    let padded = |values: &[f64]| {
+        let mut padded = values
+            .iter()
+            .map(|x| Complex::new(*x, 0.0))
+            .collect::<Vec<_>>();
+        padded.resize(size, Complex::new(0.0, 0.0));
+        padded
+    };
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(size);
+    let mut signal = padded(signal);
+    let mut impulse_response = padded(impulse_response);
+    forward.process(&mut signal);
+    forward.process(&mut impulse_response);
+    for (x, h) in signal.iter_mut().zip(impulse_response) {
+        *x *= h;
+    }
+    planner.plan_fft_inverse(size).process(&mut signal);
+    signal
+        .into_iter()
+        .take(length)
+        .map(|x| x.re / size as f64)
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{convolve, probe_sofa, render_binaural, HrtfSet, Interpolation};
+
+    /// Minimal SimpleFreeFieldHRIR set with 8 measurements of unit impulses at 48 kHz,
+    /// from the test data of the `sofar` crate
+    pub const SOFA: &[u8] = include_bytes!("../../../test_data/minimal.sofa");
+
+    #[test]
+    fn convolved() {
+        let output = convolve(&[1.0, 2.0, 3.0], &[0.0, 1.0, 0.5]);
+
+        let expected = [0.0, 1.0, 2.5, 4.0, 1.5];
+        assert_eq!(output.len(), expected.len());
+        for (x, y) in output.iter().zip(expected) {
+            assert!((x - y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn probed() {
+        let format = probe_sofa(SOFA).unwrap();
+
+        assert_eq!(format.measurements, 8);
+        assert_eq!(format.filter_length, 16);
+        assert!(format.radius > 0.0);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(probe_sofa(b"not a sofa file").is_err());
+    }
+
+    #[test]
+    fn rendered() {
+        let set = HrtfSet::open(SOFA, 48000.0).unwrap();
+        let signal = vec![0.5; 100];
+
+        for interpolation in [Interpolation::Nearest, Interpolation::Interpolated] {
+            let impulse_responses = set.impulse_responses(30.0, 0.0, None, interpolation);
+            let [left, right] = render_binaural(&signal, &impulse_responses);
+
+            assert_eq!(left.len(), right.len());
+            assert!(left.len() >= signal.len());
+            assert!(left.iter().chain(right.iter()).any(|x| x.abs() > 0.1));
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    io::{ErrorKind, Read},
+    sync::{Mutex, PoisonError},
+};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSource, MediaSourceStream, ReadOnlySource},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use super::{AudioError, AudioResult};
+
+/// Decoded audio as separate channels of samples within [-1, 1]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: Vec<Vec<f64>>,
+}
+
+/// Samples over all channels a decoded file may have, 1 GiB once decoded
+pub const MAX_DECODED_SAMPLES: usize = 1 << 27;
+
+/// Decode the first audio track of a file
+///
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+/// Audio with more than [`MAX_DECODED_SAMPLES`] is refused as [`AudioError::TooLong`].
+pub fn decode(source: Box<dyn MediaSource>) -> AudioResult<DecodedAudio> {
+    decode_at_most(source, MAX_DECODED_SAMPLES)
+}
+
+/// Decode the first audio track of a stream which cannot seek, like a file read from storage
+pub fn decode_stream(reader: impl Read + Send + 'static) -> AudioResult<DecodedAudio> {
+    decode(Box::new(ReadOnlySource::new(Exclusive(Mutex::new(reader)))))
+}
+
+/// Reader which is [`Sync`] as every source of symphonia has to be, it is only read through `&mut`
+struct Exclusive<R>(Mutex<R>);
+
+impl<R: Read> Read for Exclusive<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .read(buf)
+    }
+}
+
+fn decode_at_most(source: Box<dyn MediaSource>, max_samples: usize) -> AudioResult<DecodedAudio> {
+    let stream = MediaSourceStream::new(source, Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &Hint::new(),
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut reader = probed.format;
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(AudioError::NoTrack)?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut channels = Vec::<Vec<f64>>::new();
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => Err(e)?,
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = decoder.decode(&packet)?;
+        let spec = *decoded.spec();
+        sample_rate.get_or_insert(spec.rate);
+        let count = spec.channels.count();
+        if channels.is_empty() {
+            channels.resize(count, Vec::new());
+        }
+        let buffer = match buffer.as_mut() {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * count => buffer,
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        if (channels[0].len() + decoded.frames()) * count > max_samples {
+            Err(AudioError::TooLong(format!(
+                "more than {max_samples} samples"
+            )))?
+        }
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(count) {
+            for (channel, sample) in channels.iter_mut().zip(frame) {
+                channel.push(*sample as f64);
+            }
+        }
+    }
+
+    let sample_rate = sample_rate
+        .filter(|rate| *rate > 0)
+        .ok_or(AudioError::Malformed("missing sample rate".to_owned()))?;
+    if channels.first().is_none_or(Vec::is_empty) {
+        Err(AudioError::Malformed("stream contains no audio".to_owned()))?
+    }
+    Ok(DecodedAudio {
+        sample_rate,
+        channels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::services::audio::{tests::wav_bytes, wav::encode_wav, AudioError};
+
+    use super::{decode, decode_at_most, decode_stream};
+
+    #[test]
+    fn decoded() {
+        let data = wav_bytes(&[0, 16384, -16384, i16::MIN]);
+
+        let audio = decode(Box::new(Cursor::new(data.to_vec()))).unwrap();
+
+        assert_eq!(audio.sample_rate, 8000);
+        assert_eq!(audio.channels, vec![vec![0.0, 0.5, -0.5, -1.0]]);
+    }
+
+    #[test]
+    fn decoded_channels() {
+        let left = [0.25; 3000];
+        let right = [-0.5; 3000];
+        let data = encode_wav(&[&left, &right], 44100, 24);
+
+        let audio = decode(Box::new(Cursor::new(data))).unwrap();
+
+        assert_eq!(audio.channels[0], left);
+        assert_eq!(audio.channels[1], right);
+    }
+
+    #[test]
+    fn decoded_stream() {
+        let data = wav_bytes(&[0, 16384, -16384, i16::MIN]);
+
+        let audio = decode_stream(Cursor::new(data.to_vec())).unwrap();
+
+        assert_eq!(audio.channels, vec![vec![0.0, 0.5, -0.5, -1.0]]);
+    }
+
+    #[test]
+    fn too_long() {
+        let left = [0.25; 3000];
+        let right = [-0.5; 3000];
+        let data = encode_wav(&[&left, &right], 44100, 16);
+
+        let result = decode_at_most(Box::new(Cursor::new(data)), 5999);
+
+        assert!(matches!(result, Err(AudioError::TooLong(_))));
+    }
+}
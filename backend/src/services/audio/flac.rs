@@ -0,0 +1,99 @@
+//! FLAC encoding of delivered audio
+
+use flacenc::{
+    bitsink::ByteSink, component::BitRepr, config, encode_with_fixed_block_size, error::Verify,
+    source::MemSource,
+};
+
+use super::{wav::quantize, AudioError, AudioResult};
+
+/// Most channels a FLAC stream can hold
+pub const MAX_CHANNELS: usize = 8;
+
+/// Encode channels of samples within [-1, 1] as FLAC with 16 or 24 bits
+///
+/// Channels must have the same length, samples outside of the range are clipped.
+/// Audio decoded from integer PCM of the same bit depth is encoded without loss.
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+pub fn encode_flac(channels: &[&[f64]], sample_rate: u32, bit_depth: u16) -> AudioResult<Vec<u8>> {
+    assert!(bit_depth == 16 || bit_depth == 24, "Unsupported bit depth");
+    let frames = channels.first().map_or(0, |channel| channel.len());
+    assert!(
+        channels.iter().all(|channel| channel.len() == frames),
+        "Channels differ in length"
+    );
+    if channels.is_empty() || channels.len() > MAX_CHANNELS {
+        return Err(AudioError::Channels(format!(
+            "FLAC holds 1 to {MAX_CHANNELS} channels, the audio has {}",
+            channels.len()
+        )));
+    }
+    let samples = (0..frames)
+        .flat_map(|frame| {
+            channels
+                .iter()
+                .map(move |channel| quantize(channel[frame], bit_depth))
+        })
+        .collect::<Vec<_>>();
+    let config = config::Encoder::default()
+        .into_verified()
+        .expect("Default configuration is valid");
+    let source = MemSource::from_samples(
+        &samples,
+        channels.len(),
+        bit_depth as usize,
+        sample_rate as usize,
+    );
+    let mut stream = encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| AudioError::Unsupported(format!("cannot encode FLAC: {e}")))?;
+    // The last block is shorter, but it must not count as the minimum of a fixed block size stream
+    stream
+        .stream_info_mut()
+        .set_block_sizes(config.block_size, config.block_size)
+        .expect("Default block size is valid");
+    let mut sink = ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| AudioError::Unsupported(format!("cannot encode FLAC: {e}")))?;
+    Ok(sink.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::services::audio::{decode::decode, probe::probe};
+
+    use super::encode_flac;
+
+    #[test]
+    fn lossless() {
+        for bit_depth in [16, 24] {
+            let step = 1.0 / (1 << (bit_depth - 1)) as f64;
+            let left = (0..10000)
+                .map(|i| ((i * 7919) % 2001 - 1000) as f64 * step)
+                .collect::<Vec<_>>();
+            let right = (0..10000)
+                .map(|i| (i % 100) as f64 * step)
+                .collect::<Vec<_>>();
+
+            let data = encode_flac(&[&left, &right], 44100, bit_depth).unwrap();
+
+            let format = probe(Box::new(Cursor::new(data.clone()))).unwrap();
+            assert_eq!(format.codec, "flac");
+            assert_eq!(format.bit_depth, Some(bit_depth as u32));
+            let audio = decode(Box::new(Cursor::new(data))).unwrap();
+            assert_eq!(audio.sample_rate, 44100);
+            assert_eq!(audio.channels, vec![left, right]);
+        }
+    }
+
+    #[test]
+    fn too_many_channels() {
+        let channel = [0.0; 16];
+
+        let result = encode_flac(&[&channel[..]; 9], 48000, 16);
+
+        assert!(result.is_err());
+    }
+}
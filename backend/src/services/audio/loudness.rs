@@ -0,0 +1,182 @@
+//! Loudness measurement following EBU R128 and loudness normalization
+
+use ebur128::{Channel, EbuR128, Mode};
+use serde::{Deserialize, Serialize};
+
+use super::{AudioError, AudioResult};
+
+/// Highest true peak of a normalized copy in dBTP, leaving headroom for the lossy codecs of browsers
+pub const TRUE_PEAK_CEILING: f64 = -1.0;
+
+/// Loudness measurements of a whole file, missing for digital silence
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, also missing when the audio is quieter than the absolute gate
+    pub integrated: Option<f64>,
+    /// Highest true peak of all channels in dBTP
+    pub true_peak: Option<f64>,
+    /// RMS level of all channels in dBFS
+    pub rms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoudnessConfig {
+    /// Serve copies normalized to the target loudness unless the original is requested
+    pub normalize: bool,
+    /// Target of the normalized copies in LUFS
+    pub target: f64,
+    /// Loudness units from the median above which samples in the list are flagged
+    pub outlier_threshold: f64,
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            normalize: false,
+            target: -23.0,
+            outlier_threshold: 3.0,
+        }
+    }
+}
+
+/// Loudness meter fed with interleaved samples as they are decoded
+pub struct LoudnessMeter {
+    meter: EbuR128,
+    channels: u32,
+    sum_of_squares: f64,
+    samples: u64,
+}
+
+impl LoudnessMeter {
+    /// Channels beyond stereo are weighted equally, as their layout is unknown
+    pub fn new(channels: usize, sample_rate: u32) -> AudioResult<Self> {
+        let channels = channels as u32;
+        let mut meter = EbuR128::new(
+            channels,
+            sample_rate,
+            Mode::I | Mode::TRUE_PEAK | Mode::HISTOGRAM,
+        )
+        .map_err(meter_error)?;
+        if channels > 2 {
+            meter
+                .set_channel_map(&vec![Channel::Center; channels as usize])
+                .map_err(meter_error)?;
+        }
+        Ok(Self {
+            meter,
+            channels,
+            sum_of_squares: 0.0,
+            samples: 0,
+        })
+    }
+
+    pub fn add(&mut self, interleaved: &[f32]) -> AudioResult<()> {
+        self.meter
+            .add_frames_f32(interleaved)
+            .map_err(meter_error)?;
+        self.sum_of_squares += interleaved.iter().map(|x| (*x as f64).powi(2)).sum::<f64>();
+        self.samples += interleaved.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Loudness {
+        let decibels = |value: f64, factor: f64| {
+            Some(factor * value.log10()).filter(|level| level.is_finite())
+        };
+        let true_peak = (0..self.channels)
+            .filter_map(|channel| self.meter.true_peak(channel).ok())
+            .fold(0.0f64, f64::max);
+        let mean_square = self.sum_of_squares / self.samples.max(1) as f64;
+        Loudness {
+            integrated: self
+                .meter
+                .loudness_global()
+                .ok()
+                .filter(|lufs| lufs.is_finite()),
+            true_peak: decibels(true_peak, 20.0),
+            rms: decibels(mean_square, 10.0),
+        }
+    }
+}
+
+fn meter_error(error: ebur128::Error) -> AudioError {
+    AudioError::Unsupported(format!("cannot measure loudness: {error}"))
+}
+
+/// Gain in dB bringing audio to the target loudness without exceeding the [`TRUE_PEAK_CEILING`]
+///
+/// Missing when the loudness of the audio is unknown.
+pub fn normalization_gain(loudness: &Loudness, target: f64) -> Option<f64> {
+    let gain = target - loudness.integrated?;
+    let headroom = loudness
+        .true_peak
+        .map_or(f64::INFINITY, |peak| TRUE_PEAK_CEILING - peak);
+    Some(gain.min(headroom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalization_gain, Loudness, LoudnessMeter};
+
+    fn measure(channels: usize, sample_rate: u32, samples: &[f32]) -> Loudness {
+        let mut meter = LoudnessMeter::new(channels, sample_rate).unwrap();
+        meter.add(samples).unwrap();
+        meter.finish()
+    }
+
+    #[test]
+    fn sine() {
+        // EBU Tech 3341 case 1: stereo 1 kHz sine at -23 dBFS measures -23 LUFS
+        let amplitude = 10f32.powf(-23.0 / 20.0);
+        let samples = (0..48000 * 5)
+            .flat_map(|i| {
+                let x = amplitude * (i as f32 * 1000.0 * std::f32::consts::TAU / 48000.0).sin();
+                [x, x]
+            })
+            .collect::<Vec<_>>();
+
+        let loudness = measure(2, 48000, &samples);
+
+        assert!((loudness.integrated.unwrap() + 23.0).abs() < 0.1);
+        assert!((loudness.true_peak.unwrap() + 23.0).abs() < 0.1);
+        assert!((loudness.rms.unwrap() + 26.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn silence() {
+        let loudness = measure(1, 48000, &[0.0; 48000]);
+
+        assert_eq!(
+            loudness,
+            Loudness {
+                integrated: None,
+                true_peak: None,
+                rms: None
+            }
+        );
+    }
+
+    #[test]
+    fn gain_limited_by_peak() {
+        let loudness = Loudness {
+            integrated: Some(-30.0),
+            true_peak: Some(-3.0),
+            rms: Some(-33.0),
+        };
+
+        assert_eq!(normalization_gain(&loudness, -33.0), Some(-3.0));
+        assert_eq!(normalization_gain(&loudness, -23.0), Some(2.0));
+        assert_eq!(
+            normalization_gain(
+                &Loudness {
+                    integrated: None,
+                    ..loudness
+                },
+                -23.0
+            ),
+            None
+        );
+    }
+}
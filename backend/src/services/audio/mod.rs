@@ -0,0 +1,121 @@
+//! Audio decoding, analysis and synthesis
+
+pub mod ambisonics;
+pub mod binaural;
+pub mod decode;
+pub mod flac;
+pub mod loudness;
+pub mod preview;
+pub mod probe;
+pub mod process;
+pub mod synth;
+pub mod wav;
+
+use std::io::ErrorKind;
+
+use serde::Deserialize;
+use symphonia::core::errors::Error as SymphoniaError;
+
+use self::{flac::encode_flac, loudness::LoudnessConfig, wav::encode_wav};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AudioConfig {
+    /// Maximum size of an uploaded audio file in bytes
+    pub upload_size_limit: usize,
+    #[serde(default)]
+    pub loudness: LoudnessConfig,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("Unsupported audio format: {0}")]
+    Unsupported(String),
+    #[error("Malformed audio data: {0}")]
+    Malformed(String),
+    #[error("No audio track found")]
+    NoTrack,
+    #[error("Unexpected number of channels: {0}")]
+    Channels(String),
+    #[error("Audio too long to decode: {0}")]
+    TooLong(String),
+}
+
+impl From<SymphoniaError> for AudioError {
+    fn from(value: SymphoniaError) -> Self {
+        match value {
+            SymphoniaError::Unsupported(e) => Self::Unsupported(e.to_owned()),
+            SymphoniaError::IoError(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                Self::Malformed("unexpected end of file".to_owned())
+            }
+            e => Self::Malformed(e.to_string()),
+        }
+    }
+}
+
+pub type AudioResult<T> = Result<T, AudioError>;
+
+/// Lossless encodings in which samples are delivered besides their uploaded files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Wav,
+    Flac,
+}
+
+impl Encoding {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Wav => "audio/wav",
+            Self::Flac => "audio/flac",
+        }
+    }
+
+    /// Whether audio with this many channels can be encoded
+    pub fn holds(self, channels: usize) -> bool {
+        match self {
+            Self::Wav => true,
+            Self::Flac => channels <= flac::MAX_CHANNELS,
+        }
+    }
+
+    /// Encode channels of samples within [-1, 1] as integer PCM with 16 or 24 bits
+    pub fn encode(
+        self,
+        channels: &[&[f64]],
+        sample_rate: u32,
+        bit_depth: u16,
+    ) -> AudioResult<Vec<u8>> {
+        match self {
+            Self::Wav => Ok(encode_wav(channels, sample_rate, bit_depth)),
+            Self::Flac => encode_flac(channels, sample_rate, bit_depth),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+
+    /// Encode mono 16-bit PCM samples at 8 kHz as a WAV file
+    pub fn wav_bytes(samples: &[i16]) -> Bytes {
+        const SAMPLE_RATE: u32 = 8000;
+        let data_len = (samples.len() * 2) as u32;
+        let mut buf = BytesMut::new();
+        buf.put_slice(b"RIFF");
+        buf.put_u32_le(36 + data_len);
+        buf.put_slice(b"WAVEfmt ");
+        buf.put_u32_le(16);
+        buf.put_u16_le(1);
+        buf.put_u16_le(1);
+        buf.put_u32_le(SAMPLE_RATE);
+        buf.put_u32_le(SAMPLE_RATE * 2);
+        buf.put_u16_le(2);
+        buf.put_u16_le(16);
+        buf.put_slice(b"data");
+        buf.put_u32_le(data_len);
+        for sample in samples {
+            buf.put_i16_le(*sample);
+        }
+        buf.freeze()
+    }
+}
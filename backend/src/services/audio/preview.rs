@@ -0,0 +1,207 @@
+//! Waveform peaks and spectrogram images shown when previewing samples
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+use super::decode::DecodedAudio;
+
+/// Levels below full scale in dB drawn in the spectrogram, quieter ones are black
+const DYNAMIC_RANGE: f64 = 100.0;
+
+/// Smallest transform of a spectrogram column, so that short images keep some frequency resolution
+const MIN_FFT_SIZE: usize = 256;
+
+/// Color stops from silence to full scale, approximating the magma colormap
+const COLORMAP: [[f64; 3]; 5] = [
+    [0.0, 0.0, 4.0],
+    [81.0, 18.0, 124.0],
+    [183.0, 55.0, 121.0],
+    [252.0, 137.0, 97.0],
+    [252.0, 253.0, 191.0],
+];
+
+/// Minimum and maximum of consecutive frames of each channel
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Peaks {
+    pub sample_rate: u32,
+    /// Frames covered by each peak, the last peak may cover fewer
+    pub frames_per_peak: usize,
+    /// `[min, max]` pairs of each channel
+    pub channels: Vec<Vec<[f32; 2]>>,
+}
+
+/// Downsample audio to at most `resolution` peaks per channel
+///
+/// Audio shorter than the resolution gets a peak for every frame.
+pub fn peaks(audio: &DecodedAudio, resolution: usize) -> Peaks {
+    let frames = audio.channels.first().map_or(0, Vec::len);
+    let frames_per_peak = frames.div_ceil(resolution.max(1)).max(1);
+    let channels = audio
+        .channels
+        .iter()
+        .map(|channel| {
+            channel
+                .chunks(frames_per_peak)
+                .map(|chunk| {
+                    let (min, max) = chunk
+                        .iter()
+                        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), x| {
+                            (min.min(*x), max.max(*x))
+                        });
+                    [min as f32, max as f32]
+                })
+                .collect()
+        })
+        .collect();
+    Peaks {
+        sample_rate: audio.sample_rate,
+        frames_per_peak,
+        channels,
+    }
+}
+
+/// Render a spectrogram of all channels as an RGB PNG image
+///
+/// Time runs left to right over the whole audio and frequency bottom to top up to the Nyquist frequency, both linear.
+/// Every column is a Hann windowed transform centered on its time, with the power averaged over channels.
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+pub fn spectrogram(audio: &DecodedAudio, width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let fft_size = (2 * height).next_power_of_two().max(MIN_FFT_SIZE);
+    let bins = fft_size / 2;
+    let window = (0..fft_size)
+        .map(|i| 0.5 - 0.5 * (std::f64::consts::TAU * i as f64 / fft_size as f64).cos())
+        .collect::<Vec<_>>();
+    // Power of a full scale sine in its bin, so that it is drawn at 0 dB
+    let reference = (window.iter().sum::<f64>() / 2.0).powi(2) * audio.channels.len().max(1) as f64;
+    let fft = FftPlanner::new().plan_fft_forward(fft_size);
+    let frames = audio.channels.first().map_or(0, Vec::len);
+    let hop = frames as f64 / width as f64;
+
+    let mut image = vec![0u8; width * height * 3];
+    let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+    let mut power = vec![0.0; bins];
+    for column in 0..width {
+        let start = ((column as f64 + 0.5) * hop) as isize - (fft_size / 2) as isize;
+        power.fill(0.0);
+        for channel in &audio.channels {
+            for (i, (value, weight)) in buffer.iter_mut().zip(&window).enumerate() {
+                let sample = usize::try_from(start + i as isize)
+                    .ok()
+                    .and_then(|index| channel.get(index))
+                    .copied()
+                    .unwrap_or(0.0);
+                *value = Complex::new(sample * weight, 0.0);
+            }
+            fft.process(&mut buffer);
+            for (bin, value) in power.iter_mut().zip(&buffer) {
+                *bin += value.norm_sqr() / reference;
+            }
+        }
+        for row in 0..height {
+            let low = (height - 1 - row) * bins / height;
+            let high = ((height - row) * bins / height).max(low + 1);
+            let level = power[low..high].iter().fold(0.0f64, |a, b| a.max(*b));
+            let value = ((10.0 * level.log10() + DYNAMIC_RANGE) / DYNAMIC_RANGE).clamp(0.0, 1.0);
+            let offset = (row * width + column) * 3;
+            image[offset..offset + 3].copy_from_slice(&color(value));
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&image))
+        .expect("Image encodes into memory");
+    png
+}
+
+/// Color of a level between 0 (silence) and 1 (full scale)
+fn color(value: f64) -> [u8; 3] {
+    let position = value * (COLORMAP.len() - 1) as f64;
+    let index = (position as usize).min(COLORMAP.len() - 2);
+    let fraction = position - index as f64;
+    let (from, to) = (COLORMAP[index], COLORMAP[index + 1]);
+    [0, 1, 2].map(|i| (from[i] + (to[i] - from[i]) * fraction).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::services::audio::decode::DecodedAudio;
+
+    use super::{peaks, spectrogram, Peaks};
+
+    #[test]
+    fn peaks_per_chunk() {
+        let audio = DecodedAudio {
+            sample_rate: 8000,
+            channels: vec![
+                vec![0.0, 1.0, -1.0, 0.5, 0.25, -0.25],
+                vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.5],
+            ],
+        };
+
+        let result = peaks(&audio, 3);
+
+        assert_eq!(
+            result,
+            Peaks {
+                sample_rate: 8000,
+                frames_per_peak: 2,
+                channels: vec![
+                    vec![[0.0, 1.0], [-1.0, 0.5], [-0.25, 0.25]],
+                    vec![[0.0, 0.0], [0.0, 0.0], [0.0, 0.5]],
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn peaks_of_short_audio() {
+        let audio = DecodedAudio {
+            sample_rate: 8000,
+            channels: vec![vec![0.5, -0.5]],
+        };
+
+        let result = peaks(&audio, 1000);
+
+        assert_eq!(result.frames_per_peak, 1);
+        assert_eq!(result.channels, vec![vec![[0.5, 0.5], [-0.5, -0.5]]]);
+    }
+
+    #[test]
+    fn spectrogram_of_tone() {
+        // A quarter of the sample rate lies halfway up the image
+        let audio = DecodedAudio {
+            sample_rate: 16000,
+            channels: vec![(0..16000)
+                .map(|i| (i as f64 * std::f64::consts::FRAC_PI_2).sin())
+                .collect()],
+        };
+
+        let png = spectrogram(&audio, 32, 64);
+
+        let decoder = png::Decoder::new(Cursor::new(png));
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!((info.width, info.height), (32, 64));
+        let column = 16;
+        let brightest = (0..64)
+            .max_by_key(|row| {
+                let offset = (row * 32 + column) * 3;
+                image[offset..offset + 3]
+                    .iter()
+                    .map(|x| *x as u32)
+                    .sum::<u32>()
+            })
+            .unwrap();
+        assert_eq!(brightest, 31);
+    }
+}
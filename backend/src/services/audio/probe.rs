@@ -0,0 +1,127 @@
+use std::io::ErrorKind;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use super::{
+    loudness::{Loudness, LoudnessMeter},
+    AudioError, AudioResult,
+};
+
+/// Audio stream parameters detected while probing an uploaded file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioFormat {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Not available for lossy codecs
+    pub bit_depth: Option<u32>,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Missing for files uploaded before loudness was measured
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+}
+
+impl AudioFormat {
+    /// MIME type used when serving files in this format
+    pub fn mime_type(&self) -> &'static str {
+        match self.codec.as_str() {
+            "flac" => "audio/flac",
+            "mp3" => "audio/mpeg",
+            "vorbis" => "audio/ogg",
+            codec if codec.starts_with("pcm_") || codec.starts_with("adpcm_") => "audio/wav",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+/// Probe the container and decode the whole stream to make sure it is playable, measuring its loudness on the way.
+///
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+pub fn probe(source: Box<dyn MediaSource>) -> AudioResult<AudioFormat> {
+    let stream = MediaSourceStream::new(source, Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &Hint::new(),
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut reader = probed.format;
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(AudioError::NoTrack)?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let codec = symphonia::default::get_codecs()
+        .get_codec(params.codec)
+        .map(|descriptor| descriptor.short_name.to_owned())
+        .ok_or(AudioError::Unsupported("unknown codec".to_owned()))?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&params, &DecoderOptions { verify: true })?;
+
+    let mut frames = 0u64;
+    let mut sample_rate = params.sample_rate;
+    let mut channels = params.channels.map(|channels| channels.count());
+    let mut meter: Option<LoudnessMeter> = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => Err(e)?,
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = decoder.decode(&packet)?;
+        let spec = *decoded.spec();
+        sample_rate.get_or_insert(spec.rate);
+        channels.get_or_insert(spec.channels.count());
+        frames += decoded.frames() as u64;
+        if decoded.frames() == 0 {
+            continue;
+        }
+        let meter = match meter.as_mut() {
+            Some(meter) => meter,
+            None => meter.insert(LoudnessMeter::new(spec.channels.count(), spec.rate)?),
+        };
+        let buffer = match buffer.as_mut() {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * spec.channels.count() => {
+                buffer
+            }
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        meter.add(buffer.samples())?;
+    }
+
+    let sample_rate = sample_rate
+        .filter(|rate| *rate > 0)
+        .ok_or(AudioError::Malformed("missing sample rate".to_owned()))?;
+    let channels = channels
+        .filter(|channels| *channels > 0)
+        .ok_or(AudioError::Malformed("missing channel layout".to_owned()))?;
+    if frames == 0 {
+        Err(AudioError::Malformed("stream contains no audio".to_owned()))?
+    }
+    Ok(AudioFormat {
+        codec,
+        sample_rate,
+        channels: channels as u16,
+        bit_depth: params.bits_per_sample,
+        duration: frames as f64 / sample_rate as f64,
+        loudness: meter.map(LoudnessMeter::finish),
+    })
+}
@@ -0,0 +1,316 @@
+//! Post-processing of decoded audio
+//!
+//! Operations are applied in order to every channel, times are in seconds and levels in dB.
+
+use std::f64::consts::PI;
+
+use rubato::{audioadapter::Adapter, audioadapter_buffers::direct::SequentialSliceOfVecs};
+use rubato::{Fft, FixedSync, Resampler};
+use serde::{Deserialize, Serialize};
+use validator::ValidationError;
+
+use super::{decode::DecodedAudio, AudioError, AudioResult};
+
+/// Most operations in a processing chain
+pub const MAX_OPERATIONS: usize = 32;
+
+/// Frames resampled at once
+const RESAMPLE_CHUNK: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Operation {
+    /// Keep the audio between two times, up to the end when `end` is missing
+    Trim {
+        #[serde(default)]
+        start: f64,
+        #[serde(default)]
+        end: Option<f64>,
+    },
+    /// Remove leading audio quieter than the threshold in dBFS on every channel, and trailing audio too if asked
+    TrimSilence {
+        #[serde(default = "default_silence_threshold")]
+        threshold: f64,
+        #[serde(default)]
+        trailing: bool,
+    },
+    /// Raised-cosine onset and offset ramps
+    Ramps {
+        #[serde(default)]
+        onset: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    Gain {
+        gain: f64,
+    },
+    /// Band-limited resampling to another rate in Hz
+    Resample {
+        sample_rate: u32,
+    },
+}
+
+fn default_silence_threshold() -> f64 {
+    -60.0
+}
+
+pub fn validate_operations(operations: &[Operation]) -> Result<(), ValidationError> {
+    if operations.is_empty() || operations.len() > MAX_OPERATIONS {
+        return Err(ValidationError::new("operation_count"));
+    }
+    let seconds = |time: f64| (0.0..=3600.0).contains(&time);
+    for operation in operations {
+        let valid = match *operation {
+            Operation::Trim { start, end } => {
+                seconds(start) && end.is_none_or(|end| seconds(end) && end > start)
+            }
+            Operation::TrimSilence { threshold, .. } => (-120.0..=0.0).contains(&threshold),
+            Operation::Ramps { onset, offset } => seconds(onset) && seconds(offset),
+            Operation::Gain { gain } => (-60.0..=60.0).contains(&gain),
+            Operation::Resample { sample_rate } => (8000..=192000).contains(&sample_rate),
+        };
+        if !valid {
+            return Err(ValidationError::new("invalid_operation"));
+        }
+    }
+    Ok(())
+}
+
+/// Apply operations in order
+///
+/// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+pub fn process(mut audio: DecodedAudio, operations: &[Operation]) -> AudioResult<DecodedAudio> {
+    for operation in operations {
+        let rate = audio.sample_rate as f64;
+        let frames = audio.channels.first().map_or(0, Vec::len);
+        let frame = |time: f64| ((time * rate).round() as usize).min(frames);
+        match *operation {
+            Operation::Trim { start, end } => {
+                let range = frame(start)..end.map_or(frames, frame).max(frame(start));
+                keep(&mut audio, range);
+            }
+            Operation::TrimSilence {
+                threshold,
+                trailing,
+            } => {
+                let threshold = 10f64.powf(threshold / 20.0);
+                let audible = |frame: &usize| {
+                    audio
+                        .channels
+                        .iter()
+                        .any(|channel| channel[*frame].abs() > threshold)
+                };
+                let start = (0..frames).find(audible).unwrap_or(frames);
+                let end = match trailing {
+                    true => (start..frames)
+                        .rev()
+                        .find(audible)
+                        .map_or(start, |end| end + 1),
+                    false => frames,
+                };
+                keep(&mut audio, start..end);
+            }
+            Operation::Ramps { onset, offset } => {
+                for channel in &mut audio.channels {
+                    apply_ramps(channel, frame(onset), frame(offset));
+                }
+            }
+            Operation::Gain { gain } => {
+                let gain = 10f64.powf(gain / 20.0);
+                audio
+                    .channels
+                    .iter_mut()
+                    .flatten()
+                    .for_each(|sample| *sample *= gain);
+            }
+            Operation::Resample { sample_rate } => {
+                audio = resample(audio, sample_rate)?;
+            }
+        }
+    }
+    Ok(audio)
+}
+
+fn keep(audio: &mut DecodedAudio, range: std::ops::Range<usize>) {
+    for channel in &mut audio.channels {
+        channel.truncate(range.end);
+        channel.drain(..range.start);
+    }
+}
+
+/// Raised-cosine onset and offset ramps, each at most half of the signal long
+pub fn apply_ramps(signal: &mut [f64], onset: usize, offset: usize) {
+    let total = signal.len();
+    let ramp = |i: usize, len: usize| 0.5 - 0.5 * (PI * i as f64 / len as f64).cos();
+    let onset = onset.min(total / 2);
+    for (i, sample) in signal[..onset].iter_mut().enumerate() {
+        *sample *= ramp(i, onset);
+    }
+    let offset = offset.min(total / 2);
+    for (i, sample) in signal[total - offset..].iter_mut().rev().enumerate() {
+        *sample *= ramp(i, offset);
+    }
+}
+
+fn resample(audio: DecodedAudio, sample_rate: u32) -> AudioResult<DecodedAudio> {
+    let frames = audio.channels.first().map_or(0, Vec::len);
+    if sample_rate == audio.sample_rate || frames == 0 {
+        return Ok(DecodedAudio {
+            sample_rate,
+            ..audio
+        });
+    }
+    let error =
+        |e: &dyn std::error::Error| AudioError::Unsupported(format!("cannot resample: {e}"));
+    let count = audio.channels.len();
+    let input =
+        SequentialSliceOfVecs::new(&audio.channels, count, frames).map_err(|e| error(&e))?;
+    let mut resampler = Fft::<f64>::new(
+        audio.sample_rate as usize,
+        sample_rate as usize,
+        RESAMPLE_CHUNK,
+        count,
+        FixedSync::Input,
+    )
+    .map_err(|e| error(&e))?;
+    let output = resampler
+        .process_all(&input, frames, None)
+        .map_err(|e| error(&e))?;
+    let channels = (0..count)
+        .map(|channel| {
+            (0..output.frames())
+                .map(|frame| output.read_sample(channel, frame).unwrap_or(0.0))
+                .collect()
+        })
+        .collect();
+    Ok(DecodedAudio {
+        sample_rate,
+        channels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::audio::decode::DecodedAudio;
+
+    use super::{process, validate_operations, Operation};
+
+    fn audio(channels: Vec<Vec<f64>>) -> DecodedAudio {
+        DecodedAudio {
+            sample_rate: 1000,
+            channels,
+        }
+    }
+
+    #[test]
+    fn trim() {
+        let input = audio(vec![(0..1000).map(|i| i as f64).collect()]);
+
+        let result = process(
+            input,
+            &[Operation::Trim {
+                start: 0.1,
+                end: Some(0.25),
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(result.channels[0].len(), 150);
+        assert_eq!(result.channels[0][0], 100.0);
+    }
+
+    #[test]
+    fn trim_silence() {
+        let mut left = vec![0.0; 100];
+        left[20] = 0.5;
+        left[60] = -0.5;
+        let mut right = vec![0.0; 100];
+        right[70] = 0.001;
+        let input = audio(vec![left, right]);
+
+        let leading = process(
+            input,
+            &[Operation::TrimSilence {
+                threshold: -40.0,
+                trailing: false,
+            }],
+        )
+        .unwrap();
+        let both = process(
+            audio(leading.channels.clone()),
+            &[Operation::TrimSilence {
+                threshold: -80.0,
+                trailing: true,
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(leading.channels[0].len(), 80);
+        assert_eq!(leading.channels[0][0], 0.5);
+        // The quiet right channel keeps the end at a low threshold
+        assert_eq!(both.channels[1].len(), 51);
+        assert_eq!(both.channels[1][50], 0.001);
+    }
+
+    #[test]
+    fn ramps_and_gain() {
+        let input = audio(vec![vec![0.5; 1000]]);
+
+        let result = process(
+            input,
+            &[
+                Operation::Gain { gain: 6.0206 },
+                Operation::Ramps {
+                    onset: 0.01,
+                    offset: 0.1,
+                },
+            ],
+        )
+        .unwrap();
+
+        let channel = &result.channels[0];
+        assert_eq!(channel[0], 0.0);
+        assert!((channel[5] - 0.5).abs() < 1e-3);
+        assert!((channel[500] - 1.0).abs() < 1e-3);
+        assert!((channel[949] - 0.5).abs() < 0.02);
+        assert!(channel[999].abs() < 1e-3);
+    }
+
+    #[test]
+    fn resample() {
+        let tone = (0..44100)
+            .map(|i| (i as f64 * 1000.0 * std::f64::consts::TAU / 44100.0).sin())
+            .collect();
+        let input = DecodedAudio {
+            sample_rate: 44100,
+            channels: vec![tone],
+        };
+
+        let result = process(input, &[Operation::Resample { sample_rate: 48000 }]).unwrap();
+
+        assert_eq!(result.sample_rate, 48000);
+        assert!(result.channels[0].len().abs_diff(48000) < 10);
+        // A tone keeps its frequency, a quarter period later the phase has moved by 90°
+        let peak = (10000..10048)
+            .max_by(|a, b| result.channels[0][*a].total_cmp(&result.channels[0][*b]))
+            .unwrap();
+        assert!((result.channels[0][peak] - 1.0).abs() < 0.01);
+        assert!(result.channels[0][peak + 12].abs() < 0.01);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(validate_operations(&[]).is_err());
+        assert!(validate_operations(&[Operation::Trim {
+            start: 1.0,
+            end: Some(0.5)
+        }])
+        .is_err());
+        assert!(validate_operations(&[Operation::Resample { sample_rate: 100 }]).is_err());
+        assert!(validate_operations(&[Operation::Gain { gain: -6.0 }]).is_ok());
+    }
+}
@@ -0,0 +1,421 @@
+//! Synthetic stimuli
+//!
+//! Signals are rendered at the requested peak level with raised-cosine onset and offset ramps,
+//! and copied to every channel. Noise is generated from a seed, so the same parameters always give the same audio.
+
+use std::f64::consts::PI;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use super::{
+    process::apply_ramps,
+    wav::{encode_wav, wav_size},
+};
+
+/// Parametric description of a generated stimulus, stored with the sample to reproduce it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_stimulus"))]
+pub struct Stimulus {
+    pub signal: Signal,
+    /// Seconds
+    #[validate(range(min = 0.0, max = 60.0))]
+    pub duration: f64,
+    /// Length of each of the onset and offset ramps in seconds
+    #[validate(range(min = 0.0))]
+    #[serde(default)]
+    pub ramp: f64,
+    /// Peak level in dBFS
+    #[validate(range(min = -120.0, max = 0.0))]
+    #[serde(default = "default_level")]
+    pub level: f64,
+    #[validate(range(min = 8000, max = 192000))]
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: u32,
+    #[validate(range(min = 1, max = 8))]
+    #[serde(default = "default_channels")]
+    pub channels: u16,
+    /// 16 or 24
+    #[serde(default = "default_bit_depth")]
+    pub bit_depth: u16,
+    /// Seed of the noise generator, picked at random when missing
+    #[serde(default)]
+    pub seed: Option<u32>,
+}
+
+fn default_level() -> f64 {
+    -6.0
+}
+
+fn default_sample_rate() -> u32 {
+    48000
+}
+
+fn default_channels() -> u16 {
+    1
+}
+
+fn default_bit_depth() -> u16 {
+    24
+}
+
+/// Signal types, frequencies are in Hz
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Signal {
+    /// Gaussian noise, band-limited when any of the band edges is given
+    Noise {
+        #[serde(default)]
+        low_frequency: Option<f64>,
+        #[serde(default)]
+        high_frequency: Option<f64>,
+    },
+    Tone {
+        frequency: f64,
+    },
+    /// Sine sweep between two frequencies
+    Chirp {
+        start_frequency: f64,
+        end_frequency: f64,
+        #[serde(default)]
+        sweep: Sweep,
+    },
+    /// Train of rectangular clicks
+    Clicks {
+        /// Clicks per second
+        rate: f64,
+        /// Seconds
+        click_duration: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Sweep {
+    #[default]
+    Linear,
+    Logarithmic,
+}
+
+fn validate_stimulus(stimulus: &Stimulus) -> Result<(), ValidationError> {
+    let nyquist = stimulus.sample_rate as f64 / 2.0;
+    let audible = |frequency: f64| frequency > 0.0 && frequency < nyquist;
+    let valid = match stimulus.signal {
+        Signal::Noise {
+            low_frequency,
+            high_frequency,
+        } => {
+            low_frequency.is_none_or(audible)
+                && high_frequency.is_none_or(audible)
+                && low_frequency.unwrap_or(0.0) < high_frequency.unwrap_or(nyquist)
+        }
+        Signal::Tone { frequency } => audible(frequency),
+        Signal::Chirp {
+            start_frequency,
+            end_frequency,
+            ..
+        } => audible(start_frequency) && audible(end_frequency),
+        Signal::Clicks {
+            rate,
+            click_duration,
+        } => rate > 0.0 && click_duration > 0.0 && click_duration <= 1.0 / rate,
+    };
+    if !valid {
+        return Err(ValidationError::new("invalid_signal"));
+    }
+    if stimulus.ramp > stimulus.duration / 2.0 {
+        return Err(ValidationError::new("ramp_too_long"));
+    }
+    if stimulus.bit_depth != 16 && stimulus.bit_depth != 24 {
+        return Err(ValidationError::new("unsupported_bit_depth"));
+    }
+    if stimulus.frames() == 0 {
+        return Err(ValidationError::new("too_short"));
+    }
+    Ok(())
+}
+
+impl Stimulus {
+    pub fn frames(&self) -> u64 {
+        (self.duration * self.sample_rate as f64).round() as u64
+    }
+
+    /// Size in bytes of the rendered WAV file
+    pub fn wav_size(&self) -> u64 {
+        wav_size(self.frames(), self.channels, self.bit_depth)
+    }
+
+    /// Render the stimulus as a WAV file
+    ///
+    /// This is CPU bound, run it through [`tokio::task::spawn_blocking`] from async code.
+    pub fn render_wav(&self) -> Vec<u8> {
+        let signal = self.render();
+        let channels = vec![&signal[..]; self.channels as usize];
+        encode_wav(&channels, self.sample_rate, self.bit_depth)
+    }
+
+    /// Render a single channel
+    pub fn render(&self) -> Vec<f64> {
+        let frames = self.frames() as usize;
+        let rate = self.sample_rate as f64;
+        let time = |i: usize| i as f64 / rate;
+        let mut signal = match self.signal {
+            Signal::Noise {
+                low_frequency,
+                high_frequency,
+            } => {
+                let mut random = SplitMix64::new(self.seed.unwrap_or_default() as u64);
+                let noise = (0..frames).map(|_| random.gaussian()).collect::<Vec<_>>();
+                if low_frequency.is_some() || high_frequency.is_some() {
+                    band_limit(
+                        noise,
+                        low_frequency.unwrap_or(0.0) / rate,
+                        high_frequency.unwrap_or(rate / 2.0) / rate,
+                    )
+                } else {
+                    noise
+                }
+            }
+            Signal::Tone { frequency } => (0..frames)
+                .map(|i| (2.0 * PI * frequency * time(i)).sin())
+                .collect(),
+            Signal::Chirp {
+                start_frequency,
+                end_frequency,
+                sweep,
+            } => {
+                let duration = frames as f64 / rate;
+                let phase = |t: f64| match sweep {
+                    Sweep::Logarithmic if start_frequency != end_frequency => {
+                        let ratio = end_frequency / start_frequency;
+                        start_frequency * duration / ratio.ln() * (ratio.powf(t / duration) - 1.0)
+                    }
+                    _ => {
+                        start_frequency * t
+                            + (end_frequency - start_frequency) * t * t / (2.0 * duration)
+                    }
+                };
+                (0..frames)
+                    .map(|i| (2.0 * PI * phase(time(i))).sin())
+                    .collect()
+            }
+            Signal::Clicks {
+                rate: click_rate,
+                click_duration,
+            } => (0..frames)
+                .map(|i| {
+                    let since_click = time(i) % (1.0 / click_rate);
+                    // At least one sample per click
+                    if since_click < click_duration.max(1.0 / rate) {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                })
+                .collect(),
+        };
+        let peak = signal.iter().fold(0.0f64, |peak, x| peak.max(x.abs()));
+        if peak > 0.0 {
+            let gain = 10f64.powf(self.level / 20.0) / peak;
+            signal.iter_mut().for_each(|x| *x *= gain);
+        }
+        let ramp = (self.ramp * rate).round() as usize;
+        apply_ramps(&mut signal, ramp, ramp);
+        signal
+    }
+}
+
+/// Remove frequencies outside of the band, given as fractions of the sample rate
+fn band_limit(signal: Vec<f64>, low: f64, high: f64) -> Vec<f64> {
+    let len = signal.len();
+    let mut spectrum = signal
+        .into_iter()
+        .map(|x| Complex::new(x, 0.0))
+        .collect::<Vec<_>>();
+    let mut planner = FftPlanner::new();
+    planner.plan_fft_forward(len).process(&mut spectrum);
+    for (i, bin) in spectrum.iter_mut().enumerate() {
+        let frequency = i.min(len - i) as f64 / len as f64;
+        if frequency < low || frequency > high {
+            *bin = Complex::new(0.0, 0.0);
+        }
+    }
+    planner.plan_fft_inverse(len).process(&mut spectrum);
+    spectrum.into_iter().map(|x| x.re).collect()
+}
+
+/// Small deterministic generator, independent of library versions so stored seeds stay reproducible
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in (0, 1]
+    fn uniform(&mut self) -> f64 {
+        ((self.next() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, Box-Muller transform
+    fn gaussian(&mut self) -> f64 {
+        let radius = (-2.0 * self.uniform().ln()).sqrt();
+        radius * (2.0 * PI * self.uniform()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustfft::{num_complex::Complex, FftPlanner};
+    use validator::Validate;
+
+    use super::{Signal, Stimulus, Sweep};
+
+    fn stimulus(signal: Signal) -> Stimulus {
+        Stimulus {
+            signal,
+            duration: 0.5,
+            ramp: 0.01,
+            level: -6.0,
+            sample_rate: 16000,
+            channels: 1,
+            bit_depth: 16,
+            seed: Some(7),
+        }
+    }
+
+    fn peak(signal: &[f64]) -> f64 {
+        signal.iter().fold(0.0f64, |peak, x| peak.max(x.abs()))
+    }
+
+    /// Fraction of the energy within the band, frequencies in Hz
+    fn energy_in_band(signal: &[f64], sample_rate: f64, low: f64, high: f64) -> f64 {
+        let mut spectrum = signal
+            .iter()
+            .map(|x| Complex::new(*x, 0.0))
+            .collect::<Vec<_>>();
+        FftPlanner::new()
+            .plan_fft_forward(spectrum.len())
+            .process(&mut spectrum);
+        let len = spectrum.len();
+        let (inside, total) =
+            spectrum[..len / 2]
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(inside, total), (i, bin)| {
+                    let frequency = i as f64 * sample_rate / len as f64;
+                    let energy = bin.norm_sqr();
+                    if frequency >= low && frequency <= high {
+                        (inside + energy, total + energy)
+                    } else {
+                        (inside, total + energy)
+                    }
+                });
+        inside / total
+    }
+
+    #[test]
+    fn level_and_ramps() {
+        let signal = stimulus(Signal::Tone { frequency: 1000.0 }).render();
+
+        assert_eq!(signal.len(), 8000);
+        assert!((peak(&signal) - 10f64.powf(-6.0 / 20.0)).abs() < 1e-6);
+        assert_eq!(signal[0], 0.0);
+        assert!(peak(&signal[..16]) < 0.05);
+        assert!(peak(&signal[signal.len() - 16..]) < 0.05);
+    }
+
+    #[test]
+    fn band_noise() {
+        let mut noise = stimulus(Signal::Noise {
+            low_frequency: Some(1000.0),
+            high_frequency: Some(2000.0),
+        });
+        noise.ramp = 0.0;
+
+        let signal = noise.render();
+
+        assert!(energy_in_band(&signal, 16000.0, 990.0, 2010.0) > 0.999);
+    }
+
+    #[test]
+    fn reproducible_noise() {
+        let noise = stimulus(Signal::Noise {
+            low_frequency: None,
+            high_frequency: None,
+        });
+        let mut other_seed = noise.clone();
+        other_seed.seed = Some(8);
+
+        assert_eq!(noise.render(), noise.render());
+        assert_ne!(noise.render(), other_seed.render());
+    }
+
+    #[test]
+    fn chirp_sweeps() {
+        for sweep in [Sweep::Linear, Sweep::Logarithmic] {
+            let mut chirp = stimulus(Signal::Chirp {
+                start_frequency: 500.0,
+                end_frequency: 4000.0,
+                sweep,
+            });
+            chirp.ramp = 0.0;
+
+            let signal = chirp.render();
+
+            assert!(energy_in_band(&signal, 16000.0, 400.0, 4100.0) > 0.99);
+            assert!(energy_in_band(&signal[..800], 16000.0, 0.0, 1200.0) > 0.9);
+        }
+    }
+
+    #[test]
+    fn click_train() {
+        let mut clicks = stimulus(Signal::Clicks {
+            rate: 10.0,
+            click_duration: 0.001,
+        });
+        clicks.ramp = 0.0;
+
+        let signal = clicks.render();
+
+        let onsets = signal
+            .windows(2)
+            .filter(|pair| pair[0] == 0.0 && pair[1] > 0.0)
+            .count();
+        assert_eq!(onsets + 1, 5);
+        assert_eq!(signal.iter().filter(|x| **x > 0.0).count(), 5 * 16);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut above_nyquist = stimulus(Signal::Tone { frequency: 9000.0 });
+        let mut long_ramp = stimulus(Signal::Tone { frequency: 1000.0 });
+        long_ramp.ramp = 0.3;
+        let inverted_band = stimulus(Signal::Noise {
+            low_frequency: Some(2000.0),
+            high_frequency: Some(1000.0),
+        });
+        let mut bit_depth = stimulus(Signal::Tone { frequency: 1000.0 });
+        bit_depth.bit_depth = 8;
+
+        for stimulus in [&above_nyquist, &long_ramp, &inverted_band, &bit_depth] {
+            assert!(stimulus.validate().is_err());
+        }
+        above_nyquist.sample_rate = 48000;
+        assert!(above_nyquist.validate().is_ok());
+    }
+}
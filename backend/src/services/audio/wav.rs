@@ -0,0 +1,120 @@
+//! WAV encoding of rendered audio
+
+use bytes::{BufMut, BytesMut};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+/// `KSDATAFORMAT_SUBTYPE_PCM`
+const SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Size in bytes of the WAV file produced by [`encode_wav`]
+pub fn wav_size(frames: u64, channels: u16, bit_depth: u16) -> u64 {
+    header_size(channels, bit_depth) as u64 + frames * channels as u64 * (bit_depth / 8) as u64
+}
+
+fn header_size(channels: u16, bit_depth: u16) -> u32 {
+    if is_extensible(channels, bit_depth) {
+        68
+    } else {
+        44
+    }
+}
+
+/// More than two channels or more than 16 bits need the extensible format
+fn is_extensible(channels: u16, bit_depth: u16) -> bool {
+    channels > 2 || bit_depth > 16
+}
+
+/// Encode channels of samples within [-1, 1] as integer PCM with 16 or 24 bits
+///
+/// Channels must have the same length, samples outside of the range are clipped.
+pub fn encode_wav(channels: &[&[f64]], sample_rate: u32, bit_depth: u16) -> Vec<u8> {
+    assert!(bit_depth == 16 || bit_depth == 24, "Unsupported bit depth");
+    let frames = channels.first().map_or(0, |channel| channel.len());
+    assert!(
+        channels.iter().all(|channel| channel.len() == frames),
+        "Channels differ in length"
+    );
+    let bytes = bit_depth / 8;
+    let data_len = (frames * channels.len() * bytes as usize) as u32;
+    let count = channels.len() as u16;
+    let extensible = is_extensible(count, bit_depth);
+    let header_size = header_size(count, bit_depth);
+    let mut buf = BytesMut::with_capacity((header_size + data_len) as usize);
+    buf.put_slice(b"RIFF");
+    buf.put_u32_le(header_size - 8 + data_len);
+    buf.put_slice(b"WAVEfmt ");
+    buf.put_u32_le(if extensible { 40 } else { 16 });
+    buf.put_u16_le(if extensible {
+        WAVE_FORMAT_EXTENSIBLE
+    } else {
+        WAVE_FORMAT_PCM
+    });
+    buf.put_u16_le(count);
+    buf.put_u32_le(sample_rate);
+    buf.put_u32_le(sample_rate * (count * bytes) as u32);
+    buf.put_u16_le(count * bytes);
+    buf.put_u16_le(bit_depth);
+    if extensible {
+        buf.put_u16_le(22);
+        buf.put_u16_le(bit_depth);
+        // Speaker positions in the order of the channel mask bits
+        buf.put_u32_le(((1u64 << count) - 1) as u32);
+        buf.put_slice(&SUBTYPE_PCM);
+    }
+    buf.put_slice(b"data");
+    buf.put_u32_le(data_len);
+    for frame in 0..frames {
+        for channel in channels {
+            let value = quantize(channel[frame], bit_depth);
+            buf.put_slice(&value.to_le_bytes()[..bytes as usize]);
+        }
+    }
+    buf.to_vec()
+}
+
+/// Convert a sample within [-1, 1] to an integer with the given number of bits, clipping it
+///
+/// This inverts decoding, so integer PCM decoded and quantized at the same bit depth is unchanged.
+pub fn quantize(value: f64, bit_depth: u16) -> i32 {
+    let scale = (1i32 << (bit_depth - 1)) as f64;
+    (value * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::services::audio::probe::probe;
+
+    use super::{encode_wav, wav_size};
+
+    #[test]
+    fn probed() {
+        for (channels, bit_depth) in [(1, 16), (2, 16), (1, 24), (6, 24)] {
+            let samples = vec![0.5; 4800];
+
+            let data = encode_wav(&vec![&samples[..]; channels as usize], 48000, bit_depth);
+
+            assert_eq!(data.len() as u64, wav_size(4800, channels, bit_depth));
+            let format = probe(Box::new(Cursor::new(data))).unwrap();
+            assert_eq!(format.channels, channels);
+            assert_eq!(format.sample_rate, 48000);
+            assert_eq!(format.bit_depth, Some(bit_depth as u32));
+            assert_eq!(format.duration, 0.1);
+        }
+    }
+
+    #[test]
+    fn clipped() {
+        let data = encode_wav(&[&[1.0, -1.0, 2.0]], 8000, 16);
+
+        let samples = data[44..]
+            .chunks(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(samples, vec![i16::MAX, i16::MIN, i16::MAX]);
+    }
+}
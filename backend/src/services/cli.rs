@@ -0,0 +1,68 @@
+//! Command line arguments
+
+use super::repositories::consistency::CheckOptions;
+
+pub const USAGE: &str = "Usage: backend [serve | fsck [--verify-hashes] [--repair]]";
+
+#[derive(Debug)]
+pub enum Command {
+    /// Run the server, the default
+    Serve,
+    /// Check storage consistency, print the report and exit
+    Fsck(CheckOptions),
+}
+
+impl Command {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = match args.next().as_deref() {
+            None | Some("serve") => Self::Serve,
+            Some("fsck") => {
+                let mut options = CheckOptions::default();
+                for arg in args.by_ref() {
+                    match arg.as_str() {
+                        "--verify-hashes" => options.verify_hashes = true,
+                        "--repair" => options.repair = true,
+                        option => return Err(format!("Unknown option `{option}`")),
+                    }
+                }
+                Self::Fsck(options)
+            }
+            Some(command) => return Err(format!("Unknown command `{command}`")),
+        };
+        match args.next() {
+            Some(arg) => Err(format!("Unexpected argument `{arg}`")),
+            None => Ok(command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn serve() {
+        assert!(matches!(parse(&[]), Ok(Command::Serve)));
+        assert!(matches!(parse(&["serve"]), Ok(Command::Serve)));
+        parse(&["serve", "--repair"]).unwrap_err();
+    }
+
+    #[test]
+    fn fsck() {
+        let Ok(Command::Fsck(options)) = parse(&["fsck", "--repair"]) else {
+            panic!("Expected fsck command");
+        };
+        assert!(options.repair);
+        assert!(!options.verify_hashes);
+        parse(&["fsck", "--force"]).unwrap_err();
+    }
+
+    #[test]
+    fn unknown() {
+        parse(&["migrate"]).unwrap_err();
+    }
+}
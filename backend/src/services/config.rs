@@ -3,8 +3,9 @@ use serde::Deserialize;
 use validator::Validate;
 
 use super::{
-    app::AppConfig, auth::AuthKeysConfig, database::surreal::DatabaseConfig,
-    file_storage::FileStorageConfig, tracing::TracingConfig,
+    app::AppConfig, audio::AudioConfig, auth::AuthKeysConfig, database::surreal::DatabaseConfig,
+    file_storage::FileStorageConfig, repositories::consistency::ConsistencyConfig,
+    tracing::TracingConfig,
 };
 
 #[derive(Debug, Clone, Deserialize, Validate)]
@@ -22,6 +23,8 @@ pub struct Config {
     pub auth_keys: AuthKeysConfig,
     pub database: DatabaseConfig,
     pub file_storage: FileStorageConfig,
+    pub audio: AudioConfig,
+    pub consistency: ConsistencyConfig,
     #[validate]
     pub admin: AdminConfig,
 }
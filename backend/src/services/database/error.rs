@@ -4,12 +4,19 @@ use super::migrator::MigrationError;
 pub enum DbError {
     #[error("Migration error: {0}")]
     Migration(#[from] MigrationError),
+    /// Boxed, SurrealDB errors would make every result in the crate large
     #[error("Query error: {0}")]
-    Query(#[from] surrealdb::Error),
+    Query(Box<surrealdb::Error>),
     #[error("Not found")]
     NotFound,
 }
 
+impl From<surrealdb::Error> for DbError {
+    fn from(value: surrealdb::Error) -> Self {
+        Self::Query(Box::new(value))
+    }
+}
+
 pub type DbResult<T = ()> = Result<T, DbError>;
 
 pub trait ValidateDbResponse
@@ -20,11 +27,19 @@ where
 }
 
 impl ValidateDbResponse for surrealdb::Response {
+    /// Returns the first error, skipping statements which were not executed
+    /// because a transaction failed on a later one
     fn validate(mut self) -> DbResult<Self> {
         let mut errors = self.take_errors().into_iter().collect::<Vec<_>>();
-        errors.sort_by_key(|(k, _)| *k);
+        errors.sort_by_key(|(k, error)| {
+            let not_executed = matches!(
+                error,
+                surrealdb::Error::Db(surrealdb::error::Db::QueryNotExecuted)
+            );
+            (not_executed, *k)
+        });
         if let Some((_, error)) = errors.into_iter().next() {
-            Err(DbError::Query(error))
+            Err(error.into())
         } else {
             Ok(self)
         }
@@ -137,14 +137,16 @@ COMMIT TRANSACTION;
 "#;
 
         let query = PRE.to_owned() + &migration.query + POST;
-        db.query(&query)
+        let response = db
+            .query(&query)
             .bind(("mig_id", migration.id.clone()))
             .bind(("mig_hash", migration.hash.clone()))
-            .await
-            .map_err(|e| {
-                error!(error = ?e, query = &query);
-                e
-            })?;
+            .await;
+        // Errors of single statements, like a `throw` in the migration, are only found in the response
+        if let Err(e) = response.and_then(surrealdb::Response::check) {
+            error!(error = ?e, query = &query);
+            Err(e)?
+        }
         info!("Applied new migration `{}`", &migration.id);
         Ok(())
     }
@@ -163,11 +165,17 @@ COMMIT TRANSACTION;
 #[derive(Debug, thiserror::Error)]
 pub enum MigrationError {
     #[error("{0}")]
-    SurrealDB(#[from] surrealdb::Error),
+    SurrealDB(Box<surrealdb::Error>),
     #[error("{0}")]
     IO(#[from] std::io::Error),
     #[error("Applied migrations do not match provided migrations")]
     Mismatch,
 }
 
+impl From<surrealdb::Error> for MigrationError {
+    fn from(value: surrealdb::Error) -> Self {
+        Self::SurrealDB(Box::new(value))
+    }
+}
+
 pub type MigrationResult<T> = Result<T, MigrationError>;
@@ -0,0 +1,235 @@
+//! Conditional and ranged file responses
+
+use std::{
+    ops::{Bound, Range},
+    time::UNIX_EPOCH,
+};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::headers::{
+    AcceptRanges, ContentLength, ContentRange, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch,
+    IfRange, LastModified,
+};
+use tokio_util::io::ReaderStream;
+
+use super::file_storage::{FileMetadata, FileStorage, FsResult};
+
+/// What part of a stored file should be sent back
+pub struct FileRequest {
+    content_type: &'static str,
+    metadata: FileMetadata,
+    etag: ETag,
+    kind: FileRequestKind,
+}
+
+enum FileRequestKind {
+    NotModified,
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+impl FileRequest {
+    /// Evaluate conditional (`If-None-Match`, `If-Modified-Since`, `If-Range`) and `Range` headers.
+    ///
+    /// The entity tag defaults to file size and modification time.
+    pub fn evaluate(
+        headers: &HeaderMap,
+        content_type: &'static str,
+        metadata: FileMetadata,
+        etag: Option<ETag>,
+    ) -> Self {
+        let etag = etag.unwrap_or_else(|| Self::default_etag(&metadata));
+        let last_modified = LastModified::from(metadata.modified);
+        let kind = Self::evaluate_kind(headers, &metadata, &etag, &last_modified);
+        Self {
+            content_type,
+            metadata,
+            etag,
+            kind,
+        }
+    }
+
+    fn default_etag(metadata: &FileMetadata) -> ETag {
+        let modified = metadata
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        format!("\"{:x}-{:x}\"", metadata.size, modified)
+            .parse()
+            .expect("Hex entity tag is always valid")
+    }
+
+    fn evaluate_kind(
+        headers: &HeaderMap,
+        metadata: &FileMetadata,
+        etag: &ETag,
+        last_modified: &LastModified,
+    ) -> FileRequestKind {
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            if !if_none_match.precondition_passes(etag) {
+                return FileRequestKind::NotModified;
+            }
+        } else if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
+            if !if_modified_since.is_modified(metadata.modified) {
+                return FileRequestKind::NotModified;
+            }
+        }
+
+        let Some(range) = headers.typed_get::<axum_extra::headers::Range>() else {
+            return FileRequestKind::Full;
+        };
+        if let Some(if_range) = headers.typed_get::<IfRange>() {
+            if if_range.is_modified(Some(etag), Some(last_modified)) {
+                return FileRequestKind::Full;
+            }
+        }
+        let len = metadata.size;
+        let mut ranges = range.satisfiable_ranges(len).map(|(start, end)| {
+            let start = match start {
+                Bound::Included(start) => start,
+                Bound::Excluded(start) => start.saturating_add(1),
+                Bound::Unbounded => 0,
+            };
+            let end = match end {
+                Bound::Included(end) => end.saturating_add(1).min(len),
+                Bound::Excluded(end) => end.min(len),
+                Bound::Unbounded => len,
+            };
+            start..end
+        });
+        match (ranges.next(), ranges.next()) {
+            (Some(range), None) if range.start < range.end => FileRequestKind::Partial(range),
+            (Some(_), None) => FileRequestKind::Unsatisfiable,
+            // Multipart ranges are not supported, the whole file is sent instead
+            _ => FileRequestKind::Full,
+        }
+    }
+
+    /// Byte range of the file to read, if any
+    pub fn range(&self) -> Option<Range<u64>> {
+        match &self.kind {
+            FileRequestKind::Full => Some(0..self.metadata.size),
+            FileRequestKind::Partial(range) => Some(range.clone()),
+            FileRequestKind::NotModified | FileRequestKind::Unsatisfiable => None,
+        }
+    }
+
+    /// Read the requested range from storage and build the response
+    pub async fn respond(self, storage: &FileStorage, path: &str) -> FsResult<Response> {
+        let body = match self.range() {
+            Some(range) => Body::from_stream(ReaderStream::new(storage.read(path, range).await?)),
+            None => Body::empty(),
+        };
+        Ok(self.into_response_with(body))
+    }
+
+    fn into_response_with(self, body: Body) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(self.etag.clone());
+        headers.typed_insert(LastModified::from(self.metadata.modified));
+        headers.typed_insert(AcceptRanges::bytes());
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        let status = match self.kind {
+            FileRequestKind::NotModified => StatusCode::NOT_MODIFIED,
+            FileRequestKind::Unsatisfiable => {
+                headers.typed_insert(ContentRange::unsatisfied_bytes(self.metadata.size));
+                StatusCode::RANGE_NOT_SATISFIABLE
+            }
+            FileRequestKind::Full => {
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(self.content_type),
+                );
+                headers.typed_insert(ContentLength(self.metadata.size));
+                StatusCode::OK
+            }
+            FileRequestKind::Partial(range) => {
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(self.content_type),
+                );
+                headers.typed_insert(ContentLength(range.end - range.start));
+                headers.typed_insert(
+                    ContentRange::bytes(range, self.metadata.size)
+                        .expect("Range is within file size"),
+                );
+                StatusCode::PARTIAL_CONTENT
+            }
+        };
+        (status, headers, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use crate::services::file_storage::FileMetadata;
+
+    use super::FileRequest;
+
+    fn evaluate(headers: &[(header::HeaderName, &'static str)]) -> FileRequest {
+        let headers: HeaderMap = headers
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect();
+        let metadata = FileMetadata {
+            size: 100,
+            modified: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        };
+        FileRequest::evaluate(&headers, "audio/wav", metadata, None)
+    }
+
+    #[test]
+    fn full() {
+        let request = evaluate(&[]);
+
+        assert_eq!(request.range(), Some(0..100));
+    }
+
+    #[test]
+    fn partial() {
+        let request = evaluate(&[(header::RANGE, "bytes=10-19")]);
+
+        assert_eq!(request.range(), Some(10..20));
+    }
+
+    #[test]
+    fn suffix() {
+        let request = evaluate(&[(header::RANGE, "bytes=-30")]);
+
+        assert_eq!(request.range(), Some(70..100));
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        let request = evaluate(&[(header::RANGE, "bytes=200-")]);
+
+        assert_eq!(request.range(), None);
+    }
+
+    #[test]
+    fn not_modified() {
+        let request = evaluate(&[(header::IF_NONE_MATCH, "*")]);
+
+        assert_eq!(request.range(), None);
+    }
+
+    #[test]
+    fn if_range_mismatch() {
+        let request = evaluate(&[
+            (header::RANGE, "bytes=10-19"),
+            (header::IF_RANGE, "\"outdated\""),
+        ]);
+
+        assert_eq!(request.range(), Some(0..100));
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    io::{ErrorKind, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use axum::async_trait;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::{FileMetadata, FileReader, FsError, FsResult, StorageBackend};
+
+/// Subfolder for files moved out of the way
+const QUARANTINE_FOLDER: &str = ".quarantine";
+
+/// Files kept in a folder on the local disk
+#[derive(Debug)]
+pub struct LocalStorage {
+    folder: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(folder: PathBuf) -> Self {
+        Self { folder }
+    }
+
+    fn preprocess_path(&self, name: &str) -> FsResult<PathBuf> {
+        let name = Path::new(name)
+            .components()
+            .next_back()
+            .ok_or(FsError::new(ErrorKind::NotFound, "Not Found"))?;
+        Ok(self.folder.join(name))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn store(&self, staged: &Path, name: &str) -> FsResult<()> {
+        let path = self.preprocess_path(name)?;
+        tokio::fs::rename(staged, path).await?;
+        Ok(())
+    }
+
+    async fn metadata(&self, name: &str) -> FsResult<FileMetadata> {
+        let path = self.preprocess_path(name)?;
+        let metadata = tokio::fs::metadata(path).await?;
+        Ok(FileMetadata {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    async fn read(&self, name: &str, range: Range<u64>) -> FsResult<FileReader> {
+        let path = self.preprocess_path(name)?;
+        let mut file = tokio::fs::OpenOptions::new().read(true).open(path).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        Ok(Box::pin(file.take(range.end.saturating_sub(range.start))))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let from = self.preprocess_path(from)?;
+        let to = self.preprocess_path(to)?;
+        tokio::fs::rename(from, to).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> FsResult<()> {
+        let path = self.preprocess_path(name)?;
+        tokio::fs::remove_file(path).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> FsResult<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(&self.folder).await?;
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            // Staging and quarantine folders are skipped
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Ok(name) = entry.file_name().into_string() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    async fn quarantine(&self, name: &str) -> FsResult<()> {
+        let path = self.preprocess_path(name)?;
+        let quarantine = self.folder.join(QUARANTINE_FOLDER);
+        tokio::fs::create_dir_all(&quarantine).await?;
+        let file_name = path
+            .file_name()
+            .ok_or(FsError::new(ErrorKind::NotFound, "Not Found"))?;
+        tokio::fs::rename(&path, quarantine.join(file_name)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    collections::HashMap, io::ErrorKind, ops::Range, path::Path, sync::RwLock, time::SystemTime,
+};
+
+use axum::async_trait;
+use bytes::Bytes;
+
+use super::{FileMetadata, FileReader, FsError, FsResult, StorageBackend};
+
+/// Files kept in memory, lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    files: RwLock<HashMap<String, MemoryFile>>,
+}
+
+#[derive(Debug, Clone)]
+struct MemoryFile {
+    data: Bytes,
+    modified: SystemTime,
+}
+
+/// Prefix of quarantined file names
+const QUARANTINE_PREFIX: &str = "quarantine/";
+
+fn not_found() -> FsError {
+    FsError::new(ErrorKind::NotFound, "Not Found")
+}
+
+impl MemoryStorage {
+    fn file(&self, name: &str) -> FsResult<MemoryFile> {
+        self.files
+            .read()
+            .expect("Memory storage lock poisoned")
+            .get(name)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    async fn store(&self, staged: &Path, name: &str) -> FsResult<()> {
+        let data = Bytes::from(tokio::fs::read(staged).await?);
+        let file = MemoryFile {
+            data,
+            modified: SystemTime::now(),
+        };
+        self.files
+            .write()
+            .expect("Memory storage lock poisoned")
+            .insert(name.to_owned(), file);
+        Ok(())
+    }
+
+    async fn metadata(&self, name: &str) -> FsResult<FileMetadata> {
+        let file = self.file(name)?;
+        Ok(FileMetadata {
+            size: file.data.len() as u64,
+            modified: file.modified,
+        })
+    }
+
+    async fn read(&self, name: &str, range: Range<u64>) -> FsResult<FileReader> {
+        let data = self.file(name)?.data;
+        let end = (range.end as usize).min(data.len());
+        let start = (range.start as usize).min(end);
+        Ok(Box::pin(std::io::Cursor::new(data.slice(start..end))))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let mut files = self.files.write().expect("Memory storage lock poisoned");
+        let file = files.remove(from).ok_or_else(not_found)?;
+        files.insert(to.to_owned(), file);
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> FsResult<()> {
+        self.files
+            .write()
+            .expect("Memory storage lock poisoned")
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(not_found)
+    }
+
+    async fn list(&self) -> FsResult<Vec<String>> {
+        let files = self.files.read().expect("Memory storage lock poisoned");
+        Ok(files
+            .keys()
+            .filter(|name| !name.starts_with(QUARANTINE_PREFIX))
+            .cloned()
+            .collect())
+    }
+
+    async fn quarantine(&self, name: &str) -> FsResult<()> {
+        self.rename(name, &format!("{QUARANTINE_PREFIX}{name}"))
+            .await
+    }
+}
@@ -0,0 +1,364 @@
+//! File storage with pluggable backends
+
+mod local;
+mod memory;
+mod s3;
+
+use std::{
+    fmt::Debug,
+    io::ErrorKind,
+    ops::Range,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use axum::async_trait;
+use serde::Deserialize;
+use sha3::{Digest, Sha3_256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tracing::warn;
+use uuid::Uuid;
+
+pub use local::LocalStorage;
+pub use memory::MemoryStorage;
+pub use s3::{S3Storage, S3StorageConfig};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileStorageConfig {
+    /// Local folder for uploads in progress, also holds the stored files with the local backend
+    pub folder: PathBuf,
+    #[serde(default)]
+    pub backend: StorageBackendKind,
+    /// Required by the S3 backend
+    pub s3: Option<S3StorageConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackendKind {
+    #[default]
+    Local,
+    Memory,
+    S3,
+}
+
+/// Place where committed files are kept
+///
+/// Files are addressed by flat names, without any folder structure.
+#[async_trait]
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Move a fully written local file into storage, replacing the target if it exists
+    async fn store(&self, staged: &Path, name: &str) -> FsResult<()>;
+
+    /// Get file size and modification time
+    async fn metadata(&self, name: &str) -> FsResult<FileMetadata>;
+
+    /// Stream a byte range of a file
+    async fn read(&self, name: &str, range: Range<u64>) -> FsResult<FileReader>;
+
+    /// Rename a file, replacing the target if it exists
+    async fn rename(&self, from: &str, to: &str) -> FsResult<()>;
+
+    /// Delete a file
+    async fn delete(&self, name: &str) -> FsResult<()>;
+
+    /// Names of all stored files, without quarantined ones
+    async fn list(&self) -> FsResult<Vec<String>>;
+
+    /// Move a file out of the way, keeping it for manual inspection
+    async fn quarantine(&self, name: &str) -> FsResult<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    backend: Arc<dyn StorageBackend>,
+    staging: PathBuf,
+}
+
+/// Subfolder for files that are still being written
+const STAGING_FOLDER: &str = ".staging";
+
+/// Suffix of files derived from a stored file, which can be rebuilt at any time
+const CACHE_SUFFIX: &str = ".cache";
+
+/// Name of a file derived from a stored file, `key` must not contain dots
+///
+/// The name starts with the name of its source, so stale files can be told apart once the source is removed.
+pub fn cache_name(source: &str, key: &str) -> String {
+    format!("{source}.{key}{CACHE_SUFFIX}")
+}
+
+/// Cached file in storage
+pub struct CachedFile {
+    pub name: String,
+    pub metadata: FileMetadata,
+}
+
+/// Name of the file a cached file was derived from, missing for other files
+pub fn cache_source(name: &str) -> Option<&str> {
+    name.strip_suffix(CACHE_SUFFIX)?.split('.').next()
+}
+
+impl FileStorage {
+    pub async fn setup(config: &FileStorageConfig) -> FsResult<Self> {
+        tokio::fs::create_dir_all(&config.folder).await?;
+        let folder = tokio::fs::canonicalize(&config.folder).await?;
+        let staging = folder.join(STAGING_FOLDER);
+        tokio::fs::create_dir_all(&staging).await?;
+        let backend: Arc<dyn StorageBackend> = match config.backend {
+            StorageBackendKind::Local => Arc::new(LocalStorage::new(folder)),
+            StorageBackendKind::Memory => Arc::new(MemoryStorage::default()),
+            StorageBackendKind::S3 => {
+                let s3 = config.s3.as_ref().ok_or(FsError::new(
+                    ErrorKind::InvalidInput,
+                    "Missing S3 configuration",
+                ))?;
+                Arc::new(S3Storage::new(s3)?)
+            }
+        };
+        Ok(Self { backend, staging })
+    }
+
+    /// Start writing a new file
+    ///
+    /// Data is written to a temporary local file, which is moved into storage by [`StagedFile::commit`].
+    pub async fn stage(&self) -> FsResult<StagedFile> {
+        let temp_path = self.staging.join(Uuid::new_v4().to_string());
+        let file = tokio::fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&temp_path)
+            .await?;
+        Ok(StagedFile {
+            storage: self.clone(),
+            file,
+            temp_path,
+            hasher: Sha3_256::new(),
+            size: 0,
+        })
+    }
+
+    /// Get file size and modification time
+    pub async fn metadata(&self, name: &str) -> FsResult<FileMetadata> {
+        self.backend.metadata(name).await
+    }
+
+    /// Stream a byte range of a file
+    pub async fn read(&self, name: &str, range: Range<u64>) -> FsResult<FileReader> {
+        self.backend.read(name, range).await
+    }
+
+    /// Rename a file, replacing the target if it exists
+    pub async fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        self.backend.rename(from, to).await
+    }
+
+    /// Delete a file
+    pub async fn delete(&self, name: &str) -> FsResult<()> {
+        self.backend.delete(name).await
+    }
+
+    /// Names of all stored files, without quarantined ones
+    pub async fn list(&self) -> FsResult<Vec<String>> {
+        self.backend.list().await
+    }
+
+    /// Move a file out of the way, keeping it for manual inspection
+    pub async fn quarantine(&self, name: &str) -> FsResult<()> {
+        self.backend.quarantine(name).await
+    }
+
+    /// Stage a file from data already in memory
+    pub async fn stage_bytes(&self, data: bytes::Bytes) -> FsResult<StagedFile> {
+        let mut staged = self.stage().await?;
+        staged.write(&data).await?;
+        Ok(staged)
+    }
+
+    /// Get a whole file, only meant for small files
+    pub async fn get(&self, name: &str) -> FsResult<bytes::Bytes> {
+        let size = self.metadata(name).await?.size;
+        let mut reader = self.read(name, 0..size).await?;
+        let mut buf = bytes::BytesMut::new();
+        while reader.read_buf(&mut buf).await? != 0 {}
+        Ok(buf.freeze())
+    }
+
+    /// Get a cached file, missing if it was not stored yet
+    pub async fn cached(&self, name: &str) -> FsResult<Option<CachedFile>> {
+        match self.metadata(name).await {
+            Ok(metadata) => Ok(Some(CachedFile {
+                name: name.to_owned(),
+                metadata,
+            })),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store a cached file from data in memory, replacing it if it exists
+    ///
+    /// Cached files with the same name are expected to have the same content, so concurrent writers do not conflict.
+    pub async fn store_cached(&self, name: String, data: bytes::Bytes) -> FsResult<CachedFile> {
+        self.stage_bytes(data).await?.commit(&name).await?;
+        let metadata = self.metadata(&name).await?;
+        Ok(CachedFile { name, metadata })
+    }
+
+    /// Hex encoded SHA3-256 of a file
+    pub async fn hash(&self, name: &str) -> FsResult<String> {
+        let size = self.metadata(name).await?.size;
+        let mut reader = self.read(name, 0..size).await?;
+        let mut hasher = Sha3_256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+/// File being written into storage
+///
+/// The temporary file is removed on drop, whether it was committed or not.
+pub struct StagedFile {
+    storage: FileStorage,
+    file: tokio::fs::File,
+    temp_path: PathBuf,
+    hasher: Sha3_256,
+    size: u64,
+}
+
+impl StagedFile {
+    /// Append a chunk of data
+    pub async fn write(&mut self, chunk: &[u8]) -> FsResult<()> {
+        self.file.write_all(chunk).await?;
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Number of bytes written so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hex encoded SHA3-256 of the data written so far
+    pub fn hash(&self) -> String {
+        hex::encode(self.hasher.clone().finalize())
+    }
+
+    /// Flush written data and open it for reading
+    pub async fn open_read(&mut self) -> FsResult<std::fs::File> {
+        self.file.flush().await?;
+        std::fs::File::open(&self.temp_path)
+    }
+
+    /// Flush written data and get a handle for synchronous writers
+    ///
+    /// Data written through the handle is not counted in [`StagedFile::size`] and [`StagedFile::hash`].
+    pub async fn open_write(&mut self) -> FsResult<std::fs::File> {
+        self.file.flush().await?;
+        Ok(self.file.try_clone().await?.into_std().await)
+    }
+
+    /// Move the file into storage
+    pub async fn commit(mut self, name: &str) -> FsResult<()> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        self.storage.backend.store(&self.temp_path, name).await
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.temp_path) {
+            // The local backend moves the file instead of copying it
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!({error = ?e}, "Failed to remove staged file"),
+            Ok(()) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FileMetadata {
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+pub type FileReader = Pin<Box<dyn AsyncRead + Send>>;
+
+pub type FsError = std::io::Error;
+pub type FsResult<T> = Result<T, FsError>;
+
+#[cfg(test)]
+pub mod tests {
+    use std::{io::ErrorKind, path::PathBuf};
+
+    use tokio::io::AsyncReadExt;
+    use uuid::Uuid;
+
+    use super::{FileStorage, FileStorageConfig, StorageBackendKind};
+
+    /// Fresh in-memory storage
+    pub async fn memory_storage() -> FileStorage {
+        let config = FileStorageConfig {
+            folder: PathBuf::from("./tmp/file_storage"),
+            backend: StorageBackendKind::Memory,
+            s3: None,
+        };
+        FileStorage::setup(&config).await.unwrap()
+    }
+
+    /// Exercise every backend operation
+    pub async fn roundtrip(storage: FileStorage) {
+        let name = Uuid::new_v4().to_string();
+        let renamed = Uuid::new_v4().to_string();
+        let data = bytes::Bytes::from_static(b"0123456789");
+
+        let staged = storage.stage_bytes(data.clone()).await.unwrap();
+        assert_eq!(staged.size(), 10);
+        staged.commit(&name).await.unwrap();
+        assert_eq!(storage.get(&name).await.unwrap(), data);
+        assert_eq!(storage.metadata(&name).await.unwrap().size, 10);
+        let mut part = String::new();
+        storage
+            .read(&name, 2..5)
+            .await
+            .unwrap()
+            .read_to_string(&mut part)
+            .await
+            .unwrap();
+        assert_eq!(part, "234");
+        storage.rename(&name, &renamed).await.unwrap();
+        let error = storage.metadata(&name).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(storage.list().await.unwrap().contains(&renamed));
+        storage.quarantine(&renamed).await.unwrap();
+        assert!(!storage.list().await.unwrap().contains(&renamed));
+        let error = storage.delete(&renamed).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn local() {
+        let config = FileStorageConfig {
+            folder: PathBuf::from("./tmp/file_storage").join(Uuid::new_v4().to_string()),
+            backend: StorageBackendKind::Local,
+            s3: None,
+        };
+        roundtrip(FileStorage::setup(&config).await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn memory() {
+        roundtrip(memory_storage().await).await;
+    }
+}
@@ -0,0 +1,185 @@
+use std::{ops::Range, path::Path, time::SystemTime};
+
+use axum::async_trait;
+use object_store::{
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path as ObjectPath,
+    GetOptions, GetRange, ObjectStore, PutPayload, WriteMultipart,
+};
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::StreamReader;
+
+use super::{FileMetadata, FileReader, FsResult, StorageBackend};
+
+/// Files up to this size are uploaded in a single request
+const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+const PART_SIZE: usize = 8 * 1024 * 1024;
+const MAX_CONCURRENT_PARTS: usize = 4;
+/// Quarantined files are kept under this prefix, outside of the listed top level
+const QUARANTINE_PREFIX: &str = "quarantine";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3StorageConfig {
+    pub bucket: String,
+    pub region: Option<String>,
+    /// Custom endpoint for S3-compatible services such as MinIO
+    pub endpoint: Option<String>,
+    /// Taken from the `AWS_*` environment variables when missing
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+/// Files kept in an S3-compatible bucket
+#[derive(Debug)]
+pub struct S3Storage {
+    store: AmazonS3,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3StorageConfig) -> FsResult<Self> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&config.bucket)
+            .with_allow_http(config.allow_http);
+        if let Some(region) = &config.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.with_endpoint(endpoint);
+        }
+        if let Some(access_key_id) = &config.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = &config.secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+        let store = builder.build()?;
+        Ok(Self { store })
+    }
+
+    async fn upload_multipart(&self, staged: &Path, location: &ObjectPath) -> FsResult<()> {
+        let mut file = tokio::fs::File::open(staged).await?;
+        let upload = self.store.put_multipart(location).await?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+        let mut buf = vec![0; PART_SIZE];
+        loop {
+            let read = match file.read(&mut buf).await {
+                Ok(read) => read,
+                Err(e) => {
+                    writer.abort().await.ok();
+                    return Err(e);
+                }
+            };
+            if read == 0 {
+                break;
+            }
+            if let Err(e) = writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await {
+                writer.abort().await.ok();
+                return Err(e.into());
+            }
+            writer.write(&buf[..read]);
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn store(&self, staged: &Path, name: &str) -> FsResult<()> {
+        let location = ObjectPath::from(name);
+        let size = tokio::fs::metadata(staged).await?.len();
+        if size > MULTIPART_THRESHOLD {
+            return self.upload_multipart(staged, &location).await;
+        }
+        let data = tokio::fs::read(staged).await?;
+        self.store.put(&location, PutPayload::from(data)).await?;
+        Ok(())
+    }
+
+    async fn metadata(&self, name: &str) -> FsResult<FileMetadata> {
+        let meta = self.store.head(&ObjectPath::from(name)).await?;
+        Ok(FileMetadata {
+            size: meta.size as u64,
+            modified: SystemTime::from(meta.last_modified),
+        })
+    }
+
+    async fn read(&self, name: &str, range: Range<u64>) -> FsResult<FileReader> {
+        // Zero length ranges are rejected by S3
+        if range.start >= range.end {
+            return Ok(Box::pin(tokio::io::empty()));
+        }
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(range.start as usize..range.end as usize)),
+            ..Default::default()
+        };
+        let result = self
+            .store
+            .get_opts(&ObjectPath::from(name), options)
+            .await?;
+        Ok(Box::pin(StreamReader::new(result.into_stream())))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        self.store
+            .rename(&ObjectPath::from(from), &ObjectPath::from(to))
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> FsResult<()> {
+        // S3 deletes succeed for missing objects, check first to behave like the other backends
+        self.metadata(name).await?;
+        self.store.delete(&ObjectPath::from(name)).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> FsResult<Vec<String>> {
+        let listing = self.store.list_with_delimiter(None).await?;
+        Ok(listing
+            .objects
+            .into_iter()
+            .map(|object| object.location.to_string())
+            .collect())
+    }
+
+    async fn quarantine(&self, name: &str) -> FsResult<()> {
+        let to = ObjectPath::from(QUARANTINE_PREFIX).child(name);
+        self.store.rename(&ObjectPath::from(name), &to).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::services::file_storage::{
+        tests::roundtrip, FileStorage, FileStorageConfig, S3StorageConfig, StorageBackendKind,
+    };
+
+    /// Bucket created by the MinIO service in `docker-compose.yml` next to this module
+    fn minio_config() -> FileStorageConfig {
+        FileStorageConfig {
+            folder: PathBuf::from("./tmp/file_storage"),
+            backend: StorageBackendKind::S3,
+            s3: Some(S3StorageConfig {
+                bucket: "samples".to_owned(),
+                region: Some("us-east-1".to_owned()),
+                endpoint: Some("http://127.0.0.1:9000".to_owned()),
+                access_key_id: Some("minioadmin".to_owned()),
+                secret_access_key: Some("minioadmin".to_owned()),
+                allow_http: true,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running MinIO instance"]
+    async fn minio() {
+        roundtrip(FileStorage::setup(&minio_config()).await.unwrap()).await;
+    }
+}
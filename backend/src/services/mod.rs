@@ -1,7 +1,10 @@
 pub mod app;
+pub mod audio;
 pub mod auth;
+pub mod cli;
 pub mod config;
 pub mod database;
+pub mod file_response;
 pub mod file_storage;
 pub mod repositories;
 pub mod runner;
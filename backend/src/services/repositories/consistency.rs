@@ -0,0 +1,392 @@
+//! Consistency between sample rows and stored files
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::ErrorKind,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::services::file_storage::cache_source;
+
+use super::{sample::SampleRepository, RepoResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConsistencyConfig {
+    /// Check consistency before the server starts
+    pub check_at_startup: bool,
+    pub verify_hashes: bool,
+    pub repair: bool,
+}
+
+impl ConsistencyConfig {
+    pub fn options(&self) -> CheckOptions {
+        CheckOptions {
+            verify_hashes: self.verify_hashes,
+            repair: self.repair,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CheckOptions {
+    /// Read every file to compare its content with the hash it is stored under
+    pub verify_hashes: bool,
+    /// Quarantine orphaned files, remove stale cached files and finish pending file removals
+    pub repair: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsistencyReport {
+    pub missing_files: Vec<MissingFile>,
+    pub orphaned_files: Vec<String>,
+    pub size_mismatches: Vec<SizeMismatch>,
+    pub hash_mismatches: Vec<HashMismatch>,
+    /// Orphaned files moved to quarantine in repair mode
+    pub quarantined: Vec<String>,
+    /// Cached files derived from files which no longer exist, removed in repair mode
+    pub stale_cache_files: Vec<String>,
+}
+
+impl ConsistencyReport {
+    /// No problems were found, or all of them were repaired
+    pub fn is_consistent(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.size_mismatches.is_empty()
+            && self.hash_mismatches.is_empty()
+            && self.orphaned_files.len() == self.quarantined.len()
+    }
+}
+
+/// Samples or HRTF sets whose file does not exist
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingFile {
+    /// Missing for samples that were never assigned a blob
+    pub blob: Option<String>,
+    /// Sample identifiers, HRTF set identifiers are prefixed with `hrtf:`
+    pub samples: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeMismatch {
+    pub blob: String,
+    /// Missing if there is no blob row
+    pub expected: Option<u64>,
+    pub actual: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashMismatch {
+    pub blob: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SampleBlob {
+    id: String,
+    hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlobSize {
+    hash: String,
+    size: u64,
+}
+
+impl SampleRepository {
+    /// Compare sample and blob rows with the files in storage
+    ///
+    /// Files are listed before the database is queried, so files of samples created meanwhile are not reported as orphans.
+    pub async fn check_consistency(&self, options: CheckOptions) -> RepoResult<ConsistencyReport> {
+        if options.repair {
+            self.remove_released_files().await?;
+        }
+        let files = self.file_storage.list().await?;
+        let mut result = self
+            .database
+            .query("select record::id(id) as id, hash from sample")
+            .query("select record::id(id) as hash, size from blob")
+            .query("select value record::id(id) from tombstone")
+            .query("select 'hrtf:' + record::id(id) as id, hash from hrtf")
+            .await?;
+        let mut samples = result.take::<Vec<SampleBlob>>(0)?;
+        let blobs = result
+            .take::<Vec<BlobSize>>(1)?
+            .into_iter()
+            .map(|blob| (blob.hash, blob.size))
+            .collect::<HashMap<_, _>>();
+        let tombstones = result.take::<Vec<String>>(2)?;
+        samples.extend(result.take::<Vec<SampleBlob>>(3)?);
+
+        let mut report = ConsistencyReport::default();
+        let mut samples_by_blob = BTreeMap::<Option<String>, Vec<String>>::new();
+        for sample in samples {
+            samples_by_blob
+                .entry(sample.hash)
+                .or_default()
+                .push(sample.id);
+        }
+        for (blob, samples) in samples_by_blob.iter() {
+            let Some(blob) = blob else {
+                report.missing_files.push(MissingFile {
+                    blob: None,
+                    samples: samples.clone(),
+                });
+                continue;
+            };
+            let metadata = match self.file_storage.metadata(blob).await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    report.missing_files.push(MissingFile {
+                        blob: Some(blob.clone()),
+                        samples: samples.clone(),
+                    });
+                    continue;
+                }
+                Err(e) => Err(e)?,
+            };
+            let expected = blobs.get(blob).copied();
+            if expected != Some(metadata.size) {
+                report.size_mismatches.push(SizeMismatch {
+                    blob: blob.clone(),
+                    expected,
+                    actual: metadata.size,
+                });
+            }
+            if options.verify_hashes {
+                let actual = self.file_storage.hash(blob).await?;
+                if &actual != blob {
+                    report.hash_mismatches.push(HashMismatch {
+                        blob: blob.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+
+        // Files of deleted samples wait for removal under their own name or moved aside
+        let pending = tombstones
+            .iter()
+            .flat_map(|hash| [hash.clone(), format!("{hash}.removed")])
+            .collect::<HashSet<_>>();
+        let is_stored = |file: &str| samples_by_blob.contains_key(&Some(file.to_owned()));
+        let (cached, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|file| cache_source(file).is_some());
+        report.stale_cache_files = cached
+            .into_iter()
+            .filter(|file| !cache_source(file).is_some_and(is_stored))
+            .collect();
+        report.stale_cache_files.sort();
+        report.orphaned_files = files
+            .into_iter()
+            .filter(|file| !is_stored(file))
+            .filter(|file| !pending.contains(file))
+            .collect();
+        report.orphaned_files.sort();
+
+        if options.repair {
+            for file in report.stale_cache_files.iter() {
+                match self.file_storage.delete(file).await {
+                    Ok(()) => info!("Removed stale cached file `{}`", file),
+                    Err(e) if e.kind() == ErrorKind::NotFound => warn!("{}", e),
+                    Err(e) => Err(e)?,
+                }
+            }
+            for file in report.orphaned_files.iter() {
+                match self.file_storage.quarantine(file).await {
+                    Ok(()) => {
+                        info!("Quarantined orphaned file `{}`", file);
+                        report.quarantined.push(file.clone());
+                    }
+                    // Removed by a concurrent deletion after being listed
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        warn!("{}", e);
+                        report.quarantined.push(file.clone());
+                    }
+                    Err(e) => Err(e)?,
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{
+        audio::tests::wav_bytes,
+        database::surreal::tests::surreal_in_memory,
+        file_storage::{cache_name, tests::memory_storage},
+        repositories::{
+            hrtf::tests::create_hrtf,
+            sample::{tests::create_sample, SampleInfo, SampleRepository},
+        },
+    };
+
+    use super::CheckOptions;
+
+    async fn setup() -> SampleRepository {
+        SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        }
+    }
+
+    async fn create(sut: &SampleRepository, name: &str, samples: &[i16]) -> (String, String) {
+        let info = SampleInfo {
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        let sample = create_sample(sut, info, wav_bytes(samples)).await.unwrap();
+        (sample.id.clone(), sample.data.hash.unwrap())
+    }
+
+    #[tokio::test]
+    async fn consistent() {
+        let sut = setup().await;
+        create(&sut, "consistent.wav", &[7, 6, 5, 4]).await;
+        create_hrtf(&sut.database, &sut.file_storage, "kemar").await;
+        let options = CheckOptions {
+            verify_hashes: true,
+            repair: false,
+        };
+
+        let report = sut.check_consistency(options).await.unwrap();
+
+        assert!(report.is_consistent());
+        assert!(report.orphaned_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_file() {
+        let sut = setup().await;
+        let (id, hash) = create(&sut, "missing.wav", &[7, 6, 5, 4]).await;
+        sut.file_storage.delete(&hash).await.unwrap();
+
+        let report = sut.check_consistency(Default::default()).await.unwrap();
+
+        assert!(!report.is_consistent());
+        assert_eq!(report.missing_files.len(), 1);
+        assert_eq!(report.missing_files[0].blob, Some(hash));
+        assert_eq!(report.missing_files[0].samples, vec![id]);
+    }
+
+    #[tokio::test]
+    async fn mismatches() {
+        let sut = setup().await;
+        let (_, hash) = create(&sut, "mismatch.wav", &[7, 6, 5, 4]).await;
+        let file = sut
+            .file_storage
+            .stage_bytes(wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+        file.commit(&hash).await.unwrap();
+        let options = CheckOptions {
+            verify_hashes: true,
+            repair: false,
+        };
+
+        let report = sut.check_consistency(options).await.unwrap();
+
+        assert_eq!(report.size_mismatches.len(), 1);
+        assert_eq!(report.size_mismatches[0].actual, 50);
+        assert_eq!(report.hash_mismatches.len(), 1);
+        assert_eq!(report.hash_mismatches[0].blob, hash);
+    }
+
+    #[tokio::test]
+    async fn orphans_quarantined() {
+        let sut = setup().await;
+        let (_, hash) = create(&sut, "kept.wav", &[7, 6, 5, 4]).await;
+        let file = sut
+            .file_storage
+            .stage_bytes(wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+        file.commit("orphan").await.unwrap();
+
+        let report = sut.check_consistency(Default::default()).await.unwrap();
+        assert_eq!(report.orphaned_files, vec!["orphan".to_owned()]);
+        assert!(report.quarantined.is_empty());
+
+        let options = CheckOptions {
+            verify_hashes: false,
+            repair: true,
+        };
+        let report = sut.check_consistency(options).await.unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.quarantined, vec!["orphan".to_owned()]);
+        assert_eq!(sut.file_storage.list().await.unwrap(), vec![hash]);
+    }
+
+    #[tokio::test]
+    async fn check_without_repair_changes_nothing() {
+        let sut = setup().await;
+        for name in ["orphan", "pending", &cache_name("removed", "stale")] {
+            let file = sut
+                .file_storage
+                .stage_bytes(wav_bytes(&[1, 2, 3]))
+                .await
+                .unwrap();
+            file.commit(name).await.unwrap();
+        }
+        sut.database
+            .query("create tombstone:pending set created_at = time::now()")
+            .await
+            .unwrap();
+        let files = sut.file_storage.list().await.unwrap();
+
+        let report = sut.check_consistency(Default::default()).await.unwrap();
+
+        assert_eq!(report.orphaned_files, vec!["orphan".to_owned()]);
+        assert_eq!(sut.file_storage.list().await.unwrap(), files);
+        let mut tombstones = sut
+            .database
+            .query("select value record::id(id) from tombstone")
+            .await
+            .unwrap();
+        assert_eq!(
+            tombstones.take::<Vec<String>>(0).unwrap(),
+            vec!["pending".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_cache_removed() {
+        let sut = setup().await;
+        let (_, hash) = create(&sut, "cached.wav", &[7, 6, 5, 4]).await;
+        let kept = cache_name(&hash, "kept");
+        let stale = cache_name("removed", "stale");
+        for name in [&kept, &stale] {
+            let file = sut
+                .file_storage
+                .stage_bytes(wav_bytes(&[1, 2, 3]))
+                .await
+                .unwrap();
+            file.commit(name).await.unwrap();
+        }
+
+        let report = sut.check_consistency(Default::default()).await.unwrap();
+        assert!(report.is_consistent());
+        assert!(report.orphaned_files.is_empty());
+        assert_eq!(report.stale_cache_files, vec![stale.clone()]);
+
+        let options = CheckOptions {
+            verify_hashes: false,
+            repair: true,
+        };
+        sut.check_consistency(options).await.unwrap();
+        let mut files = sut.file_storage.list().await.unwrap();
+        files.sort();
+        let mut expected = vec![hash, kept];
+        expected.sort();
+        assert_eq!(files, expected);
+    }
+}
@@ -3,9 +3,12 @@ use axum::{
     extract::{FromRef, FromRequestParts},
     http::request::Parts,
 };
+use std::{collections::HashSet, fmt};
+
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
-use serde::{Deserialize, Serialize};
-use validator::Validate;
+use serde::{Deserialize, Deserializer, Serialize};
+use validator::{Validate, ValidationError};
 
 use crate::services::database::{
     error::ValidateDbResponse,
@@ -13,23 +16,59 @@ use crate::services::database::{
     surreal::{Database, MapToNotFound},
 };
 
-use super::RepoResult;
+use super::{
+    experiment_trials::TrialDesign,
+    position::{normalize_azimuth, validate_azimuth, validate_distance, validate_elevation},
+    speaker_layout::{SpeakerLayout, SpeakerLayoutRepository},
+    RepoError, RepoResult,
+};
 
 pub struct ExperimentRepository {
     pub surreal: Database,
 }
 
+/// Throw unless `$experiment` takes results at `$now`, the check of [`Experiment::accepts_results`] inside a transaction
+pub(super) const CHECK_ACCEPTS_RESULTS: &str = r"
+    if $experiment.state is not 'open'
+        or ($experiment.opens_at is not none and <datetime> $now < <datetime> $experiment.opens_at)
+        or ($experiment.closes_at is not none and <datetime> $now >= <datetime> $experiment.closes_at) {
+        throw 'The experiment does not take results';
+    };
+";
+
+/// Store `$new_result` for `$experiment` as `$result`, two statements
+pub(super) const CREATE_RESULT: &str = r"
+    let $result = create only result content { experiment_id: record::id($experiment.id), training: $new_result.training, user: $new_result.user, sequence_id: $new_result.sequence_id };
+    for $sample_result in $new_result.sample_results {
+        let $experiment_sample = select value id from only experiment_sample where in is $experiment.id and record::id(out) is $sample_result.sample_id limit 1;
+        relate ($experiment_sample)->sample_result->($result) content { azimuth: $sample_result.azimuth, elevation: $sample_result.elevation, distance: $sample_result.distance, speaker_id: $sample_result.speaker_id };
+    };
+";
+
 impl ExperimentRepository {
     /// Create a new experiment and return it with an identifier
-    pub async fn create(&self, experiment: Experiment) -> RepoResult<StringIdentified<Experiment>> {
+    ///
+    /// A design without a seed gets a random one.
+    pub async fn create(
+        &self,
+        mut experiment: Experiment,
+    ) -> RepoResult<StringIdentified<Experiment>> {
+        experiment.design.seed = experiment.design.seed.or_else(|| Some(rand::random()));
+        if let Some(layout_id) = experiment.speaker_layout_id.clone() {
+            self.layouts().referenced(layout_id).await?;
+        }
         let mut result = self
             .surreal
             .query("begin")
-            .query("let $exp = create only experiment content { name: $experiment.name, is_public: $experiment.is_public } RETURN AFTER")
+            .query("let $exp = create only experiment content { name: $experiment.name, is_public: $experiment.is_public, speaker_layout_id: $experiment.speaker_layout_id, state: $experiment.state, opens_at: $experiment.opens_at, closes_at: $experiment.closes_at, design: $experiment.design } RETURN AFTER")
             .query(
                 r"
                 for $sample_id in $experiment.sample_ids {
-                    let $sample = select value id from only sample where record::id(id) is $sample_id limit 1;
+                    -- Writing to the sample makes a concurrent deletion of it conflict with this transaction
+                    let $sample = (update type::thing('sample', $sample_id) set used_at = time::now() return value id)[0];
+                    if $sample is none {
+                        throw 'Sample ' + $sample_id + ' does not exist';
+                    };
                     relate ($exp)->experiment_sample->($sample);
                 }
                 ",
@@ -60,11 +99,11 @@ impl ExperimentRepository {
         Ok(experiment)
     }
 
-    /// Return existing public experiments
+    /// Return existing public experiments, drafts and archived experiments are left out
     pub async fn public_infos(&self) -> RepoResult<Vec<StringIdentified<Experiment>>> {
         let mut result = self
             .surreal
-            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from experiment where is_public is true")
+            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from experiment where is_public is true and state notinside ['draft', 'archived']")
             .await?;
         let experiments = result
             .take::<Vec<Identified<Experiment>>>(0)?
@@ -72,11 +111,15 @@ impl ExperimentRepository {
         Ok(experiments)
     }
 
-    /// Return existing experiments
-    pub async fn infos(&self) -> RepoResult<Vec<StringIdentified<Experiment>>> {
+    /// Return existing experiments, archived ones only if asked for
+    pub async fn infos(
+        &self,
+        include_archived: bool,
+    ) -> RepoResult<Vec<StringIdentified<Experiment>>> {
         let mut result = self
             .surreal
-            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from experiment")
+            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from experiment where $include_archived or state is not 'archived'")
+            .bind(("include_archived", include_archived))
             .await?;
         let experiments = result
             .take::<Vec<Identified<Experiment>>>(0)?
@@ -84,37 +127,220 @@ impl ExperimentRepository {
         Ok(experiments)
     }
 
-    /// Return all results for an experiment
+    /// Create a result for an experiment
+    ///
+    /// Only open experiments within their schedule take results, others are reported as [`RepoError::Closed`].
+    /// Experiments using a speaker layout take a speaker for every sample, its position is stored along with it.
     pub async fn create_result(
         &self,
         experiment_id: String,
-        result: ExperimentResult,
+        mut result: ExperimentResult,
     ) -> RepoResult<StringIdentified<ExperimentResult>> {
+        let experiment = self.info(experiment_id.clone()).await?;
+        if let Some(sequence_id) = result.sequence_id.clone() {
+            self.check_trials(&experiment_id, sequence_id).await?;
+        }
+        let layout = self.layout(&experiment).await?;
+        for sample_result in result.sample_results.iter_mut() {
+            sample_result.place(layout.as_ref())?;
+        }
+        let now = Utc::now();
+        let mut result = self
+            .surreal
+            .query("begin")
+            .query("let $experiment = select *, (select value record::id(out) from ->experiment_sample) as sample_ids from only experiment where record::id(id) is $experiment_id limit 1")
+            .query(CHECK_ACCEPTS_RESULTS)
+            .query(CREATE_RESULT)
+            .query("commit")
+            .query("return $experiment")
+            .query("select *, (select record::id(in.out) as sample_id, azimuth, elevation, distance, speaker_id from <-sample_result) as sample_results from only result where id is $result.id limit 1")
+            .bind(("experiment_id", experiment_id))
+            .bind(("now", now))
+            .bind(("new_result", result))
+            .await?;
+        result
+            .take::<Option<Identified<Experiment>>>(4)?
+            .found()?
+            .data
+            .accepts_results(now)?;
+        let result = result
+            .validate()?
+            .take::<Option<Identified<ExperimentResult>>>(5)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(result)
+    }
+
+    /// Change name, visibility, schedule, trial design or speaker layout of an experiment
+    ///
+    /// A new design without a seed keeps the current one, sequences drawn before keep their trials.
+    /// Changing the layout of an experiment with results is refused unless forced or a draft, the results keep their speakers then.
+    pub async fn update_info(
+        &self,
+        experiment_id: String,
+        update: ExperimentUpdate,
+        force: bool,
+    ) -> RepoResult<ExperimentChange> {
+        if let Some(Some(layout_id)) = update.speaker_layout_id.clone() {
+            self.layouts().referenced(layout_id).await?;
+        }
         let mut result = self
             .surreal
             .query("begin")
-            .query("let $result = create only result content { experiment_id: $experiment_id, training: $training, user: $user }")
+            .query("let $experiment = select * from only experiment where record::id(id) is $experiment_id limit 1")
             .query(
                 r"
-                for $sample_result in $sample_results {
-                    let $experiment_sample = select value id from only experiment_sample where record::id(in) is $experiment_id and record::id(out) is $sample_result.sample_id limit 1;
-                    relate ($experiment_sample)->sample_result->($result) content { azimuth: $sample_result.azimuth , elevation: $sample_result.elevation };
-                }
+                let $schedule = {
+                    opens_at: if $opens_at_set { $opens_at } else { $experiment.opens_at },
+                    closes_at: if $closes_at_set { $closes_at } else { $experiment.closes_at },
+                };
+                ",
+            )
+            .query("let $invalid_schedule = $schedule.opens_at is not none and $schedule.closes_at is not none and <datetime> $schedule.opens_at >= <datetime> $schedule.closes_at")
+            .query("let $layout_changed = $layout_set and $layout_id is not $experiment.speaker_layout_id")
+            .query("let $samples = if $layout_changed and !$force and $experiment.state is not 'draft' { (select out.id as id, out.name as name from experiment_sample where in is $experiment.id and array::len(->sample_result) > 0) } else { [] }")
+            .query("if $experiment is none or $invalid_schedule or array::len($samples) > 0 { throw 'The experiment was not changed' }")
+            .query(
+                r"
+                update $experiment.id set
+                    name = $name ?? name,
+                    is_public = $is_public ?? is_public,
+                    opens_at = $schedule.opens_at,
+                    closes_at = $schedule.closes_at,
+                    design = $design ?? design,
+                    design.seed = $design.seed ?? $experiment.design.seed,
+                    speaker_layout_id = if $layout_changed { $layout_id } else { speaker_layout_id };
                 ",
             )
             .query("commit")
-            .query("select *, (select record::id(in.out) as sample_id, azimuth, elevation from <-sample_result) as sample_results from only result where id is $result.id limit 1")
+            .query("return $invalid_schedule")
+            .query("return $samples")
+            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from only experiment where record::id(id) is $experiment_id limit 1")
             .bind(("experiment_id", experiment_id))
-            .bind(("training", result.training))
-            .bind(("user", result.user))
-            .bind(("sample_results", result.sample_results))
+            .bind(("force", force))
+            .bind(("name", update.name))
+            .bind(("is_public", update.is_public))
+            .bind(("opens_at_set", update.opens_at.is_some()))
+            .bind(("opens_at", update.opens_at.flatten()))
+            .bind(("closes_at_set", update.closes_at.is_some()))
+            .bind(("closes_at", update.closes_at.flatten()))
+            .bind(("design", update.design))
+            .bind(("layout_set", update.speaker_layout_id.is_some()))
+            .bind(("layout_id", update.speaker_layout_id.flatten()))
+            .await?;
+        let experiment = result
+            .take::<Option<Identified<Experiment>>>(9)?
+            .found()?
+            .try_into_string_id()?;
+        if result.take::<Option<bool>>(7)?.unwrap_or_default() {
+            return Ok(ExperimentChange::InvalidSchedule);
+        }
+        let samples = result
+            .take::<Vec<Identified<SampleReference>>>(8)?
+            .try_into_string_id()?;
+        if !samples.is_empty() {
+            return Ok(ExperimentChange::HasResults(samples));
+        }
+        result.validate()?;
+        Ok(ExperimentChange::Changed(Box::new(experiment)))
+    }
+
+    /// Replace the samples of an experiment
+    ///
+    /// Samples kept in the set keep their results, new samples are added.
+    /// Removing samples with results is refused unless forced or a draft, their results are deleted along with them then.
+    pub async fn replace_samples(
+        &self,
+        experiment_id: String,
+        sample_ids: Vec<String>,
+        force: bool,
+    ) -> RepoResult<ExperimentChange> {
+        let mut unique = HashSet::new();
+        let sample_ids = sample_ids
+            .into_iter()
+            .filter(|id| unique.insert(id.clone()))
+            .collect::<Vec<_>>();
+        let mut result = self
+            .surreal
+            .query("begin")
+            .query("let $experiment = select id, state from only experiment where record::id(id) is $experiment_id limit 1")
+            .query("let $missing = array::complement($sample_ids, (select value record::id(id) from sample where record::id(id) inside $sample_ids))")
+            .query("let $removed = select value id from experiment_sample where in is $experiment.id and record::id(out) notinside $sample_ids")
+            .query("let $samples = if $force or $experiment.state is 'draft' { [] } else { (select out.id as id, out.name as name from experiment_sample where id inside $removed and array::len(->sample_result) > 0) }")
+            .query("if $experiment is none or array::len($missing) > 0 or array::len($samples) > 0 { throw 'The samples were not replaced' }")
+            .query(
+                r"
+                delete sample_result where in inside $removed;
+                for $edge in $removed {
+                    delete $edge;
+                };
+                let $kept = select value record::id(out) from experiment_sample where in is $experiment.id;
+                for $sample_id in array::complement($sample_ids, $kept) {
+                    -- Writing to the sample makes a concurrent deletion of it conflict with this transaction
+                    let $sample = (update type::thing('sample', $sample_id) set used_at = time::now() return value id)[0];
+                    relate ($experiment.id)->experiment_sample->($sample);
+                };
+                ",
+            )
+            .query("commit")
+            .query("return $missing")
+            .query("return $samples")
+            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from only experiment where record::id(id) is $experiment_id limit 1")
+            .bind(("experiment_id", experiment_id))
+            .bind(("sample_ids", sample_ids))
+            .bind(("force", force))
+            .await?;
+        let experiment = result
+            .take::<Option<Identified<Experiment>>>(11)?
+            .found()?
+            .try_into_string_id()?;
+        let missing = result.take::<Vec<String>>(9)?;
+        if !missing.is_empty() {
+            return Ok(ExperimentChange::MissingSamples(missing));
+        }
+        let samples = result
+            .take::<Vec<Identified<SampleReference>>>(10)?
+            .try_into_string_id()?;
+        if !samples.is_empty() {
+            return Ok(ExperimentChange::HasResults(samples));
+        }
+        result.validate()?;
+        Ok(ExperimentChange::Changed(Box::new(experiment)))
+    }
+
+    /// Move an experiment to another state
+    ///
+    /// The state is only written if the experiment is in a state it can leave for the new one,
+    /// moving to the current state changes nothing.
+    pub async fn transition(
+        &self,
+        experiment_id: String,
+        state: ExperimentState,
+    ) -> RepoResult<ExperimentChange> {
+        let sources = ExperimentState::ALL
+            .into_iter()
+            .filter(|source| source.can_become(state))
+            .collect::<Vec<_>>();
+        let mut result = self
+            .surreal
+            .query("update experiment set state = $state where record::id(id) is $experiment_id and state inside $sources return none")
+            .query("select *, (select value record::id(out) from ->experiment_sample) as sample_ids from only experiment where record::id(id) is $experiment_id limit 1")
+            .bind(("experiment_id", experiment_id))
+            .bind(("state", state))
+            .bind(("sources", sources))
             .await?
             .validate()?;
-        let result = result
-            .take::<Option<Identified<ExperimentResult>>>(2)?
+        let experiment = result
+            .take::<Option<Identified<Experiment>>>(1)?
             .found()?
             .try_into_string_id()?;
-        Ok(result)
+        if experiment.data.state != state {
+            return Ok(ExperimentChange::Transition {
+                from: experiment.data.state,
+                to: state,
+            });
+        }
+        Ok(ExperimentChange::Changed(Box::new(experiment)))
     }
 
     /// Return all results for an experiment
@@ -124,7 +350,7 @@ impl ExperimentRepository {
     ) -> RepoResult<Vec<StringIdentified<ExperimentResult>>> {
         let mut result = self
             .surreal
-            .query("select *, (select record::id(in.out) as sample_id, azimuth, elevation from <-sample_result) as sample_results from result where experiment_id is $experiment_id")
+            .query("select *, (select record::id(in.out) as sample_id, azimuth, elevation, distance, speaker_id from <-sample_result) as sample_results from result where experiment_id is $experiment_id")
             .bind(("experiment_id", experiment_id))
             .await?;
         let results = result
@@ -133,38 +359,285 @@ impl ExperimentRepository {
         Ok(results)
     }
 
-    /// Delete the entire experiment
+    /// Get the speaker layout an experiment is run on
+    pub(super) async fn layout(
+        &self,
+        experiment: &Experiment,
+    ) -> RepoResult<Option<StringIdentified<SpeakerLayout>>> {
+        match experiment.speaker_layout_id.clone() {
+            Some(layout_id) => Ok(Some(self.layouts().referenced(layout_id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    fn layouts(&self) -> SpeakerLayoutRepository {
+        SpeakerLayoutRepository {
+            database: self.surreal.clone(),
+        }
+    }
+
+    /// Delete the entire experiment with its samples edges, results, trial sequences and sessions in one transaction
+    ///
+    /// Samples themselves are kept, missing experiments count as deleted.
     pub async fn delete(&self, experiment_id: String) -> RepoResult {
         self.surreal
-            .query("delete from experiment where record::id(id) is $experiment_id")
+            .query("begin")
+            .query("let $experiment = select value id from only experiment where record::id(id) is $experiment_id limit 1")
+            .query(
+                r"
+                if $experiment is not none {
+                    let $edges = select value id from experiment_sample where in is $experiment;
+                    delete sample_result where in inside $edges;
+                    delete result where experiment_id is $experiment_id;
+                    delete trial_sequence where experiment_id is $experiment_id;
+                    delete experiment_session where experiment_id is $experiment_id;
+                    delete experiment_sample where in is $experiment;
+                    delete $experiment;
+                };
+                ",
+            )
+            .query("commit")
             .bind(("experiment_id", experiment_id))
-            .await?;
+            .await?
+            .validate()?;
         Ok(())
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Validate)]
+#[validate(schema(function = "validate_experiment_schedule"))]
 pub struct Experiment {
     #[validate(length(min = 1, max = 63))]
     pub name: String,
     pub sample_ids: Vec<String>,
     pub is_public: bool,
+    /// Loudspeaker array the experiment is run on, answers are speakers of it
+    #[serde(default)]
+    pub speaker_layout_id: Option<String>,
+    /// New experiments are open unless created as drafts, other states are reached with [`ExperimentRepository::transition`]
+    #[validate(custom = "validate_initial_state")]
+    #[serde(default)]
+    pub state: ExperimentState,
+    /// Open experiments take results from this time on
+    #[serde(default)]
+    pub opens_at: Option<DateTime<Utc>>,
+    /// Open experiments take results until this time
+    #[serde(default)]
+    pub closes_at: Option<DateTime<Utc>>,
+    /// Repetitions, blocks and randomization of participant trial sequences
+    #[validate]
+    #[serde(default)]
+    pub design: TrialDesign,
+}
+
+impl Experiment {
+    /// Whether the experiment takes results at the given time, [`RepoError::Closed`] tells why not
+    pub fn accepts_results(&self, now: DateTime<Utc>) -> RepoResult {
+        let message = match self.state {
+            ExperimentState::Open => match (self.opens_at, self.closes_at) {
+                (Some(opens_at), _) if now < opens_at => {
+                    format!("The experiment opens at {opens_at}")
+                }
+                (_, Some(closes_at)) if now >= closes_at => {
+                    format!("The experiment closed at {closes_at}")
+                }
+                _ => return Ok(()),
+            },
+            state => format!("The experiment is {state}"),
+        };
+        Err(RepoError::Closed(message))
+    }
+}
+
+/// Lifecycle of an experiment
+///
+/// Drafts are edited freely and take no results, open experiments take results within their schedule,
+/// closed ones keep their results and archived ones are also left out of listings.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExperimentState {
+    Draft,
+    #[default]
+    Open,
+    Closed,
+    Archived,
+}
+
+impl ExperimentState {
+    pub const ALL: [Self; 4] = [Self::Draft, Self::Open, Self::Closed, Self::Archived];
+
+    /// Whether an experiment in this state can be moved to another one
+    pub fn can_become(self, to: Self) -> bool {
+        use ExperimentState::*;
+        self == to
+            || matches!(
+                (self, to),
+                (Draft, Open)
+                    | (Draft, Archived)
+                    | (Open, Closed)
+                    | (Closed, Open)
+                    | (Closed, Archived)
+                    | (Archived, Closed)
+            )
+    }
+}
+
+impl fmt::Display for ExperimentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Draft => "a draft",
+            Self::Open => "open",
+            Self::Closed => "closed",
+            Self::Archived => "archived",
+        };
+        f.write_str(name)
+    }
+}
+
+fn validate_initial_state(state: &ExperimentState) -> Result<(), ValidationError> {
+    match state {
+        ExperimentState::Draft | ExperimentState::Open => Ok(()),
+        _ => Err(ValidationError::new("initial_state")),
+    }
+}
+
+fn validate_experiment_schedule(experiment: &Experiment) -> Result<(), ValidationError> {
+    match valid_schedule(experiment.opens_at, experiment.closes_at) {
+        true => Ok(()),
+        false => Err(ValidationError::new("closes_before_opening")),
+    }
+}
+
+fn valid_schedule(opens_at: Option<DateTime<Utc>>, closes_at: Option<DateTime<Utc>>) -> bool {
+    match (opens_at, closes_at) {
+        (Some(opens_at), Some(closes_at)) => opens_at < closes_at,
+        _ => true,
+    }
+}
+
+/// Name of an experiment, used when listing experiments related to something else
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ExperimentReference {
+    pub name: String,
+}
+
+/// Name of a sample, used when listing samples of an experiment
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SampleReference {
+    pub name: String,
+}
+
+/// Editable experiment fields, missing ones are left unchanged
+///
+/// The speaker layout is removed with `"speaker_layout_id": null`.
+#[derive(Clone, Debug, Deserialize, Validate, Default)]
+pub struct ExperimentUpdate {
+    #[validate(length(min = 1, max = 63))]
+    pub name: Option<String>,
+    pub is_public: Option<bool>,
+    #[serde(default, deserialize_with = "present")]
+    pub speaker_layout_id: Option<Option<String>>,
+    /// Removed with `null` like the speaker layout
+    #[serde(default, deserialize_with = "present")]
+    pub opens_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub closes_at: Option<Option<DateTime<Utc>>>,
+    #[validate]
+    pub design: Option<TrialDesign>,
+}
+
+/// Requested state of an experiment
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct ExperimentTransition {
+    pub state: ExperimentState,
+}
+
+/// Tell a field set to `null` from a missing one
+fn present<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// New sample set of an experiment
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct ExperimentSamples {
+    #[validate(length(min = 1))]
+    pub sample_ids: Vec<String>,
+}
+
+/// Outcome of an experiment change
+#[derive(Debug)]
+pub enum ExperimentChange {
+    Changed(Box<StringIdentified<Experiment>>),
+    /// Samples with results the change would invalidate
+    HasResults(Vec<StringIdentified<SampleReference>>),
+    /// Requested samples which do not exist
+    MissingSamples(Vec<String>),
+    /// The experiment closes before it opens
+    InvalidSchedule,
+    /// The experiment cannot be moved from its state to the requested one
+    Transition {
+        from: ExperimentState,
+        to: ExperimentState,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct ExperimentResult {
-    training: bool,
+    pub training: bool,
     #[validate(length(min = 1, max = 63))]
-    user: String,
+    pub user: String,
+    /// Trial sequence the participant was presented with
+    #[serde(default)]
+    pub sequence_id: Option<String>,
     #[validate]
-    sample_results: Vec<SampleResult>,
+    pub sample_results: Vec<SampleResult>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Validate)]
+#[derive(Clone, Debug, Serialize, Deserialize, Validate)]
 pub struct SampleResult {
     pub sample_id: String,
+    /// See [`position`](super::position) for the conventions
+    #[validate(custom = "validate_azimuth")]
     pub azimuth: f32,
+    #[validate(custom = "validate_elevation")]
     pub elevation: f32,
+    /// Meters, only in experiments asking for distance
+    #[validate(custom = "validate_distance")]
+    #[serde(default)]
+    pub distance: Option<f32>,
+    /// Speaker chosen in experiments using a speaker layout, its position replaces the angles and distance
+    #[serde(default)]
+    pub speaker_id: Option<String>,
+}
+
+impl SampleResult {
+    /// Take the position of the chosen speaker in experiments using a speaker layout and normalize the azimuth
+    pub(super) fn place(&mut self, layout: Option<&StringIdentified<SpeakerLayout>>) -> RepoResult {
+        match (layout, self.speaker_id.as_deref()) {
+            (Some(layout), Some(speaker_id)) => {
+                let speaker = layout.speaker(speaker_id).ok_or_else(|| {
+                    RepoError::Speaker(format!(
+                        "Speaker layout `{}` has no speaker `{speaker_id}`",
+                        layout.name
+                    ))
+                })?;
+                self.azimuth = speaker.azimuth;
+                self.elevation = speaker.elevation;
+                self.distance = speaker.distance;
+            }
+            (Some(_), None) => Err(RepoError::Speaker(
+                "The experiment uses a speaker layout, every result needs a speaker".to_owned(),
+            ))?,
+            (None, Some(_)) => Err(RepoError::Speaker(
+                "The experiment has no speaker layout".to_owned(),
+            ))?,
+            (None, None) => {}
+        }
+        self.azimuth = normalize_azimuth(self.azimuth);
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -183,29 +656,47 @@ where
 }
 
 #[cfg(test)]
-mod tests {
-    use std::path::PathBuf;
-
-    use bytes::Bytes;
+pub mod tests {
+    use chrono::{Duration, Utc};
     use uuid::Uuid;
+    use validator::Validate;
 
     use crate::services::{
-        database::surreal::tests::surreal_in_memory,
-        file_storage::{FileStorage, FileStorageConfig},
+        audio::tests::wav_bytes,
+        database::{error::ValidateDbResponse, surreal::tests::surreal_in_memory},
+        file_storage::tests::memory_storage,
         repositories::{
-            experiment::{Experiment, ExperimentResult, SampleResult},
-            sample::{SampleInfo, SampleRepository},
+            experiment::{
+                Experiment, ExperimentChange, ExperimentResult, ExperimentState, ExperimentUpdate,
+                SampleResult,
+            },
+            experiment_sessions::SessionRequest,
+            experiment_trials::TrialDesign,
+            sample::{tests::create_sample, SampleDeletion, SampleInfo, SampleRepository},
+            speaker_layout::tests::create_ring,
+            RepoError,
         },
     };
 
     use super::ExperimentRepository;
 
+    /// Open experiment named `exp-1` with the default design
+    pub fn test_experiment(sample_ids: Vec<String>) -> Experiment {
+        Experiment {
+            name: "exp-1".to_owned(),
+            sample_ids,
+            is_public: false,
+            speaker_layout_id: None,
+            state: ExperimentState::Open,
+            opens_at: None,
+            closes_at: None,
+            design: TrialDesign::default(),
+        }
+    }
+
     async fn setup() -> (ExperimentRepository, SampleRepository) {
         let surreal = surreal_in_memory().await;
-        let file_storage_config = FileStorageConfig {
-            folder: PathBuf::from("./tmp/file_storage"),
-        };
-        let file_storage = FileStorage::setup(&file_storage_config).await.unwrap();
+        let file_storage = memory_storage().await;
 
         (
             ExperimentRepository {
@@ -225,14 +716,11 @@ mod tests {
             name: Uuid::new_v4().to_string(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sample_repo.create(info, data).await.unwrap();
-        let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id],
-            is_public: false,
-        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id]);
 
         let experiment = sut.create(experiment).await.unwrap();
 
@@ -242,11 +730,7 @@ mod tests {
     #[tokio::test]
     async fn create_non_existing_audio() {
         let (sut, _) = setup().await;
-        let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec!["aaa".to_owned()],
-            is_public: false,
-        };
+        let experiment = test_experiment(vec!["aaa".to_owned()]);
 
         sut.create(experiment).await.unwrap_err();
     }
@@ -258,14 +742,11 @@ mod tests {
             name: Uuid::new_v4().to_string(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sample_repo.create(info, data).await.unwrap();
-        let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id],
-            is_public: false,
-        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id]);
         let experiment = sut.create(experiment).await.unwrap();
 
         sut.info(experiment.id).await.unwrap();
@@ -278,19 +759,19 @@ mod tests {
             name: Uuid::new_v4().to_string(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sample_repo.create(info, data).await.unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
         let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id.clone()],
             is_public: true,
+            ..test_experiment(vec![sample.id.clone()])
         };
         sut.create(experiment).await.unwrap();
         let experiment = Experiment {
             name: "exp-2".to_owned(),
-            sample_ids: vec![sample.id],
             is_public: true,
+            ..test_experiment(vec![sample.id])
         };
         sut.create(experiment).await.unwrap();
 
@@ -308,19 +789,89 @@ mod tests {
             name: Uuid::new_v4().to_string(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sample_repo.create(info, data).await.unwrap();
-        let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id],
-            is_public: false,
-        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id]);
         let experiment = sut.create(experiment).await.unwrap();
 
         sut.delete(experiment.id).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn delete_cascades() {
+        let (sut, sample_repo) = setup().await;
+        let (id, with_result, without_result) = experiment_with_result(&sut, &sample_repo).await;
+        sut.create_session(id.clone(), SessionRequest::default())
+            .await
+            .unwrap();
+
+        sut.delete(id.clone()).await.unwrap();
+
+        let mut result = sut
+            .surreal
+            .query("select value record::id(id) from experiment_sample")
+            .query("select value record::id(id) from result")
+            .query("select value record::id(id) from sample_result")
+            .query("select value record::id(id) from trial_sequence")
+            .query("select value record::id(id) from experiment_session")
+            .await
+            .unwrap();
+        for table in 0..5 {
+            assert!(result.take::<Vec<String>>(table).unwrap().is_empty());
+        }
+        assert!(sut.info(id).await.is_err());
+        for sample_id in [with_result, without_result] {
+            let deletion = sample_repo.delete(sample_id).await.unwrap();
+            assert!(matches!(deletion, SampleDeletion::Deleted));
+        }
+    }
+
+    #[tokio::test]
+    async fn orphans_migration() {
+        let (sut, sample_repo) = setup().await;
+        let (kept, _, _) = experiment_with_result(&sut, &sample_repo).await;
+        let info = SampleInfo {
+            name: "orphaned".to_owned(),
+            ..Default::default()
+        };
+        let sample = create_sample(&sample_repo, info, wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+        // Experiments used to be deleted without their edges and results
+        sut.surreal
+            .query("relate experiment:gone->experiment_sample->(type::thing('sample', $sample_id))")
+            .query("create result:gone content { experiment_id: 'gone', training: false, user: '' }")
+            .query("relate (select value id from experiment_sample where in is experiment:gone)->sample_result->result:gone content { azimuth: 0, elevation: 0 }")
+            .bind(("sample_id", sample.id.clone()))
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        let migration =
+            std::fs::read_to_string("./migrations/007__experiment_orphans.surrealql").unwrap();
+        sut.surreal
+            .query(migration)
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        let deletion = sample_repo.delete(sample.id).await.unwrap();
+        assert!(matches!(deletion, SampleDeletion::Deleted));
+        let mut result = sut
+            .surreal
+            .query("select value record::id(id) from result")
+            .query("select value record::id(id) from sample_result")
+            .await
+            .unwrap();
+        assert_eq!(result.take::<Vec<String>>(0).unwrap().len(), 1);
+        assert_eq!(result.take::<Vec<String>>(1).unwrap().len(), 1);
+        assert_eq!(sut.results(kept).await.unwrap().len(), 1);
+    }
+
     #[tokio::test]
     async fn create_result() {
         let (sut, sample_repo) = setup().await;
@@ -328,22 +879,22 @@ mod tests {
             name: Uuid::new_v4().to_string(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sample_repo.create(info, data).await.unwrap();
-        let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id.clone()],
-            is_public: false,
-        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id.clone()]);
         let experiment = sut.create(experiment).await.unwrap();
         let result = ExperimentResult {
             training: false,
             user: String::default(),
+            sequence_id: None,
             sample_results: vec![SampleResult {
                 sample_id: sample.id,
                 azimuth: 17.0,
                 elevation: 9.3,
+                distance: None,
+                speaker_id: None,
             }],
         };
 
@@ -352,6 +903,36 @@ mod tests {
         assert_eq!(result.sample_results.len(), 1);
     }
 
+    #[tokio::test]
+    async fn create_result_normalizes_position() {
+        let (sut, sample_repo) = setup().await;
+        let info = SampleInfo {
+            name: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id.clone()]);
+        let experiment = sut.create(experiment).await.unwrap();
+        let result = ExperimentResult {
+            training: false,
+            user: String::default(),
+            sequence_id: None,
+            sample_results: vec![SampleResult {
+                sample_id: sample.id,
+                azimuth: 375.0,
+                elevation: 0.0,
+                distance: Some(2.0),
+                speaker_id: None,
+            }],
+        };
+
+        let result = sut.create_result(experiment.id, result).await.unwrap();
+
+        assert_eq!(result.sample_results[0].azimuth, 15.0);
+        assert_eq!(result.sample_results[0].distance, Some(2.0));
+    }
+
     #[tokio::test]
     async fn results() {
         let (sut, sample_repo) = setup().await;
@@ -359,22 +940,22 @@ mod tests {
             name: Uuid::new_v4().to_string(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sample_repo.create(info, data).await.unwrap();
-        let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id.clone()],
-            is_public: false,
-        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id.clone()]);
         let experiment = sut.create(experiment).await.unwrap();
         let result = ExperimentResult {
             training: false,
             user: String::default(),
+            sequence_id: None,
             sample_results: vec![SampleResult {
                 sample_id: sample.id.clone(),
                 azimuth: 17.0,
                 elevation: 9.3,
+                distance: None,
+                speaker_id: None,
             }],
         };
         sut.create_result(experiment.id.clone(), result)
@@ -383,10 +964,13 @@ mod tests {
         let result = ExperimentResult {
             training: false,
             user: String::default(),
+            sequence_id: None,
             sample_results: vec![SampleResult {
                 sample_id: sample.id.clone(),
                 azimuth: 10.3,
                 elevation: 1.5,
+                distance: None,
+                speaker_id: None,
             }],
         };
         sut.create_result(experiment.id.clone(), result)
@@ -400,4 +984,397 @@ mod tests {
         assert_eq!(result[1].sample_results.len(), 1);
         assert_eq!(result[0].sample_results[0].sample_id, sample.id);
     }
+
+    #[tokio::test]
+    async fn create_result_with_speakers() {
+        let (sut, sample_repo) = setup().await;
+        let layout_id = create_ring(&sut.surreal, "ring").await;
+        let info = SampleInfo {
+            name: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sample_repo, info, data).await.unwrap();
+        let experiment = Experiment {
+            speaker_layout_id: Some(layout_id),
+            ..test_experiment(vec![sample.id.clone()])
+        };
+        let experiment = sut.create(experiment).await.unwrap();
+        let result = |speaker_id: Option<&str>| ExperimentResult {
+            training: false,
+            user: String::default(),
+            sequence_id: None,
+            sample_results: vec![SampleResult {
+                sample_id: sample.id.clone(),
+                azimuth: 0.0,
+                elevation: 0.0,
+                distance: None,
+                speaker_id: speaker_id.map(str::to_owned),
+            }],
+        };
+
+        let created = sut
+            .create_result(experiment.id.clone(), result(Some("back")))
+            .await
+            .unwrap();
+        let unknown = sut
+            .create_result(experiment.id.clone(), result(Some("top")))
+            .await;
+        let missing = sut.create_result(experiment.id.clone(), result(None)).await;
+
+        let sample_result = &created.sample_results[0];
+        assert_eq!(sample_result.speaker_id.as_deref(), Some("back"));
+        assert_eq!(sample_result.azimuth, 180.0);
+        assert_eq!(sample_result.distance, Some(1.5));
+        assert!(matches!(unknown, Err(RepoError::Speaker(_))));
+        assert!(matches!(missing, Err(RepoError::Speaker(_))));
+        assert_eq!(sut.results(experiment.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_missing_layout() {
+        let (sut, _) = setup().await;
+        let experiment = Experiment {
+            speaker_layout_id: Some("missing".to_owned()),
+            ..test_experiment(vec![])
+        };
+
+        let result = sut.create(experiment).await;
+
+        assert!(matches!(result, Err(RepoError::Speaker(_))));
+    }
+
+    async fn experiment_with_result(
+        sut: &ExperimentRepository,
+        sample_repo: &SampleRepository,
+    ) -> (String, String, String) {
+        let mut ids = Vec::new();
+        for name in ["with-result", "without-result"] {
+            let info = SampleInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            };
+            let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+            ids.push(create_sample(sample_repo, info, data).await.unwrap().id);
+        }
+        let experiment = test_experiment(ids.clone());
+        let experiment = sut.create(experiment).await.unwrap();
+        let result = ExperimentResult {
+            training: false,
+            user: String::default(),
+            sequence_id: None,
+            sample_results: vec![SampleResult {
+                sample_id: ids[0].clone(),
+                azimuth: 17.0,
+                elevation: 9.3,
+                distance: None,
+                speaker_id: None,
+            }],
+        };
+        sut.create_result(experiment.id.clone(), result)
+            .await
+            .unwrap();
+        (experiment.id, ids.remove(0), ids.remove(0))
+    }
+
+    #[tokio::test]
+    async fn update_info() {
+        let (sut, sample_repo) = setup().await;
+        let (id, _, _) = experiment_with_result(&sut, &sample_repo).await;
+        let seed = sut.info(id.clone()).await.unwrap().data.design.seed;
+        let update = ExperimentUpdate {
+            name: Some("renamed".to_owned()),
+            is_public: Some(true),
+            design: Some(TrialDesign {
+                repetitions: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let changed = sut.update_info(id.clone(), update, false).await.unwrap();
+
+        let ExperimentChange::Changed(experiment) = changed else {
+            panic!("Experiment is not changed");
+        };
+        assert_eq!(experiment.name, "renamed");
+        assert!(experiment.is_public);
+        assert_eq!(experiment.design.repetitions, 2);
+        assert!(seed.is_some());
+        assert_eq!(experiment.design.seed, seed);
+        assert_eq!(experiment.sample_ids.len(), 2);
+        assert_eq!(sut.results(id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_layout_with_results() {
+        let (sut, sample_repo) = setup().await;
+        let (id, _, _) = experiment_with_result(&sut, &sample_repo).await;
+        let layout_id = create_ring(&sut.surreal, "ring").await;
+        let update = ExperimentUpdate {
+            speaker_layout_id: Some(Some(layout_id.clone())),
+            ..Default::default()
+        };
+
+        let refused = sut
+            .update_info(id.clone(), update.clone(), false)
+            .await
+            .unwrap();
+        let forced = sut.update_info(id.clone(), update, true).await.unwrap();
+
+        let ExperimentChange::HasResults(samples) = refused else {
+            panic!("Layout change is not refused");
+        };
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].name, "with-result");
+        let ExperimentChange::Changed(experiment) = forced else {
+            panic!("Forced layout change is refused");
+        };
+        assert_eq!(experiment.speaker_layout_id, Some(layout_id));
+        assert_eq!(sut.results(id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replace_samples() {
+        let (sut, sample_repo) = setup().await;
+        let (id, with_result, without_result) = experiment_with_result(&sut, &sample_repo).await;
+        let info = SampleInfo {
+            name: "added".to_owned(),
+            ..Default::default()
+        };
+        let added = create_sample(&sample_repo, info, wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap()
+            .id;
+
+        let changed = sut
+            .replace_samples(id.clone(), vec![with_result.clone(), added.clone()], false)
+            .await
+            .unwrap();
+
+        let ExperimentChange::Changed(experiment) = changed else {
+            panic!("Sample set is not changed");
+        };
+        let mut sample_ids = experiment.sample_ids.clone();
+        sample_ids.sort();
+        let mut expected = vec![with_result, added];
+        expected.sort();
+        assert_eq!(sample_ids, expected);
+        assert_eq!(sut.results(id).await.unwrap()[0].sample_results.len(), 1);
+        // The removed sample is no longer used and can be deleted
+        sample_repo.delete(without_result).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replace_samples_with_results() {
+        let (sut, sample_repo) = setup().await;
+        let (id, _, without_result) = experiment_with_result(&sut, &sample_repo).await;
+
+        let refused = sut
+            .replace_samples(id.clone(), vec![without_result.clone()], false)
+            .await
+            .unwrap();
+        let unchanged = sut.info(id.clone()).await.unwrap();
+        let forced = sut
+            .replace_samples(id.clone(), vec![without_result.clone()], true)
+            .await
+            .unwrap();
+
+        assert!(matches!(refused, ExperimentChange::HasResults(samples) if samples.len() == 1));
+        assert_eq!(unchanged.sample_ids.len(), 2);
+        let ExperimentChange::Changed(experiment) = forced else {
+            panic!("Forced sample change is refused");
+        };
+        assert_eq!(experiment.sample_ids, vec![without_result]);
+        assert!(sut.results(id).await.unwrap()[0].sample_results.is_empty());
+    }
+
+    #[test]
+    fn new_experiments_are_open() {
+        let experiment = serde_json::from_value::<Experiment>(serde_json::json!({
+            "name": "exp-1",
+            "sample_ids": [],
+            "is_public": false,
+        }))
+        .unwrap();
+
+        assert_eq!(experiment.state, ExperimentState::Open);
+    }
+
+    #[tokio::test]
+    async fn lifecycle() {
+        let (sut, sample_repo) = setup().await;
+        let info = SampleInfo {
+            name: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+        let sample = create_sample(&sample_repo, info, wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+        let experiment = Experiment {
+            is_public: true,
+            state: ExperimentState::Draft,
+            ..test_experiment(vec![sample.id.clone()])
+        };
+        let id = sut.create(experiment).await.unwrap().id;
+        let result = || ExperimentResult {
+            training: false,
+            user: String::default(),
+            sequence_id: None,
+            sample_results: vec![SampleResult {
+                sample_id: sample.id.clone(),
+                azimuth: 0.0,
+                elevation: 0.0,
+                distance: None,
+                speaker_id: None,
+            }],
+        };
+
+        let draft = sut.create_result(id.clone(), result()).await;
+        sut.transition(id.clone(), ExperimentState::Open)
+            .await
+            .unwrap();
+        let open = sut.create_result(id.clone(), result()).await;
+        let refused = sut
+            .transition(id.clone(), ExperimentState::Archived)
+            .await
+            .unwrap();
+        sut.transition(id.clone(), ExperimentState::Closed)
+            .await
+            .unwrap();
+        let closed = sut.create_result(id.clone(), result()).await;
+        let archived = sut
+            .transition(id.clone(), ExperimentState::Archived)
+            .await
+            .unwrap();
+
+        assert!(matches!(draft, Err(RepoError::Closed(_))));
+        assert!(open.is_ok());
+        assert!(matches!(
+            refused,
+            ExperimentChange::Transition {
+                from: ExperimentState::Open,
+                to: ExperimentState::Archived
+            }
+        ));
+        assert!(matches!(closed, Err(RepoError::Closed(_))));
+        assert!(
+            matches!(archived, ExperimentChange::Changed(experiment) if experiment.state == ExperimentState::Archived)
+        );
+        assert!(sut.infos(false).await.unwrap().is_empty());
+        assert_eq!(sut.infos(true).await.unwrap().len(), 1);
+        assert!(sut.public_infos().await.unwrap().is_empty());
+        assert_eq!(sut.results(id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn states_migration() {
+        let (sut, _) = setup().await;
+        // Experiments used to have no state and took results all the time
+        sut.surreal
+            .query("create experiment:legacy content { name: 'legacy', is_public: true }")
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        let migration =
+            std::fs::read_to_string("./migrations/008__experiment_states.surrealql").unwrap();
+        sut.surreal
+            .query(migration)
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        let experiment = sut.info("legacy".to_owned()).await.unwrap();
+        assert_eq!(experiment.state, ExperimentState::Open);
+        assert_eq!(sut.public_infos().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn schedule() {
+        let now = Utc::now();
+        let experiment = |opens_at: Option<i64>, closes_at: Option<i64>| Experiment {
+            opens_at: opens_at.map(|hours| now + Duration::hours(hours)),
+            closes_at: closes_at.map(|hours| now + Duration::hours(hours)),
+            ..test_experiment(vec![])
+        };
+
+        assert!(experiment(None, None).accepts_results(now).is_ok());
+        assert!(experiment(Some(-1), Some(1)).accepts_results(now).is_ok());
+        assert!(experiment(Some(1), None).accepts_results(now).is_err());
+        assert!(experiment(None, Some(-1)).accepts_results(now).is_err());
+        assert!(experiment(Some(1), Some(-1)).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn update_schedule() {
+        let (sut, sample_repo) = setup().await;
+        let (id, _, _) = experiment_with_result(&sut, &sample_repo).await;
+        let opens_at = Utc::now() + Duration::days(1);
+        let update = |closes_at| ExperimentUpdate {
+            opens_at: Some(Some(opens_at)),
+            closes_at: Some(closes_at),
+            ..Default::default()
+        };
+
+        let invalid = sut
+            .update_info(
+                id.clone(),
+                update(Some(opens_at - Duration::hours(1))),
+                false,
+            )
+            .await
+            .unwrap();
+        let changed = sut
+            .update_info(id.clone(), update(None), false)
+            .await
+            .unwrap();
+        let closes_only = ExperimentUpdate {
+            closes_at: Some(Some(opens_at - Duration::hours(1))),
+            ..Default::default()
+        };
+        let invalid_closing = sut
+            .update_info(id.clone(), closes_only, false)
+            .await
+            .unwrap();
+
+        assert!(matches!(invalid, ExperimentChange::InvalidSchedule));
+        assert!(matches!(invalid_closing, ExperimentChange::InvalidSchedule));
+        let ExperimentChange::Changed(experiment) = changed else {
+            panic!("Schedule is not changed");
+        };
+        assert_eq!(experiment.opens_at, Some(opens_at));
+        assert!(matches!(
+            experiment.accepts_results(Utc::now()),
+            Err(RepoError::Closed(_))
+        ));
+        let result = ExperimentResult {
+            training: false,
+            user: "participant".to_owned(),
+            sequence_id: None,
+            sample_results: vec![],
+        };
+        assert!(matches!(
+            sut.create_result(id, result).await,
+            Err(RepoError::Closed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replace_missing_samples() {
+        let (sut, sample_repo) = setup().await;
+        let (id, with_result, _) = experiment_with_result(&sut, &sample_repo).await;
+
+        let result = sut
+            .replace_samples(id.clone(), vec![with_result, "missing".to_owned()], true)
+            .await
+            .unwrap();
+
+        assert!(
+            matches!(result, ExperimentChange::MissingSamples(missing) if missing == vec!["missing".to_owned()])
+        );
+        assert_eq!(sut.info(id).await.unwrap().sample_ids.len(), 2);
+    }
 }
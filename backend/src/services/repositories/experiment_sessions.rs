@@ -0,0 +1,494 @@
+//! Participant sessions answering the trials of an experiment one at a time
+//!
+//! Answers are stored as they arrive, so an interrupted session resumes from its next trial.
+//! Finishing a session stores its answers as an ordinary experiment result.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::services::database::{
+    error::ValidateDbResponse,
+    identified::{Identified, StringIdentified, TryIntoStringId},
+    surreal::MapToNotFound,
+};
+
+use super::{
+    experiment::{
+        Experiment, ExperimentRepository, SampleResult, CHECK_ACCEPTS_RESULTS, CREATE_RESULT,
+    },
+    experiment_trials::{Trial, TrialRequest, TrialSequence},
+    position::{validate_azimuth, validate_distance, validate_elevation},
+    RepoResult,
+};
+
+/// Unfinished sessions without answers for this long count as abandoned, they can still be resumed
+pub const ABANDONED_AFTER: TimeDelta = TimeDelta::hours(1);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub experiment_id: String,
+    /// Trial sequence drawn for the session, its trials are copied to the session
+    pub sequence_id: String,
+    pub participant: String,
+    pub training: bool,
+    pub trials: Vec<Trial>,
+    /// Answers in trial order
+    pub responses: Vec<SampleResult>,
+    pub started_at: DateTime<Utc>,
+    /// Time of the latest answer
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Result the answers were stored as when the session finished
+    pub result_id: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStatus {
+    Active,
+    Finished,
+    Abandoned,
+}
+
+impl Session {
+    pub fn status(&self, now: DateTime<Utc>) -> SessionStatus {
+        match self.finished_at {
+            Some(_) => SessionStatus::Finished,
+            None if now - self.updated_at > ABANDONED_AFTER => SessionStatus::Abandoned,
+            None => SessionStatus::Active,
+        }
+    }
+}
+
+/// Session with its status and the trial answered next
+#[derive(Debug, Serialize)]
+pub struct SessionProgress {
+    #[serde(flatten)]
+    pub session: StringIdentified<Session>,
+    pub status: SessionStatus,
+    /// Equals the number of trials once all are answered
+    pub next_trial: usize,
+}
+
+impl From<StringIdentified<Session>> for SessionProgress {
+    fn from(session: StringIdentified<Session>) -> Self {
+        Self {
+            status: session.status(Utc::now()),
+            next_trial: session.responses.len(),
+            session,
+        }
+    }
+}
+
+/// Request for a new session
+#[derive(Clone, Debug, Deserialize, Validate, Default)]
+pub struct SessionRequest {
+    /// Stored as the user of the result once the session finishes
+    #[validate(length(min = 1, max = 63))]
+    pub participant: String,
+    #[serde(default)]
+    pub training: bool,
+}
+
+/// Answer to one trial, the sample is the one of the trial
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct TrialAnswer {
+    #[validate(custom = "validate_azimuth")]
+    pub azimuth: f32,
+    #[validate(custom = "validate_elevation")]
+    pub elevation: f32,
+    #[validate(custom = "validate_distance")]
+    #[serde(default)]
+    pub distance: Option<f32>,
+    #[serde(default)]
+    pub speaker_id: Option<String>,
+}
+
+/// Outcome of answering a trial or finishing a session
+#[derive(Debug)]
+pub enum SessionChange {
+    Changed(Box<SessionProgress>),
+    /// The session is finished and takes no more answers
+    Finished,
+    /// Trials are answered in order, this one is next
+    OutOfOrder {
+        next: usize,
+    },
+    /// Sessions finish once all trials are answered, this one is next
+    Incomplete {
+        next: usize,
+    },
+}
+
+impl ExperimentRepository {
+    /// Start a session with a newly drawn trial sequence
+    ///
+    /// Only experiments taking results start sessions, others are reported as [`RepoError::Closed`](super::RepoError::Closed).
+    /// The sequence is stored in the same transaction as the session, so no sequence is left without its session.
+    pub async fn create_session(
+        &self,
+        experiment_id: String,
+        request: SessionRequest,
+    ) -> RepoResult<SessionProgress> {
+        let trial_request = TrialRequest {
+            participant: Some(request.participant.clone()),
+        };
+        self.draw_trials(experiment_id, trial_request, |sequence| {
+            self.store_session(sequence, &request)
+        })
+        .await
+    }
+
+    async fn store_session(
+        &self,
+        sequence: TrialSequence,
+        request: &SessionRequest,
+    ) -> RepoResult<SessionProgress> {
+        let mut result = self
+            .surreal
+            .query("begin")
+            .query("let $sequence = create only trial_sequence content $new_sequence")
+            .query(
+                r"
+                create only experiment_session set
+                    experiment_id = $sequence.experiment_id,
+                    sequence_id = record::id($sequence.id),
+                    participant = $participant,
+                    training = $training,
+                    trials = $sequence.trials,
+                    responses = [],
+                    started_at = $now,
+                    updated_at = $now;
+                ",
+            )
+            .query("commit")
+            .bind(("new_sequence", sequence))
+            .bind(("participant", request.participant.clone()))
+            .bind(("training", request.training))
+            .bind(("now", Utc::now()))
+            .await?
+            .validate()?;
+        let session = result
+            .take::<Option<Identified<Session>>>(1)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(session.into())
+    }
+
+    /// Get a session, for example to resume it
+    pub async fn session(&self, session_id: String) -> RepoResult<SessionProgress> {
+        let mut result = self
+            .surreal
+            .query(
+                "select * from only experiment_session where record::id(id) is $session_id limit 1",
+            )
+            .bind(("session_id", session_id))
+            .await?;
+        let session = result
+            .take::<Option<Identified<Session>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(session.into())
+    }
+
+    /// Return sessions of an experiment in the order they were started, abandoned ones included
+    pub async fn sessions(&self, experiment_id: String) -> RepoResult<Vec<SessionProgress>> {
+        let mut result = self
+            .surreal
+            .query("select * from experiment_session where experiment_id is $experiment_id order by started_at")
+            .bind(("experiment_id", experiment_id))
+            .await?;
+        let sessions = result
+            .take::<Vec<Identified<Session>>>(0)?
+            .try_into_string_id()?;
+        Ok(sessions.into_iter().map(SessionProgress::from).collect())
+    }
+
+    /// Store the answer to a trial
+    ///
+    /// Answering a trial again leaves the first answer, so a repeated request changes nothing.
+    /// The answer is only appended if no other answer was stored in between.
+    pub async fn answer(
+        &self,
+        session_id: String,
+        trial: usize,
+        answer: TrialAnswer,
+    ) -> RepoResult<SessionChange> {
+        let progress = self.session(session_id.clone()).await?;
+        if progress.session.finished_at.is_some() {
+            return Ok(SessionChange::Finished);
+        }
+        if trial < progress.next_trial {
+            return Ok(SessionChange::Changed(Box::new(progress)));
+        }
+        let Some(presented) = progress
+            .session
+            .trials
+            .get(trial)
+            .filter(|_| trial == progress.next_trial)
+        else {
+            return Ok(SessionChange::OutOfOrder {
+                next: progress.next_trial,
+            });
+        };
+        let experiment = self.info(progress.session.experiment_id.clone()).await?;
+        experiment.data.accepts_results(Utc::now())?;
+        let mut response = SampleResult {
+            sample_id: presented.sample_id.clone(),
+            azimuth: answer.azimuth,
+            elevation: answer.elevation,
+            distance: answer.distance,
+            speaker_id: answer.speaker_id,
+        };
+        response.place(self.layout(&experiment).await?.as_ref())?;
+        self.surreal
+            .query("update experiment_session set responses = array::append(responses, $response), updated_at = $now where record::id(id) is $session_id and array::len(responses) is $trial and finished_at is none return none")
+            .bind(("session_id", session_id.clone()))
+            .bind(("response", response))
+            .bind(("trial", trial))
+            .bind(("now", Utc::now()))
+            .await?
+            .validate()?;
+        let progress = self.session(session_id).await?;
+        if progress.next_trial > trial {
+            Ok(SessionChange::Changed(Box::new(progress)))
+        } else {
+            Ok(SessionChange::Finished)
+        }
+    }
+
+    /// Finish a session with all trials answered and store its answers as a result
+    ///
+    /// Finishing a finished session changes nothing.
+    /// The session is marked finished in the transaction storing the result, so it is never finished without one.
+    pub async fn finish_session(&self, session_id: String) -> RepoResult<SessionChange> {
+        let now = Utc::now();
+        let mut result = self
+            .surreal
+            .query("begin")
+            .query("let $experiment_session = select * from only experiment_session where record::id(id) is $session_id limit 1")
+            .query("if $experiment_session is none or $experiment_session.finished_at is not none or array::len($experiment_session.responses) != array::len($experiment_session.trials) { throw 'The session cannot be finished' }")
+            .query("let $experiment = select *, (select value record::id(out) from ->experiment_sample) as sample_ids from only experiment where record::id(id) is $experiment_session.experiment_id limit 1")
+            .query(CHECK_ACCEPTS_RESULTS)
+            .query("let $new_result = { training: $experiment_session.training, user: $experiment_session.participant, sequence_id: $experiment_session.sequence_id, sample_results: $experiment_session.responses }")
+            .query(CREATE_RESULT)
+            .query("update $experiment_session.id set finished_at = $now, result_id = record::id($result.id)")
+            .query("commit")
+            .query("return $experiment_session")
+            .query("return $experiment")
+            .bind(("session_id", session_id.clone()))
+            .bind(("now", now))
+            .await?;
+        let progress = SessionProgress::from(
+            result
+                .take::<Option<Identified<Session>>>(8)?
+                .found()?
+                .try_into_string_id()?,
+        );
+        if progress.session.finished_at.is_some() {
+            return Ok(SessionChange::Changed(Box::new(progress)));
+        }
+        if progress.next_trial < progress.session.trials.len() {
+            return Ok(SessionChange::Incomplete {
+                next: progress.next_trial,
+            });
+        }
+        result
+            .take::<Option<Identified<Experiment>>>(9)?
+            .found()?
+            .data
+            .accepts_results(now)?;
+        let failed = result.validate().err();
+        let progress = self.session(session_id).await?;
+        match failed {
+            // Finishing the session at the same time conflicts with this transaction
+            Some(e) if progress.session.finished_at.is_none() => Err(e)?,
+            _ => Ok(SessionChange::Changed(Box::new(progress))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+    use validator::Validate;
+
+    use crate::services::{
+        audio::tests::wav_bytes,
+        database::{error::ValidateDbResponse, surreal::tests::surreal_in_memory},
+        file_storage::tests::memory_storage,
+        repositories::{
+            experiment::{
+                tests::test_experiment, Experiment, ExperimentRepository, ExperimentState,
+            },
+            experiment_trials::{Randomization, TrialDesign},
+            sample::{tests::create_sample, SampleInfo, SampleRepository},
+            RepoError,
+        },
+    };
+
+    use super::{SessionChange, SessionRequest, SessionStatus, TrialAnswer};
+
+    async fn setup() -> (ExperimentRepository, String) {
+        let surreal = surreal_in_memory().await;
+        let sample_repo = SampleRepository {
+            database: surreal.clone(),
+            file_storage: memory_storage().await,
+        };
+        let mut sample_ids = Vec::new();
+        for name in ["a", "b"] {
+            let info = SampleInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            };
+            let data = wav_bytes(&[1, 2, 3]);
+            sample_ids.push(create_sample(&sample_repo, info, data).await.unwrap().id);
+        }
+        let sut = ExperimentRepository { surreal };
+        let experiment = Experiment {
+            is_public: true,
+            design: TrialDesign {
+                repetitions: 2,
+                randomization: Randomization::WithinBlock,
+                ..Default::default()
+            },
+            ..test_experiment(sample_ids)
+        };
+        let id = sut.create(experiment).await.unwrap().id;
+        (sut, id)
+    }
+
+    fn answer(azimuth: f32) -> TrialAnswer {
+        TrialAnswer {
+            azimuth,
+            elevation: 0.0,
+            distance: None,
+            speaker_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_session_stores_no_sequence() {
+        let (sut, experiment_id) = setup().await;
+        sut.surreal
+            .query("define field participant on experiment_session assert $value is not 'rejected'")
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+        let request = SessionRequest {
+            participant: "rejected".to_owned(),
+            training: false,
+        };
+
+        let result = sut.create_session(experiment_id.clone(), request).await;
+
+        result.unwrap_err();
+        assert!(sut.trials(experiment_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn answer_and_finish() {
+        let (sut, experiment_id) = setup().await;
+        let request = SessionRequest {
+            participant: "participant".to_owned(),
+            training: false,
+        };
+        let session = sut
+            .create_session(experiment_id.clone(), request)
+            .await
+            .unwrap();
+        let id = session.session.id.clone();
+
+        let skipped = sut.answer(id.clone(), 1, answer(10.0)).await.unwrap();
+        for trial in 0..3 {
+            sut.answer(id.clone(), trial, answer(trial as f32))
+                .await
+                .unwrap();
+        }
+        let repeated = sut.answer(id.clone(), 2, answer(90.0)).await.unwrap();
+        let incomplete = sut.finish_session(id.clone()).await.unwrap();
+        // The session resumes from its fourth trial
+        let resumed = sut.session(id.clone()).await.unwrap();
+        sut.answer(id.clone(), 3, answer(-10.0)).await.unwrap();
+        let finished = sut.finish_session(id.clone()).await.unwrap();
+        let again = sut.finish_session(id.clone()).await.unwrap();
+        let late = sut.answer(id.clone(), 3, answer(0.0)).await.unwrap();
+
+        assert_eq!(session.next_trial, 0);
+        assert_eq!(session.session.trials.len(), 4);
+        assert!(matches!(skipped, SessionChange::OutOfOrder { next: 0 }));
+        assert!(
+            matches!(repeated, SessionChange::Changed(progress) if progress.session.responses[2].azimuth == 2.0)
+        );
+        assert!(matches!(incomplete, SessionChange::Incomplete { next: 3 }));
+        assert_eq!(resumed.next_trial, 3);
+        assert_eq!(resumed.status, SessionStatus::Active);
+        let SessionChange::Changed(finished) = finished else {
+            panic!("Session is not finished");
+        };
+        assert_eq!(finished.status, SessionStatus::Finished);
+        assert!(
+            matches!(again, SessionChange::Changed(progress) if progress.session.result_id == finished.session.result_id)
+        );
+        assert!(matches!(late, SessionChange::Finished));
+        let results = sut.results(experiment_id.clone()).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(Some(&results[0].id), finished.session.result_id.as_ref());
+        assert_eq!(results[0].user, "participant");
+        assert_eq!(results[0].sample_results.len(), 4);
+        assert!(results[0]
+            .sample_results
+            .iter()
+            .any(|result| result.azimuth == 350.0));
+        assert_eq!(sut.sessions(experiment_id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn abandoned() {
+        let (sut, experiment_id) = setup().await;
+        let session = sut
+            .create_session(experiment_id.clone(), SessionRequest::default())
+            .await
+            .unwrap();
+        let later = Utc::now() + TimeDelta::hours(2);
+
+        let sessions = sut.sessions(experiment_id).await.unwrap();
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].status, SessionStatus::Active);
+        assert_eq!(session.session.status(later), SessionStatus::Abandoned);
+    }
+
+    #[tokio::test]
+    async fn finish_closed() {
+        let (sut, experiment_id) = setup().await;
+        let session = sut
+            .create_session(experiment_id.clone(), SessionRequest::default())
+            .await
+            .unwrap();
+        let id = session.session.id.clone();
+        for trial in 0..4 {
+            sut.answer(id.clone(), trial, answer(0.0)).await.unwrap();
+        }
+        sut.transition(experiment_id.clone(), ExperimentState::Closed)
+            .await
+            .unwrap();
+
+        let closed = sut.finish_session(id.clone()).await;
+
+        assert!(matches!(closed, Err(RepoError::Closed(_))));
+        assert_eq!(sut.session(id).await.unwrap().session.finished_at, None);
+        assert!(sut.results(experiment_id).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn participant_required() {
+        let missing =
+            serde_json::from_value::<SessionRequest>(serde_json::json!({ "training": true }));
+
+        assert!(missing.is_err());
+        assert!(SessionRequest::default().validate().is_err());
+    }
+}
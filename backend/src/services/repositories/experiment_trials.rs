@@ -0,0 +1,535 @@
+//! Trial sequences presented to experiment participants
+//!
+//! Every participant gets their own order of trials drawn from the design of the experiment,
+//! sequences are stored so analysis knows what was presented in which order.
+
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::services::database::{
+    error::ValidateDbResponse,
+    identified::{Identified, StringIdentified, TryIntoStringId},
+    surreal::MapToNotFound,
+};
+
+use super::{experiment::ExperimentRepository, IsViolatingUnique, RepoError, RepoResult};
+
+/// Times a sequence is drawn before an ordinal taken by concurrent draws is reported
+const TRIAL_ATTEMPTS: usize = 8;
+
+/// How trials of an experiment are built
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Validate)]
+#[validate(schema(function = "validate_design"))]
+pub struct TrialDesign {
+    /// Presentations of every sample
+    #[validate(range(min = 1, max = 100))]
+    #[serde(default = "one")]
+    pub repetitions: u32,
+    /// Consecutive blocks trials are split into, every block presents each sample equally often
+    #[validate(range(min = 1, max = 100))]
+    #[serde(default = "one")]
+    pub blocks: u32,
+    #[serde(default)]
+    pub randomization: Randomization,
+    /// Seed of the trial orders, drawn when the experiment is created if missing
+    #[serde(default)]
+    pub seed: Option<u32>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl Default for TrialDesign {
+    fn default() -> Self {
+        Self {
+            repetitions: 1,
+            blocks: 1,
+            randomization: Randomization::default(),
+            seed: None,
+        }
+    }
+}
+
+fn validate_design(design: &TrialDesign) -> Result<(), ValidationError> {
+    match design.repetitions % design.blocks {
+        0 => Ok(()),
+        _ => Err(ValidationError::new("repetitions_not_divisible_by_blocks")),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Randomization {
+    /// Samples in the same order in every block
+    Fixed,
+    /// All trials shuffled together, blocks only number the trials
+    #[default]
+    Shuffle,
+    /// Trials shuffled within each block
+    WithinBlock,
+    /// Trials shuffled within each block so that no sample directly follows itself, also across blocks
+    ///
+    /// Experiments with a single sample repeat it anyway.
+    NoConsecutiveRepeats,
+}
+
+/// Sample presented at a position of a trial sequence
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Trial {
+    pub sample_id: String,
+    /// Counted from 0
+    pub block: u32,
+    /// Earlier presentations of the sample in the sequence
+    pub repetition: u32,
+}
+
+/// Trials of one participant in presentation order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrialSequence {
+    pub experiment_id: String,
+    /// Position among the sequences of the experiment, the random stream the order is drawn from
+    pub ordinal: u64,
+    pub seed: u32,
+    pub participant: Option<String>,
+    pub trials: Vec<Trial>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request for a new trial sequence
+#[derive(Clone, Debug, Deserialize, Validate, Default)]
+pub struct TrialRequest {
+    #[validate(length(min = 1, max = 63))]
+    #[serde(default)]
+    pub participant: Option<String>,
+}
+
+/// Build the trials of a sequence
+///
+/// Samples are taken in identifier order, so the same design, samples, seed and ordinal always give the same trials.
+pub fn generate_trials(
+    design: &TrialDesign,
+    sample_ids: &[String],
+    seed: u32,
+    ordinal: u64,
+) -> Vec<Trial> {
+    let mut sample_ids = sample_ids.to_vec();
+    sample_ids.sort();
+    let mut rng = ChaCha8Rng::seed_from_u64(seed as u64);
+    rng.set_stream(ordinal);
+    let per_block = (design.repetitions / design.blocks.max(1)) as usize;
+    let block_len = per_block * sample_ids.len();
+    let block = || {
+        (0..per_block)
+            .flat_map(|_| 0..sample_ids.len())
+            .collect::<Vec<_>>()
+    };
+    let mut order = Vec::with_capacity(block_len * design.blocks as usize);
+    for _ in 0..design.blocks {
+        let mut indexes = block();
+        match design.randomization {
+            Randomization::Fixed | Randomization::Shuffle => {}
+            Randomization::WithinBlock => indexes.shuffle(&mut rng),
+            Randomization::NoConsecutiveRepeats => {
+                indexes =
+                    without_repeats(&mut rng, sample_ids.len(), per_block, order.last().copied())
+            }
+        }
+        order.extend(indexes);
+    }
+    if design.randomization == Randomization::Shuffle {
+        order.shuffle(&mut rng);
+    }
+
+    let mut presented = vec![0; sample_ids.len()];
+    order
+        .into_iter()
+        .enumerate()
+        .map(|(position, index)| {
+            presented[index] += 1;
+            Trial {
+                sample_id: sample_ids[index].clone(),
+                block: (position / block_len.max(1)) as u32,
+                repetition: presented[index] - 1,
+            }
+        })
+        .collect()
+}
+
+/// Random order of `count` presentations of every sample in which no sample follows itself
+///
+/// Each pick is drawn among samples which still leave an order without repeats,
+/// weighted by their remaining presentations.
+fn without_repeats(
+    rng: &mut ChaCha8Rng,
+    samples: usize,
+    count: usize,
+    mut previous: Option<usize>,
+) -> Vec<usize> {
+    let mut remaining = vec![count; samples];
+    let mut left = samples * count;
+    let mut order = Vec::with_capacity(left);
+    while left > 0 {
+        // Two largest remaining counts, enough to check the others after a pick
+        let mut largest = (usize::MAX, 0);
+        let mut second = 0;
+        for (index, &count) in remaining.iter().enumerate() {
+            if count > largest.1 {
+                second = largest.1;
+                largest = (index, count);
+            } else if count > second {
+                second = count;
+            }
+        }
+        let after = left - 1;
+        let feasible = |index: usize| {
+            let others = if index == largest.0 {
+                second
+            } else {
+                largest.1
+            };
+            remaining[index] - 1 <= after / 2 && others <= after.div_ceil(2)
+        };
+        let pickable = |index: &usize| remaining[*index] > 0 && Some(*index) != previous;
+        let mut candidates = (0..samples)
+            .filter(|index| pickable(index) && feasible(*index))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            candidates = (0..samples).filter(pickable).collect();
+        }
+        if candidates.is_empty() {
+            candidates = (0..samples).filter(|index| remaining[*index] > 0).collect();
+        }
+        let total = candidates
+            .iter()
+            .map(|index| remaining[*index])
+            .sum::<usize>();
+        let mut draw = rng.gen_range(0..total);
+        let pick = candidates
+            .into_iter()
+            .find(|index| {
+                let found = draw < remaining[*index];
+                draw = draw.saturating_sub(remaining[*index]);
+                found
+            })
+            .expect("Draw is below the total");
+        remaining[pick] -= 1;
+        left -= 1;
+        order.push(pick);
+        previous = Some(pick);
+    }
+    order
+}
+
+impl ExperimentRepository {
+    /// Draw and store the next trial sequence of an experiment
+    ///
+    /// Only experiments taking results hand out sequences, others are reported as [`RepoError::Closed`].
+    pub async fn create_trials(
+        &self,
+        experiment_id: String,
+        request: TrialRequest,
+    ) -> RepoResult<StringIdentified<TrialSequence>> {
+        self.draw_trials(experiment_id, request, |sequence| {
+            self.store_trials(sequence)
+        })
+        .await
+    }
+
+    /// Draw the next trial sequence of an experiment and store it with `store`
+    ///
+    /// Sequences drawn at the same time can take the same ordinal, the later one violates a unique index and is drawn again with the next one.
+    pub(super) async fn draw_trials<T, Stored>(
+        &self,
+        experiment_id: String,
+        request: TrialRequest,
+        store: impl Fn(TrialSequence) -> Stored,
+    ) -> RepoResult<T>
+    where
+        Stored: Future<Output = RepoResult<T>>,
+    {
+        let experiment = self.info(experiment_id.clone()).await?;
+        experiment.data.accepts_results(Utc::now())?;
+        let design = &experiment.data.design;
+        let seed = design.seed.unwrap_or_default();
+        let mut attempts = 1;
+        loop {
+            let mut result = self
+                .surreal
+                .query("select value ordinal from trial_sequence where experiment_id is $experiment_id order by ordinal desc limit 1")
+                .bind(("experiment_id", experiment_id.clone()))
+                .await?;
+            let ordinal = result
+                .take::<Option<u64>>(0)?
+                .map_or(0, |ordinal| ordinal + 1);
+            let sequence = TrialSequence {
+                trials: generate_trials(design, &experiment.data.sample_ids, seed, ordinal),
+                experiment_id: experiment_id.clone(),
+                ordinal,
+                seed,
+                participant: request.participant.clone(),
+                created_at: Utc::now(),
+            };
+            let result = store(sequence).await;
+            if attempts < TRIAL_ATTEMPTS && result.is_violating_unique() {
+                attempts += 1;
+                continue;
+            }
+            return result;
+        }
+    }
+
+    async fn store_trials(
+        &self,
+        sequence: TrialSequence,
+    ) -> RepoResult<StringIdentified<TrialSequence>> {
+        let mut result = self
+            .surreal
+            .query("create only trial_sequence content $sequence")
+            .bind(("sequence", sequence))
+            .await?
+            .validate()?;
+        let sequence = result
+            .take::<Option<Identified<TrialSequence>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(sequence)
+    }
+
+    /// Return trial sequences of an experiment in the order they were drawn
+    pub async fn trials(
+        &self,
+        experiment_id: String,
+    ) -> RepoResult<Vec<StringIdentified<TrialSequence>>> {
+        let mut result = self
+            .surreal
+            .query("select * from trial_sequence where experiment_id is $experiment_id order by ordinal")
+            .bind(("experiment_id", experiment_id))
+            .await?;
+        let sequences = result
+            .take::<Vec<Identified<TrialSequence>>>(0)?
+            .try_into_string_id()?;
+        Ok(sequences)
+    }
+
+    /// Check that a trial sequence was drawn for an experiment, reported as [`RepoError::Trials`] otherwise
+    pub(super) async fn check_trials(
+        &self,
+        experiment_id: &str,
+        sequence_id: String,
+    ) -> RepoResult {
+        let mut result = self
+            .surreal
+            .query("select value experiment_id from only trial_sequence where record::id(id) is $sequence_id limit 1")
+            .bind(("sequence_id", sequence_id.clone()))
+            .await?;
+        match result.take::<Option<String>>(0)? {
+            Some(id) if id == experiment_id => Ok(()),
+            _ => Err(RepoError::Trials(format!(
+                "Trial sequence `{sequence_id}` does not belong to the experiment"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::services::{
+        audio::tests::wav_bytes,
+        database::surreal::tests::surreal_in_memory,
+        file_storage::tests::memory_storage,
+        repositories::{
+            experiment::{
+                tests::test_experiment, Experiment, ExperimentRepository, ExperimentResult,
+                SampleResult,
+            },
+            sample::{tests::create_sample, SampleInfo, SampleRepository},
+            RepoError,
+        },
+    };
+
+    use super::{generate_trials, Randomization, Trial, TrialDesign, TrialRequest};
+
+    fn samples(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("sample-{i}")).collect()
+    }
+
+    fn design(repetitions: u32, blocks: u32, randomization: Randomization) -> TrialDesign {
+        TrialDesign {
+            repetitions,
+            blocks,
+            randomization,
+            seed: Some(7),
+        }
+    }
+
+    fn counts(trials: &[Trial]) -> HashMap<(&str, u32), usize> {
+        let mut counts = HashMap::new();
+        for trial in trials {
+            *counts
+                .entry((trial.sample_id.as_str(), trial.block))
+                .or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn fixed() {
+        let trials = generate_trials(&design(2, 2, Randomization::Fixed), &samples(3), 7, 0);
+
+        let ids = trials.iter().map(|trial| trial.sample_id.as_str());
+        assert!(ids.eq(["sample-0", "sample-1", "sample-2"].repeat(2)));
+        assert_eq!(trials[3].block, 1);
+        assert_eq!(trials[3].repetition, 1);
+    }
+
+    #[test]
+    fn reproducible() {
+        let design = design(4, 1, Randomization::Shuffle);
+        let mut reversed = samples(10);
+        reversed.reverse();
+
+        let first = generate_trials(&design, &samples(10), 7, 3);
+        let same = generate_trials(&design, &reversed, 7, 3);
+        let other = generate_trials(&design, &samples(10), 7, 4);
+
+        assert_eq!(first, same);
+        assert_ne!(first, other);
+        assert_eq!(first.len(), 40);
+        assert!(counts(&first).values().all(|count| *count == 4));
+    }
+
+    #[test]
+    fn within_block() {
+        let trials = generate_trials(&design(6, 3, Randomization::WithinBlock), &samples(5), 7, 0);
+
+        assert_eq!(trials.len(), 30);
+        // Every block presents every sample twice
+        assert_eq!(counts(&trials).len(), 15);
+        assert!(counts(&trials).values().all(|count| *count == 2));
+    }
+
+    #[test]
+    fn no_consecutive_repeats() {
+        for (count, repetitions) in [(2, 20), (3, 10), (7, 4)] {
+            for ordinal in 0..20 {
+                let trials = generate_trials(
+                    &design(repetitions, 2, Randomization::NoConsecutiveRepeats),
+                    &samples(count),
+                    7,
+                    ordinal,
+                );
+
+                assert_eq!(trials.len(), count * repetitions as usize);
+                assert!(counts(&trials)
+                    .values()
+                    .all(|c| *c == repetitions as usize / 2));
+                assert!(trials
+                    .windows(2)
+                    .all(|pair| pair[0].sample_id != pair[1].sample_id));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn create_trials() {
+        let surreal = surreal_in_memory().await;
+        let sut = ExperimentRepository {
+            surreal: surreal.clone(),
+        };
+        let sample_repo = SampleRepository {
+            database: surreal,
+            file_storage: memory_storage().await,
+        };
+        let mut sample_ids = Vec::new();
+        for name in ["a", "b"] {
+            let info = SampleInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            };
+            let data = wav_bytes(&[1, 2, 3]);
+            sample_ids.push(create_sample(&sample_repo, info, data).await.unwrap().id);
+        }
+        let experiment = Experiment {
+            design: design(3, 1, Randomization::Shuffle),
+            ..test_experiment(sample_ids.clone())
+        };
+        let experiment = sut.create(experiment).await.unwrap();
+        let request = TrialRequest {
+            participant: Some("participant".to_owned()),
+        };
+
+        let first = sut
+            .create_trials(experiment.id.clone(), request.clone())
+            .await
+            .unwrap();
+        let second = sut
+            .create_trials(experiment.id.clone(), request)
+            .await
+            .unwrap();
+
+        assert_eq!(first.ordinal, 0);
+        assert_eq!(second.ordinal, 1);
+        assert_eq!(first.trials.len(), 6);
+        assert_eq!(
+            first.trials,
+            generate_trials(&experiment.design, &sample_ids, 7, 0)
+        );
+        assert_eq!(sut.trials(experiment.id.clone()).await.unwrap().len(), 2);
+
+        let result = |sequence_id: String| ExperimentResult {
+            training: false,
+            user: String::default(),
+            sequence_id: Some(sequence_id),
+            sample_results: vec![SampleResult {
+                sample_id: sample_ids[0].clone(),
+                azimuth: 0.0,
+                elevation: 0.0,
+                distance: None,
+                speaker_id: None,
+            }],
+        };
+        let linked = sut
+            .create_result(experiment.id.clone(), result(first.id.clone()))
+            .await
+            .unwrap();
+        let foreign = sut
+            .create_result(experiment.id, result("missing".to_owned()))
+            .await;
+        assert_eq!(linked.sequence_id, Some(first.id.clone()));
+        assert!(matches!(foreign, Err(RepoError::Trials(_))));
+    }
+
+    #[tokio::test]
+    async fn concurrent_trials() {
+        let surreal = surreal_in_memory().await;
+        let sut = ExperimentRepository {
+            surreal: surreal.clone(),
+        };
+        let experiment = sut.create(test_experiment(vec![])).await.unwrap();
+
+        let draws = (0..4)
+            .map(|_| {
+                let sut = ExperimentRepository {
+                    surreal: surreal.clone(),
+                };
+                let id = experiment.id.clone();
+                tokio::spawn(async move { sut.create_trials(id, TrialRequest::default()).await })
+            })
+            .collect::<Vec<_>>();
+        let mut ordinals = Vec::new();
+        for draw in draws {
+            ordinals.push(draw.await.unwrap().unwrap().ordinal);
+        }
+
+        ordinals.sort();
+        assert_eq!(ordinals, vec![0, 1, 2, 3]);
+    }
+}
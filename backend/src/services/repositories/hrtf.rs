@@ -0,0 +1,274 @@
+//! HRTF sets uploaded as SOFA files, used for binaural rendering of samples
+
+use std::io::Read;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::services::{
+    audio::{
+        binaural::{probe_sofa, HrtfFormat},
+        AudioError,
+    },
+    database::{
+        error::ValidateDbResponse,
+        identified::{Identified, StringIdentified, TryIntoStringId},
+        surreal::{Database, MapToNotFound},
+    },
+    file_storage::{FileStorage, StagedFile},
+};
+
+use super::{sample::SampleRepository, RepoResult};
+
+pub struct HrtfRepository {
+    pub database: Database,
+    pub file_storage: FileStorage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HrtfInfo {
+    #[validate(length(min = 1, max = 63))]
+    pub name: String,
+    #[validate(length(max = 1000))]
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Detected on upload, ignored in requests
+    #[serde(default)]
+    pub format: Option<HrtfFormat>,
+    /// SHA3-256 of the SOFA file, used as the stored blob name
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl HrtfRepository {
+    /// Create HRTF set from a staged SOFA file
+    ///
+    /// The file is parsed first and rejected if it cannot be used for rendering.
+    /// Files are stored as blobs shared with samples, the same way as audio files.
+    pub async fn create_staged(
+        &self,
+        mut info: HrtfInfo,
+        mut file: StagedFile,
+    ) -> RepoResult<StringIdentified<HrtfInfo>> {
+        let mut source = file.open_read().await?;
+        let format = tokio::task::spawn_blocking(move || {
+            let mut data = Vec::new();
+            source
+                .read_to_end(&mut data)
+                .map_err(|e| AudioError::Malformed(e.to_string()))?;
+            probe_sofa(&data)
+        })
+        .await
+        .map_err(|e| AudioError::Malformed(e.to_string()))??;
+        let hash = file.hash();
+        info.format = Some(format);
+        info.hash = Some(hash.clone());
+        let mut result = self
+            .database
+            .query("begin")
+            .query("create only hrtf content $info")
+            .query("fn::retain_blob($hash, $size)")
+            .query("commit")
+            .bind(("info", info))
+            .bind(("hash", hash.clone()))
+            .bind(("size", file.size()))
+            .await?
+            .validate()?;
+        let hrtf = result
+            .take::<Option<Identified<HrtfInfo>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        if let Err(e) = file.commit(&hash).await {
+            self.delete(hrtf.id.clone()).await?;
+            Err(e)?
+        }
+        Ok(hrtf)
+    }
+
+    /// List HRTF sets
+    pub async fn infos(&self) -> RepoResult<Vec<StringIdentified<HrtfInfo>>> {
+        let mut result = self
+            .database
+            .query("select * from hrtf order by name")
+            .await?;
+        let sets = result
+            .take::<Vec<Identified<HrtfInfo>>>(0)?
+            .try_into_string_id()?;
+        Ok(sets)
+    }
+
+    /// Get HRTF set info
+    pub async fn info(&self, id: String) -> RepoResult<StringIdentified<HrtfInfo>> {
+        let mut result = self
+            .database
+            .query("select * from only hrtf where record::id(id) is $hrtf_id limit 1")
+            .bind(("hrtf_id", id))
+            .await?;
+        let hrtf = result
+            .take::<Option<Identified<HrtfInfo>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(hrtf)
+    }
+
+    /// Get HRTF set info with the content of its SOFA file
+    pub async fn data(&self, id: String) -> RepoResult<(StringIdentified<HrtfInfo>, bytes::Bytes)> {
+        let hrtf = self.info(id).await?;
+        let data = self.file_storage.get(&hrtf.hash.clone().found()?).await?;
+        Ok((hrtf, data))
+    }
+
+    /// Delete HRTF set, missing sets count as deleted
+    ///
+    /// Samples rendered with the set keep its identifier.
+    pub async fn delete(&self, id: String) -> RepoResult {
+        self.database
+            .query("begin")
+            .query("let $hrtf = select id, hash from only hrtf where record::id(id) is $hrtf_id limit 1")
+            .query(
+                r"
+                if $hrtf is not none {
+                    delete $hrtf.id;
+                    if $hrtf.hash is not none {
+                        fn::release_blob($hrtf.hash);
+                    };
+                };
+                ",
+            )
+            .query("commit")
+            .bind(("hrtf_id", id))
+            .await?
+            .validate()?;
+        SampleRepository {
+            database: self.database.clone(),
+            file_storage: self.file_storage.clone(),
+        }
+        .remove_released_files()
+        .await
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for HrtfRepository
+where
+    Database: FromRef<S>,
+    FileStorage: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(_: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            database: Database::from_ref(state),
+            file_storage: FileStorage::from_ref(state),
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::services::{
+        audio::binaural::tests::SOFA,
+        database::{
+            error::DbError,
+            surreal::{tests::surreal_in_memory, Database},
+        },
+        file_storage::{tests::memory_storage, FileStorage},
+        repositories::{IsViolatingUnique, RepoError},
+    };
+
+    use super::{HrtfInfo, HrtfRepository};
+
+    pub async fn create_hrtf(
+        database: &Database,
+        file_storage: &FileStorage,
+        name: &str,
+    ) -> String {
+        let sut = HrtfRepository {
+            database: database.clone(),
+            file_storage: file_storage.clone(),
+        };
+        let info = HrtfInfo {
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        let file = file_storage.stage_bytes(SOFA.into()).await.unwrap();
+        sut.create_staged(info, file).await.unwrap().id
+    }
+
+    async fn setup() -> HrtfRepository {
+        HrtfRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        }
+    }
+
+    #[tokio::test]
+    async fn create() {
+        let sut = setup().await;
+
+        let id = create_hrtf(&sut.database, &sut.file_storage, "kemar").await;
+
+        let (hrtf, data) = sut.data(id).await.unwrap();
+        assert_eq!(hrtf.name, "kemar");
+        assert_eq!(hrtf.format.as_ref().unwrap().measurements, 8);
+        assert_eq!(data, SOFA);
+        assert_eq!(sut.infos().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_invalid() {
+        let sut = setup().await;
+        let info = HrtfInfo {
+            name: "broken".to_owned(),
+            ..Default::default()
+        };
+        let file = sut
+            .file_storage
+            .stage_bytes(bytes::Bytes::from_static(b"not sofa"))
+            .await
+            .unwrap();
+
+        let result = sut.create_staged(info, file).await;
+
+        assert!(matches!(result, Err(RepoError::Audio(_))));
+        assert!(sut.infos().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn create_same_name() {
+        let sut = setup().await;
+        create_hrtf(&sut.database, &sut.file_storage, "kemar").await;
+        let info = HrtfInfo {
+            name: "kemar".to_owned(),
+            ..Default::default()
+        };
+        let file = sut.file_storage.stage_bytes(SOFA.into()).await.unwrap();
+
+        let result = sut.create_staged(info, file).await;
+
+        assert!(result.is_violating_unique());
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let sut = setup().await;
+        let id = create_hrtf(&sut.database, &sut.file_storage, "kemar").await;
+        let hash = sut.info(id.clone()).await.unwrap().hash.clone().unwrap();
+
+        sut.delete(id.clone()).await.unwrap();
+
+        assert!(matches!(
+            sut.info(id).await,
+            Err(RepoError::Database(DbError::NotFound))
+        ));
+        assert!(sut.file_storage.metadata(&hash).await.is_err());
+    }
+}
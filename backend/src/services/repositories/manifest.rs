@@ -0,0 +1,228 @@
+//! Manifests describing samples in ZIP archives
+//!
+//! An archive holds audio files and a `manifest.csv` or `manifest.json` at its root, with one row per sample:
+//!
+//! ```csv
+//! file,name,azimuth,elevation,distance,tags,description
+//! front.wav,Front,0,0,1.5,"noise,hrtf",
+//! ```
+//!
+//! In JSON the rows are objects with the same fields, tags are an array of strings.
+//! Distance, tags and description are optional, a file can be shared by many rows.
+
+use serde::{Deserialize, Serialize};
+
+use super::{sample::SampleInfo, tag::parse_tags};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ManifestFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ManifestFormat {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Csv => "manifest.csv",
+            ManifestFormat::Json => "manifest.json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestRow {
+    /// Path of the audio file inside the archive
+    pub file: String,
+    pub name: String,
+    pub azimuth: f32,
+    pub elevation: f32,
+    #[serde(default)]
+    pub distance: Option<f32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl ManifestRow {
+    pub fn new(file: String, info: SampleInfo) -> Self {
+        Self {
+            file,
+            name: info.name,
+            azimuth: info.azimuth,
+            elevation: info.elevation,
+            distance: info.distance,
+            tags: info.tags,
+            description: info.description,
+        }
+    }
+
+    pub fn info(&self) -> SampleInfo {
+        SampleInfo {
+            name: self.name.clone(),
+            azimuth: self.azimuth,
+            elevation: self.elevation,
+            distance: self.distance,
+            tags: self.tags.clone(),
+            description: self.description.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// CSV row, tags are a single comma separated field
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    file: String,
+    name: String,
+    azimuth: f32,
+    elevation: f32,
+    #[serde(default)]
+    distance: Option<f32>,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl From<CsvRow> for ManifestRow {
+    fn from(row: CsvRow) -> Self {
+        Self {
+            file: row.file,
+            name: row.name,
+            azimuth: row.azimuth,
+            elevation: row.elevation,
+            distance: row.distance,
+            tags: parse_tags(&row.tags),
+            description: row
+                .description
+                .filter(|description| !description.is_empty()),
+        }
+    }
+}
+
+impl From<ManifestRow> for CsvRow {
+    fn from(row: ManifestRow) -> Self {
+        Self {
+            file: row.file,
+            name: row.name,
+            azimuth: row.azimuth,
+            elevation: row.elevation,
+            distance: row.distance,
+            tags: row.tags.join(","),
+            description: row.description,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    #[error("Invalid CSV manifest: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Invalid JSON manifest: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+pub fn parse_manifest(
+    format: ManifestFormat,
+    data: &[u8],
+) -> Result<Vec<ManifestRow>, ManifestError> {
+    match format {
+        ManifestFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data);
+            let rows = reader
+                .deserialize::<CsvRow>()
+                .map(|row| row.map(ManifestRow::from))
+                .collect::<Result<_, _>>()?;
+            Ok(rows)
+        }
+        ManifestFormat::Json => Ok(serde_json::from_slice(data)?),
+    }
+}
+
+pub fn write_manifest(
+    format: ManifestFormat,
+    rows: Vec<ManifestRow>,
+) -> Result<Vec<u8>, ManifestError> {
+    match format {
+        ManifestFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in rows {
+                writer.serialize(CsvRow::from(row))?;
+            }
+            Ok(writer
+                .into_inner()
+                .map_err(|e| csv::Error::from(e.into_error()))?)
+        }
+        ManifestFormat::Json => Ok(serde_json::to_vec_pretty(&rows)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_manifest, write_manifest, ManifestFormat, ManifestRow};
+
+    fn row() -> ManifestRow {
+        ManifestRow {
+            file: "stimuli/front.wav".to_owned(),
+            name: "Front".to_owned(),
+            azimuth: 0.0,
+            elevation: 15.0,
+            distance: Some(1.5),
+            tags: vec!["hrtf".to_owned(), "noise".to_owned()],
+            description: None,
+        }
+    }
+
+    #[test]
+    fn parse_csv() {
+        let csv = "file, name, azimuth, elevation, distance, tags, description\n\
+                   stimuli/front.wav, Front, 0, 15, 1.5,\"noise, HRTF\",\n\
+                   back.wav,Back,180,0,,,behind\n";
+
+        let rows = parse_manifest(ManifestFormat::Csv, csv.as_bytes()).unwrap();
+
+        assert_eq!(rows[0], row());
+        assert_eq!(rows[1].distance, None);
+        assert!(rows[1].tags.is_empty());
+        assert_eq!(rows[1].description.as_deref(), Some("behind"));
+    }
+
+    #[test]
+    fn parse_invalid_csv() {
+        let csv = "file,name,azimuth,elevation\nfront.wav,Front,ahead,0\n";
+
+        assert!(parse_manifest(ManifestFormat::Csv, csv.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_json() {
+        let json = r#"[{
+            "file": "stimuli/front.wav",
+            "name": "Front",
+            "azimuth": 0,
+            "elevation": 15,
+            "distance": 1.5,
+            "tags": ["hrtf", "noise"]
+        }]"#;
+
+        let rows = parse_manifest(ManifestFormat::Json, json.as_bytes()).unwrap();
+
+        assert_eq!(rows, vec![row()]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        for format in [ManifestFormat::Csv, ManifestFormat::Json] {
+            let data = write_manifest(format, vec![row()]).unwrap();
+
+            let rows = parse_manifest(format, &data).unwrap();
+
+            assert_eq!(rows, vec![row()]);
+        }
+    }
+}
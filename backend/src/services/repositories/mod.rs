@@ -1,10 +1,29 @@
 use super::{
+    audio::AudioError,
     database::{error::DbError, identified::IdConversionError},
     file_storage::FsError,
 };
 
+pub mod consistency;
 pub mod experiment;
+pub mod experiment_sessions;
+pub mod experiment_trials;
+pub mod hrtf;
+pub mod manifest;
+pub mod position;
 pub mod sample;
+pub mod sample_ambisonics;
+pub mod sample_binaural;
+pub mod sample_export;
+pub mod sample_import;
+pub mod sample_loudness;
+pub mod sample_preview;
+pub mod sample_processing;
+pub mod sample_search;
+pub mod sample_synth;
+pub mod sample_variants;
+pub mod speaker_layout;
+pub mod tag;
 pub mod user;
 
 #[derive(Debug, thiserror::Error)]
@@ -15,6 +34,17 @@ pub enum RepoError {
     Database(#[from] DbError),
     #[error("Id Covnersion: {0}")]
     IdConversion(#[from] IdConversionError),
+    #[error("Audio: {0}")]
+    Audio(#[from] AudioError),
+    /// Reference to a speaker layout or speaker which does not exist
+    #[error("Speaker: {0}")]
+    Speaker(String),
+    /// Result for an experiment which does not take results now
+    #[error("Closed: {0}")]
+    Closed(String),
+    /// Trial sequence which does not belong to the experiment
+    #[error("Trials: {0}")]
+    Trials(String),
 }
 
 impl From<surrealdb::Error> for RepoError {
@@ -0,0 +1,89 @@
+//! Positions of sound sources around the listener
+//!
+//! - Azimuth in degrees, 0° straight ahead and increasing clockwise as seen from above, so 90° is on the listener's right.
+//!   Stored normalized into the `[0, 360)` range.
+//! - Elevation in degrees from -90° below the listener to 90° above, 0° at ear level.
+//! - Distance in meters from the center of the listener's head, optional.
+
+use std::borrow::Cow;
+
+use validator::ValidationError;
+
+/// Wrap any finite azimuth into `[0, 360)`
+pub fn normalize_azimuth(azimuth: f32) -> f32 {
+    let azimuth = azimuth.rem_euclid(360.0);
+    // Tiny negative angles round up to a full turn
+    if azimuth >= 360.0 {
+        0.0
+    } else {
+        azimuth
+    }
+}
+
+pub fn validate_azimuth(azimuth: f32) -> Result<(), ValidationError> {
+    if azimuth.is_finite() {
+        Ok(())
+    } else {
+        Err(error(
+            "azimuth",
+            "Azimuth must be a finite number of degrees",
+        ))
+    }
+}
+
+pub fn validate_elevation(elevation: f32) -> Result<(), ValidationError> {
+    if (-90.0..=90.0).contains(&elevation) {
+        Ok(())
+    } else {
+        Err(error(
+            "elevation",
+            "Elevation must be between -90 and 90 degrees",
+        ))
+    }
+}
+
+pub fn validate_distance(distance: f32) -> Result<(), ValidationError> {
+    if distance.is_finite() && distance > 0.0 {
+        Ok(())
+    } else {
+        Err(error(
+            "distance",
+            "Distance must be a positive number of meters",
+        ))
+    }
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_azimuth, validate_azimuth, validate_distance, validate_elevation};
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_azimuth(0.0), 0.0);
+        assert_eq!(normalize_azimuth(345.0), 345.0);
+        assert_eq!(normalize_azimuth(360.0), 0.0);
+        assert_eq!(normalize_azimuth(720.0), 0.0);
+        assert_eq!(normalize_azimuth(-90.0), 270.0);
+        assert!(normalize_azimuth(-1e-9) < 360.0);
+    }
+
+    #[test]
+    fn validate() {
+        validate_azimuth(-90.0).unwrap();
+        validate_azimuth(f32::NAN).unwrap_err();
+        validate_azimuth(f32::INFINITY).unwrap_err();
+        validate_elevation(-90.0).unwrap();
+        validate_elevation(90.0).unwrap();
+        validate_elevation(91.0).unwrap_err();
+        validate_elevation(f32::NAN).unwrap_err();
+        validate_distance(1.5).unwrap();
+        validate_distance(0.0).unwrap_err();
+        validate_distance(f32::INFINITY).unwrap_err();
+    }
+}
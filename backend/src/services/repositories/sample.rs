@@ -5,21 +5,39 @@ use axum::{
     extract::{FromRef, FromRequestParts},
     http::request::Parts,
 };
-use bytes::Bytes;
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
-use tracing::warn;
+use surrealdb::sql::Thing;
+use tokio::runtime::Handle;
+use tokio_util::io::SyncIoBridge;
+use tracing::{info, warn};
 use validator::Validate;
 
 use crate::services::{
+    audio::{
+        ambisonics::Ambisonics,
+        decode::{decode_stream, DecodedAudio},
+        probe::{probe, AudioFormat},
+        synth::Stimulus,
+        AudioError, AudioResult,
+    },
     database::{
+        error::ValidateDbResponse,
         identified::{Identified, StringIdentified, TryIntoStringId},
         surreal::{Database, MapToNotFound},
     },
-    file_storage::FileStorage,
+    file_storage::{cache_name, CachedFile, FileMetadata, FileStorage, StagedFile},
 };
 
-use super::RepoResult;
+use super::{
+    experiment::ExperimentReference,
+    position::{normalize_azimuth, validate_azimuth, validate_distance, validate_elevation},
+    sample_binaural::BinauralSource,
+    sample_processing::Derivation,
+    speaker_layout::{SpeakerBinding, SpeakerLayout, SpeakerLayoutRepository},
+    tag::{normalize_tags, validate_tags},
+    RepoError, RepoResult,
+};
 
 pub struct SampleRepository {
     pub database: Database,
@@ -27,23 +45,210 @@ pub struct SampleRepository {
 }
 
 impl SampleRepository {
-    /// Create sample
-    pub async fn create(
+    /// Create sample from a staged file
+    ///
+    /// Audio data is decoded first, rejected if unsupported or corrupt, and its format is stored with the sample.
+    /// Files are stored under their content hash, identical files are shared between samples.
+    /// The file is moved into place only after the sample is inserted into the database.
+    pub async fn create_staged(
         &self,
-        info: SampleInfo,
-        data: Bytes,
-    ) -> RepoResult<StringIdentified<SampleInfo>> {
+        mut info: SampleInfo,
+        file: StagedFile,
+    ) -> RepoResult<CreatedSample> {
+        info.generator = None;
+        info.binaural = None;
+        info.derivation = None;
+        self.insert_staged(info, file).await
+    }
+
+    /// Create sample from a staged file, keeping the generator parameters, binaural source and derivation of the info
+    pub(super) async fn insert_staged(
+        &self,
+        mut info: SampleInfo,
+        mut file: StagedFile,
+    ) -> RepoResult<CreatedSample> {
+        let format = Self::probe_staged(&mut file).await?;
+        check_ambisonics(info.ambisonics, &format)?;
+        if let Some(binding) = info.speaker.as_ref() {
+            let speaker = self.layouts().speaker(binding).await?;
+            info.azimuth = speaker.azimuth;
+            info.elevation = speaker.elevation;
+            info.distance = speaker.distance;
+        }
+        let hash = file.hash();
+        info.azimuth = normalize_azimuth(info.azimuth);
+        info.tags = normalize_tags(info.tags);
+        info.format = Some(format);
+        info.hash = Some(hash.clone());
         let mut result = self
             .database
+            .query("select value name from sample where hash is $hash")
+            .query("begin")
             .query("create only sample content $info")
-            .bind(("info", info.clone()))
+            .query("fn::retain_blob($hash, $size)")
+            .query("commit")
+            .bind(("info", info))
+            .bind(("hash", hash.clone()))
+            .bind(("size", file.size()))
+            .await?
+            .validate()?;
+        let duplicates = result.take::<Vec<String>>(0)?;
+        let sample = result
+            .take::<Option<Identified<SampleInfo>>>(1)?
+            .found()?
+            .try_into_string_id()?;
+        // Overwriting a blob that already exists is harmless, the content is the same
+        if let Err(e) = file.commit(&hash).await {
+            self.delete(sample.id.clone()).await?;
+            Err(e)?
+        }
+        Ok(CreatedSample { sample, duplicates })
+    }
+
+    /// Decode a staged file, rejecting unsupported or corrupt audio
+    pub(super) async fn probe_staged(file: &mut StagedFile) -> RepoResult<AudioFormat> {
+        let source = file.open_read().await?;
+        let format = tokio::task::spawn_blocking(move || probe(Box::new(source)))
+            .await
+            .map_err(|e| AudioError::Malformed(e.to_string()))??;
+        Ok(format)
+    }
+
+    /// Update editable fields of a sample
+    ///
+    /// The speaker and the Ambisonic order are checked in the same transaction as the update.
+    pub async fn update_info(
+        &self,
+        id: String,
+        mut update: SampleInfoUpdate,
+    ) -> RepoResult<StringIdentified<SampleInfo>> {
+        update.azimuth = update.azimuth.map(normalize_azimuth);
+        update.tags = update.tags.map(normalize_tags);
+        // A position set without a speaker detaches the sample from its speaker
+        let detach = update.speaker.is_none()
+            && (update.azimuth.is_some()
+                || update.elevation.is_some()
+                || update.distance.is_some());
+        let binding = update.speaker.clone();
+        let ambisonics = update.ambisonics;
+        let mut result = self
+            .database
+            .query("begin")
+            .query("let $sample = select * from only sample where record::id(id) is $sample_id limit 1")
+            .query("let $layout = if $binding is none { none } else { (select * from only speaker_layout where record::id(id) is $binding.layoutId limit 1) }")
+            .query("let $speaker = $layout.speakers[where id is $binding.speakerId][0]")
+            .query("if $sample is none or ($binding is not none and $speaker is none) or ($channels is not none and $sample.format is not none and $sample.format.channels is not $channels) { throw 'The sample was not changed' }")
+            .query("update $sample.id merge $update return none")
+            .query(
+                r"
+                update $sample.id set
+                    azimuth = if $speaker is none { azimuth } else { $speaker.azimuth },
+                    elevation = if $speaker is none { elevation } else { $speaker.elevation },
+                    distance = $speaker.distance ?? distance,
+                    speaker = if $detach { none } else { speaker }
+                    return none;
+                ",
+            )
+            .query("commit")
+            .query("return $sample")
+            .query("return $layout")
+            .query("select * from only sample where record::id(id) is $sample_id limit 1")
+            .bind(("sample_id", id))
+            .bind(("binding", binding.clone()))
+            .bind(("channels", ambisonics.map(|a| a.channels())))
+            .bind(("detach", detach))
+            .bind(("update", update))
             .await?;
-        let sample = result.take::<Option<Identified<SampleInfo>>>(0)?;
-        let sample = sample.found()?.try_into_string_id()?;
-        self.file_storage.create(&sample.id, data).await?;
+        let sample = result.take::<Option<Identified<SampleInfo>>>(6)?.found()?;
+        if let Some(binding) = binding.as_ref() {
+            result
+                .take::<Option<Identified<SpeakerLayout>>>(7)?
+                .ok_or_else(|| {
+                    RepoError::Speaker(format!(
+                        "Speaker layout `{}` does not exist",
+                        binding.layout_id
+                    ))
+                })?
+                .bound_speaker(binding)?;
+        }
+        if let Some(format) = sample.format.as_ref() {
+            check_ambisonics(ambisonics, format)?;
+        }
+        let sample = result
+            .validate()?
+            .take::<Option<Identified<SampleInfo>>>(8)?
+            .found()?
+            .try_into_string_id()?;
         Ok(sample)
     }
 
+    /// Replace the audio of a sample with a staged file
+    ///
+    /// Refused when results were already collected for the sample, as they refer to the current audio.
+    /// Generator parameters and binaural source of the sample are removed, as they no longer describe its audio.
+    /// The Ambisonic order is kept only if the new audio has the matching number of channels.
+    /// The previous file is released the same way as on deletion.
+    pub async fn replace_data(
+        &self,
+        id: String,
+        mut file: StagedFile,
+    ) -> RepoResult<SampleReplacement> {
+        let format = Self::probe_staged(&mut file).await?;
+        let hash = file.hash();
+        let mut result = self
+            .database
+            .query("select value name from sample where hash is $hash and record::id(id) is not $sample_id")
+            .query("begin")
+            .query("let $sample = select id, hash, ambisonics from only sample where record::id(id) is $sample_id limit 1")
+            .query("let $experiments = select in.id as id, in.name as name from experiment_sample where out is $sample.id and array::len(->sample_result) > 0")
+            .query(
+                r"
+                if $sample is not none and array::len($experiments) == 0 {
+                    let $kept = if $sample.ambisonics.order == $order { $sample.ambisonics } else { none };
+                    update $sample.id set format = $format, hash = $hash, generator = none, binaural = none, derivation = none, ambisonics = $kept;
+                    fn::retain_blob($hash, $size);
+                    if $sample.hash is not none {
+                        fn::release_blob($sample.hash);
+                    };
+                };
+                ",
+            )
+            .query("commit")
+            .query("return $experiments")
+            .query("select * from only sample where record::id(id) is $sample_id limit 1")
+            .bind(("sample_id", id))
+            .bind((
+                "order",
+                Ambisonics::from_channels(format.channels as usize).map(|a| a.order),
+            ))
+            .bind(("format", format))
+            .bind(("hash", hash.clone()))
+            .bind(("size", file.size()))
+            .await?
+            .validate()?;
+        let duplicates = result.take::<Vec<String>>(0)?;
+        let experiments = result
+            .take::<Vec<Identified<ExperimentReference>>>(4)?
+            .try_into_string_id()?;
+        let sample = result
+            .take::<Option<Identified<SampleInfo>>>(5)?
+            .found()?
+            .try_into_string_id()?;
+        if !experiments.is_empty() {
+            return Ok(SampleReplacement::HasResults(experiments));
+        }
+        if let Err(e) = file.commit(&hash).await {
+            // The sample now points at a blob without a file, the consistency check reports it
+            self.remove_released_files().await?;
+            Err(e)?
+        }
+        self.remove_released_files().await?;
+        Ok(SampleReplacement::Replaced(Box::new(CreatedSample {
+            sample,
+            duplicates,
+        })))
+    }
+
     /// List sample infos
     pub async fn infos(&self) -> RepoResult<Vec<StringIdentified<SampleInfo>>> {
         let mut result = self.database.query("select * from sample").await?;
@@ -53,50 +258,328 @@ impl SampleRepository {
         Ok(samples)
     }
 
-    /// Delete sample
-    pub async fn delete(&self, id: String) -> RepoResult<bool> {
+    /// Get sample info
+    pub async fn info(&self, id: String) -> RepoResult<StringIdentified<SampleInfo>> {
         let mut result = self
             .database
+            .query("select * from only sample where record::id(id) is $sample_id limit 1")
+            .bind(("sample_id", id))
+            .await?;
+        let sample = result
+            .take::<Option<Identified<SampleInfo>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(sample)
+    }
+
+    /// Delete sample unless it is used by an experiment
+    ///
+    /// References are checked in the same transaction as the deletion.
+    /// The stored file is removed after the transaction commits, once no other sample references it.
+    pub async fn delete(&self, id: String) -> RepoResult<SampleDeletion> {
+        let mut result = self
+            .database
+            .query("begin")
+            .query("let $sample = select id, hash from only sample where record::id(id) is $sample_id limit 1")
+            .query("let $experiments = select in.id as id, in.name as name from experiment_sample where out is $sample.id")
             .query(
-                "select count() from experiment_sample where record::id(out) is $sample_id group all",
+                r"
+                if $sample is not none and array::len($experiments) == 0 {
+                    delete $sample.id;
+                    if $sample.hash is not none {
+                        fn::release_blob($sample.hash);
+                    };
+                };
+                ",
             )
-            .bind(("sample_id", id.clone()))
+            .query("commit")
+            .query("return $experiments")
+            .bind(("sample_id", id))
+            .await?
+            .validate()?;
+        let experiments = result
+            .take::<Vec<Identified<ExperimentReference>>>(3)?
+            .try_into_string_id()?;
+        if !experiments.is_empty() {
+            return Ok(SampleDeletion::InUse(experiments));
+        }
+        self.remove_released_files().await?;
+        Ok(SampleDeletion::Deleted)
+    }
+
+    /// Remove files of blobs released by deleted samples
+    ///
+    /// Deletions leave a tombstone for every released blob, which is processed here.
+    /// The file is moved aside before the tombstone is removed and put back if the same audio was uploaded again meanwhile.
+    pub async fn remove_released_files(&self) -> RepoResult {
+        let mut result = self
+            .database
+            .query("select value record::id(id) from tombstone")
             .await?;
-        let relations_count: Option<usize> = result.take("count")?;
-        if relations_count.unwrap_or(0) > 0 {
-            return Ok(false);
+        let hashes = result.take::<Vec<String>>(0)?;
+        for hash in hashes {
+            let removed = format!("{hash}.removed");
+            let moved = match self.file_storage.rename(&hash, &removed).await {
+                Ok(()) => true,
+                // Moved aside by an interrupted or concurrent cleanup
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    self.file_storage.metadata(&removed).await.is_ok()
+                }
+                Err(e) => Err(e)?,
+            };
+            let mut result = self
+                .database
+                .query("begin")
+                .query("select value id from type::thing('blob', $hash)")
+                .query("delete type::thing('tombstone', $hash)")
+                .query("commit")
+                .bind(("hash", hash.clone()))
+                .await?
+                .validate()?;
+            let blob = result.take::<Option<Thing>>(0)?;
+            if !moved {
+                continue;
+            }
+            let cleanup = match blob {
+                // The content is identical, putting it back is always correct
+                Some(_) => self.file_storage.rename(&removed, &hash).await,
+                None => self.file_storage.delete(&removed).await,
+            };
+            cleanup.or_else(|err| {
+                if err.kind() == ErrorKind::NotFound {
+                    warn!("{}", err);
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            })?;
         }
-        // NOTE: Race condition
-        // Someone can add this sample to an experiment between the check and deletion.
-        self.file_storage.delete(&id).await.or_else(|err| {
-            if err.kind() == ErrorKind::NotFound {
-                warn!("{}", err);
-                Ok(())
-            } else {
-                Err(err)
+        Ok(())
+    }
+
+    /// Get sample info and metadata of its stored file
+    pub async fn file(&self, id: String) -> RepoResult<SampleFile> {
+        let sample = self.info(id).await?;
+        let blob = sample.hash.clone().found()?;
+        let metadata = self.file_storage.metadata(&blob).await?;
+        Ok(SampleFile {
+            sample,
+            blob,
+            metadata,
+        })
+    }
+
+    fn layouts(&self) -> SpeakerLayoutRepository {
+        SpeakerLayoutRepository {
+            database: self.database.clone(),
+        }
+    }
+
+    /// Get a file derived from the audio stored in a blob, rendering it if needed
+    ///
+    /// Derived files are cached under the hash of the audio, so replacing the audio of a sample renders them again.
+    pub(super) async fn derived(
+        &self,
+        blob: &str,
+        key: &str,
+        render: impl FnOnce(DecodedAudio) -> AudioResult<Vec<u8>> + Send + 'static,
+    ) -> RepoResult<CachedFile> {
+        let name = cache_name(blob, key);
+        if let Some(cached) = self.file_storage.cached(&name).await? {
+            return Ok(cached);
+        }
+
+        let audio = self.decode_blob(blob).await?;
+        let derived = tokio::task::spawn_blocking(move || render(audio))
+            .await
+            .map_err(|e| AudioError::Malformed(e.to_string()))??;
+        let cached = self.file_storage.store_cached(name, derived.into()).await?;
+        Ok(cached)
+    }
+
+    /// Decode the audio stored in a blob while it is read from storage
+    pub(super) async fn decode_blob(&self, blob: &str) -> RepoResult<DecodedAudio> {
+        let size = self.file_storage.metadata(blob).await?.size;
+        let reader = self.file_storage.read(blob, 0..size).await?;
+        let handle = Handle::current();
+        let audio = tokio::task::spawn_blocking(move || {
+            decode_stream(SyncIoBridge::new_with_handle(reader, handle))
+        })
+        .await
+        .map_err(|e| AudioError::Malformed(e.to_string()))??;
+        Ok(audio)
+    }
+
+    /// Move files stored under sample identifiers to content addressed blobs
+    ///
+    /// Files uploaded before deduplication are named after their sample.
+    /// Samples whose file cannot be moved are logged and tried again on the next start.
+    pub async fn adopt_legacy_files(&self) -> RepoResult {
+        let samples = self.infos().await?;
+        for sample in samples {
+            if let Err(e) = self.adopt_legacy_file(&sample.id).await {
+                warn!({error = ?e}, "Failed to move file of sample `{}`", sample.id);
             }
-        })?;
+        }
+        Ok(())
+    }
+
+    /// Move the file of one sample to its blob
+    ///
+    /// The blob is retained only when the hash of the sample is set, so a file left behind by an interrupted move is moved again without counting it twice.
+    async fn adopt_legacy_file(&self, sample_id: &str) -> RepoResult {
+        let metadata = match self.file_storage.metadata(sample_id).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => Err(e)?,
+        };
+        let hash = self.file_storage.hash(sample_id).await?;
         self.database
-            .query("delete sample where record::id(id) is $sample_id")
-            .bind(("sample_id", id.clone()))
-            .await?;
-        Ok(true)
+            .query("begin")
+            .query("let $sample = select id, hash from only sample where record::id(id) is $sample_id limit 1")
+            .query(
+                r"
+                if $sample is not none and $sample.hash is none {
+                    update $sample.id set hash = $hash;
+                    fn::retain_blob($hash, $size);
+                } else if $sample.hash is not $hash {
+                    throw 'The sample already has other audio';
+                };
+                ",
+            )
+            .query("commit")
+            .bind(("sample_id", sample_id.to_owned()))
+            .bind(("hash", hash.clone()))
+            .bind(("size", metadata.size))
+            .await?
+            .validate()?;
+        self.file_storage.rename(sample_id, &hash).await?;
+        info!("Moved file of sample `{}` to blob `{}`", sample_id, hash);
+        Ok(())
     }
+}
 
-    /// Get sample data
-    pub async fn data(&self, id: String) -> RepoResult<Bytes> {
-        let data = self.file_storage.get(id).await?;
-        Ok(data)
+/// Reject Ambisonic samples whose number of channels does not match their order
+fn check_ambisonics(ambisonics: Option<Ambisonics>, format: &AudioFormat) -> RepoResult {
+    match ambisonics {
+        Some(ambisonics) if ambisonics.channels() != format.channels as usize => {
+            Err(AudioError::Channels(format!(
+                "Ambisonic order {} needs {} channels, the audio has {}",
+                ambisonics.order,
+                ambisonics.channels(),
+                format.channels
+            )))?
+        }
+        _ => Ok(()),
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate)]
+/// Newly created sample along with names of samples that already had identical audio
+#[derive(Debug, Serialize)]
+pub struct CreatedSample {
+    #[serde(flatten)]
+    pub sample: StringIdentified<SampleInfo>,
+    pub duplicates: Vec<String>,
+}
+
+/// Outcome of a sample deletion, missing samples count as deleted
+#[derive(Debug)]
+pub enum SampleDeletion {
+    Deleted,
+    /// Experiments still using the sample
+    InUse(Vec<StringIdentified<ExperimentReference>>),
+}
+
+/// Outcome of an audio replacement
+#[derive(Debug)]
+pub enum SampleReplacement {
+    Replaced(Box<CreatedSample>),
+    /// Experiments with results for the sample
+    HasResults(Vec<StringIdentified<ExperimentReference>>),
+}
+
+/// Sample with its stored file
+pub struct SampleFile {
+    pub sample: StringIdentified<SampleInfo>,
+    pub blob: String,
+    pub metadata: FileMetadata,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct SampleInfo {
     #[validate(length(min = 1, max = 63))]
     pub name: String,
+    /// See [`position`](super::position) for the conventions
+    #[validate(custom = "validate_azimuth")]
     pub azimuth: f32,
+    #[validate(custom = "validate_elevation")]
     pub elevation: f32,
+    /// Meters, missing for samples without a defined distance
+    #[validate(custom = "validate_distance")]
+    #[serde(default)]
+    pub distance: Option<f32>,
+    /// See [`tag`](super::tag) for the normalization
+    #[validate(custom = "validate_tags")]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[validate(length(max = 1000))]
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Detected on upload, ignored in requests
+    #[serde(default)]
+    pub format: Option<AudioFormat>,
+    /// SHA3-256 of the audio file, set on upload and used as the stored blob name
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Parameters of a generated sample, ignored in requests
+    #[serde(default)]
+    pub generator: Option<Box<Stimulus>>,
+    /// Source sample and HRTF set of a binaurally rendered sample, ignored in requests
+    #[serde(default)]
+    pub binaural: Option<Box<BinauralSource>>,
+    /// Parent sample and processing operations of a processed sample, ignored in requests
+    #[serde(default)]
+    pub derivation: Option<Box<Derivation>>,
+    /// Order of an Ambisonic recording, the number of channels must match it
+    #[validate]
+    #[serde(default)]
+    pub ambisonics: Option<Ambisonics>,
+    /// Loudspeaker playing the sample, its position replaces the azimuth, elevation and distance of the sample
+    #[serde(default)]
+    pub speaker: Option<SpeakerBinding>,
+}
+
+/// Editable sample fields, missing ones are left unchanged
+///
+/// Distance, description and Ambisonic order can be set but not removed, tags replace the existing ones.
+/// Binding the sample to a speaker sets its position, setting a position without a speaker unbinds it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleInfoUpdate {
+    #[validate(length(min = 1, max = 63))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[validate(custom = "validate_azimuth")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azimuth: Option<f32>,
+    #[validate(custom = "validate_elevation")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<f32>,
+    #[validate(custom = "validate_distance")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f32>,
+    #[validate(custom = "validate_tags")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[validate(length(max = 1000))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[validate]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ambisonics: Option<Ambisonics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<SpeakerBinding>,
 }
 
 #[async_trait]
@@ -117,28 +600,52 @@ where
 }
 
 #[cfg(test)]
-mod tests {
-    use std::path::PathBuf;
-
+pub mod tests {
     use bytes::Bytes;
+    use surrealdb::sql::Thing;
+    use validator::Validate;
 
     use crate::services::{
-        database::surreal::tests::surreal_in_memory,
-        file_storage::{FileStorage, FileStorageConfig},
+        audio::{ambisonics::Ambisonics, tests::wav_bytes, wav::encode_wav, AudioError},
+        database::{
+            error::{DbError, ValidateDbResponse},
+            identified::StringIdentified,
+            migrator::MigratorConfig,
+            surreal::{tests::surreal_in_memory, Database, DatabaseConfig, MapToNotFound},
+        },
+        file_storage::tests::memory_storage,
         repositories::{
-            experiment::{Experiment, ExperimentRepository},
-            sample::SampleInfo,
+            experiment::{
+                tests::test_experiment, Experiment, ExperimentRepository, ExperimentResult,
+                SampleResult,
+            },
+            sample::{SampleDeletion, SampleInfo, SampleInfoUpdate, SampleReplacement},
+            speaker_layout::{tests::create_ring, SpeakerBinding},
+            IsViolatingUnique, RepoError, RepoResult,
         },
     };
 
     use super::SampleRepository;
 
+    /// Create a sample from data in memory
+    pub async fn create_sample(
+        repo: &SampleRepository,
+        info: SampleInfo,
+        data: Bytes,
+    ) -> RepoResult<StringIdentified<SampleInfo>> {
+        let file = repo.file_storage.stage_bytes(data).await?;
+        Ok(repo.create_staged(info, file).await?.sample)
+    }
+
+    /// Whole stored file of a sample
+    pub async fn sample_data(repo: &SampleRepository, id: String) -> RepoResult<Bytes> {
+        let sample = repo.info(id).await?;
+        Ok(repo.file_storage.get(&sample.hash.clone().found()?).await?)
+    }
+
     async fn setup() -> (SampleRepository, ExperimentRepository) {
         let surreal = surreal_in_memory().await;
-        let file_storage_config = FileStorageConfig {
-            folder: PathBuf::from("./tmp/file_storage"),
-        };
-        let file_storage = FileStorage::setup(&file_storage_config).await.unwrap();
+        let file_storage = memory_storage().await;
 
         (
             SampleRepository {
@@ -156,10 +663,35 @@ mod tests {
             name: "create.mp4".to_owned(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
+        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+
+        let sample = create_sample(&sut, info, data).await.unwrap();
+
+        let format = sample.data.format.unwrap();
+        assert_eq!(format.sample_rate, 8000);
+        assert_eq!(format.channels, 1);
+        assert_eq!(format.bit_depth, Some(16));
+        assert_eq!(format.duration, 0.001);
+        assert_eq!(sample.data.hash.unwrap().len(), 64);
+    }
+
+    #[tokio::test]
+    async fn create_invalid_audio() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "create_invalid_audio.mp4".to_owned(),
+            azimuth: 10.0,
+            elevation: 0.0,
+            ..Default::default()
         };
         let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
 
-        sut.create(info, data).await.unwrap();
+        let result = create_sample(&sut, info, data).await;
+
+        assert!(matches!(result, Err(RepoError::Audio(_))));
+        assert!(sut.infos().await.unwrap().is_empty());
     }
 
     #[tokio::test]
@@ -169,11 +701,14 @@ mod tests {
             name: "create.mp4".to_owned(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        sut.create(info.clone(), data.clone()).await.unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        create_sample(&sut, info.clone(), data.clone())
+            .await
+            .unwrap();
 
-        sut.create(info, data).await.unwrap_err();
+        create_sample(&sut, info, data).await.unwrap_err();
     }
 
     #[tokio::test]
@@ -183,16 +718,18 @@ mod tests {
             name: "all_infos1.mp4".to_owned(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        sut.create(info, data).await.unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        create_sample(&sut, info, data).await.unwrap();
         let info = SampleInfo {
             name: "all_infos2.mp4".to_owned(),
             azimuth: 0.0,
             elevation: 10.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[15, 14, 13, 12, 11, 10, 9, 8]);
-        sut.create(info, data).await.unwrap();
+        let data = wav_bytes(&[15, 14, 13, 12, 11, 10, 9, 8]);
+        create_sample(&sut, info, data).await.unwrap();
 
         let samples = sut.infos().await.unwrap();
 
@@ -206,15 +743,78 @@ mod tests {
             name: "data.mp4".to_owned(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sut.create(info, data.clone()).await.unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sut, info, data.clone()).await.unwrap();
 
-        let result_data = sut.data(sample.id).await.unwrap();
+        let result_data = sample_data(&sut, sample.id).await.unwrap();
 
         assert_eq!(result_data, data);
     }
 
+    #[tokio::test]
+    async fn file() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "file.mp4".to_owned(),
+            azimuth: 10.0,
+            elevation: 0.0,
+            ..Default::default()
+        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sut, info, data.clone()).await.unwrap();
+
+        let file = sut.file(sample.id).await.unwrap();
+
+        assert_eq!(file.sample.name, "file.mp4");
+        assert_eq!(file.metadata.size, data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn create_identical() {
+        let (sut, _) = setup().await;
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let info = SampleInfo {
+            name: "identical1.mp4".to_owned(),
+            ..Default::default()
+        };
+        let first = create_sample(&sut, info, data.clone()).await.unwrap();
+        let info = SampleInfo {
+            name: "identical2.mp4".to_owned(),
+            ..Default::default()
+        };
+        let file = sut.file_storage.stage_bytes(data).await.unwrap();
+
+        let second = sut.create_staged(info, file).await.unwrap();
+
+        assert_eq!(second.duplicates, vec!["identical1.mp4".to_owned()]);
+        assert_eq!(second.sample.hash, first.hash);
+    }
+
+    #[tokio::test]
+    async fn delete_identical() {
+        let (sut, _) = setup().await;
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let info = SampleInfo {
+            name: "identical1.mp4".to_owned(),
+            ..Default::default()
+        };
+        let first = create_sample(&sut, info, data.clone()).await.unwrap();
+        let info = SampleInfo {
+            name: "identical2.mp4".to_owned(),
+            ..Default::default()
+        };
+        let second = create_sample(&sut, info, data.clone()).await.unwrap();
+        let hash = first.hash.clone().unwrap();
+
+        sut.delete(first.id).await.unwrap();
+        assert_eq!(sample_data(&sut, second.id.clone()).await.unwrap(), data);
+
+        sut.delete(second.id).await.unwrap();
+        sut.file_storage.metadata(&hash).await.unwrap_err();
+    }
+
     #[tokio::test]
     async fn delete() {
         let (sut, _) = setup().await;
@@ -222,11 +822,19 @@ mod tests {
             name: "delete.mp4".to_owned(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sut.create(info, data).await.unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sut, info, data).await.unwrap();
+        let hash = sample.hash.clone().unwrap();
+
+        let result = sut.delete(sample.id.clone()).await.unwrap();
 
-        sut.delete(sample.id).await.unwrap();
+        assert!(matches!(result, SampleDeletion::Deleted));
+        sut.info(sample.id).await.unwrap_err();
+        sut.file_storage.metadata(&hash).await.unwrap_err();
+        let mut tombstones = sut.database.query("select * from tombstone").await.unwrap();
+        assert!(tombstones.take::<Vec<Thing>>((0, "id")).unwrap().is_empty());
     }
 
     #[tokio::test]
@@ -236,17 +844,444 @@ mod tests {
             name: "delete.mp4".to_owned(),
             azimuth: 10.0,
             elevation: 0.0,
+            ..Default::default()
         };
-        let data = Bytes::from_static(&[7, 6, 5, 4, 3, 2, 1, 0]);
-        let sample = sut.create(info, data).await.unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sut, info, data).await.unwrap();
+        let experiment = test_experiment(vec![sample.id.clone()]);
+        let experiment = experiment_repo.create(experiment).await.unwrap();
+
+        let result = sut.delete(sample.id.clone()).await.unwrap();
+
+        let SampleDeletion::InUse(experiments) = result else {
+            panic!("Sample attached to an experiment was deleted");
+        };
+        assert_eq!(experiments.len(), 1);
+        assert_eq!(experiments[0].id, experiment.id);
+        assert_eq!(experiments[0].name, "exp-1");
+        assert_eq!(sut.info(sample.id).await.unwrap().name, "delete.mp4");
+    }
+
+    #[tokio::test]
+    async fn remove_released_files_keeps_uploaded_again() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "released.mp4".to_owned(),
+            ..Default::default()
+        };
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let sample = create_sample(&sut, info, data.clone()).await.unwrap();
+        // Tombstone left by a deletion that raced with the upload
+        sut.database
+            .query("create type::thing('tombstone', $hash)")
+            .bind(("hash", sample.hash.clone().unwrap()))
+            .await
+            .unwrap();
+
+        sut.remove_released_files().await.unwrap();
+
+        assert_eq!(sample_data(&sut, sample.id).await.unwrap(), data);
+        let mut tombstones = sut.database.query("select * from tombstone").await.unwrap();
+        assert!(tombstones.take::<Vec<Thing>>((0, "id")).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_info() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "typo.wav".to_owned(),
+            azimuth: 10.0,
+            elevation: 5.0,
+            ..Default::default()
+        };
+        let sample = create_sample(&sut, info, wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]))
+            .await
+            .unwrap();
+        let update = SampleInfoUpdate {
+            name: Some("fixed.wav".to_owned()),
+            azimuth: Some(20.0),
+            ..Default::default()
+        };
+
+        let updated = sut.update_info(sample.id.clone(), update).await.unwrap();
+
+        assert_eq!(updated.name, "fixed.wav");
+        assert_eq!(updated.azimuth, 20.0);
+        assert_eq!(updated.elevation, 5.0);
+        assert_eq!(updated.hash, sample.hash);
+        assert_eq!(updated.format, sample.format);
+    }
+
+    #[tokio::test]
+    async fn update_info_duplicate_name() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "first.wav".to_owned(),
+            ..Default::default()
+        };
+        create_sample(&sut, info, wav_bytes(&[7, 6, 5, 4]))
+            .await
+            .unwrap();
+        let info = SampleInfo {
+            name: "second.wav".to_owned(),
+            ..Default::default()
+        };
+        let second = create_sample(&sut, info, wav_bytes(&[3, 2, 1, 0]))
+            .await
+            .unwrap();
+        let update = SampleInfoUpdate {
+            name: Some("first.wav".to_owned()),
+            ..Default::default()
+        };
+
+        let result = sut.update_info(second.id, update).await;
+
+        assert!(result.is_violating_unique());
+    }
+
+    #[tokio::test]
+    async fn speaker_position() {
+        let (sut, _) = setup().await;
+        let layout_id = create_ring(&sut.database, "ring").await;
+        let binding = |speaker_id: &str| SpeakerBinding {
+            layout_id: layout_id.clone(),
+            speaker_id: speaker_id.to_owned(),
+        };
+        let info = SampleInfo {
+            name: "bound.wav".to_owned(),
+            azimuth: 10.0,
+            speaker: Some(binding("right")),
+            ..Default::default()
+        };
+        let sample = create_sample(&sut, info, wav_bytes(&[1, 2])).await.unwrap();
+        let rebind = SampleInfoUpdate {
+            speaker: Some(binding("left")),
+            ..Default::default()
+        };
+        let moved = SampleInfoUpdate {
+            elevation: Some(30.0),
+            ..Default::default()
+        };
+        let missing = SampleInfoUpdate {
+            speaker: Some(binding("top")),
+            ..Default::default()
+        };
+        let missing_layout = SampleInfoUpdate {
+            speaker: Some(SpeakerBinding {
+                layout_id: "missing".to_owned(),
+                speaker_id: "left".to_owned(),
+            }),
+            ..Default::default()
+        };
+
+        let rebound = sut.update_info(sample.id.clone(), rebind).await.unwrap();
+        let detached = sut.update_info(sample.id.clone(), moved).await.unwrap();
+        let result = sut.update_info(sample.id.clone(), missing).await;
+        let layout_result = sut.update_info(sample.id.clone(), missing_layout).await;
+
+        assert_eq!(sample.azimuth, 90.0);
+        assert_eq!(sample.distance, Some(1.5));
+        assert_eq!(rebound.azimuth, 270.0);
+        assert_eq!(rebound.speaker, Some(binding("left")));
+        assert_eq!(detached.azimuth, 270.0);
+        assert_eq!(detached.elevation, 30.0);
+        assert_eq!(detached.speaker, None);
+        assert!(matches!(result, Err(RepoError::Speaker(_))));
+        assert!(matches!(layout_result, Err(RepoError::Speaker(_))));
+        assert_eq!(sut.info(sample.id).await.unwrap().speaker, None);
+    }
+
+    #[tokio::test]
+    async fn update_info_not_found() {
+        let (sut, _) = setup().await;
+
+        let result = sut
+            .update_info("missing".to_owned(), SampleInfoUpdate::default())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(RepoError::Database(DbError::NotFound))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replace_data() {
+        let (sut, experiment_repo) = setup().await;
+        let info = SampleInfo {
+            name: "replace.wav".to_owned(),
+            ..Default::default()
+        };
+        let sample = create_sample(&sut, info, wav_bytes(&[7, 6, 5, 4]))
+            .await
+            .unwrap();
+        let old_hash = sample.hash.clone().unwrap();
         let experiment = Experiment {
-            name: "exp-1".to_owned(),
-            sample_ids: vec![sample.id.clone()],
-            is_public: false,
+            name: "replace-exp".to_owned(),
+            ..test_experiment(vec![sample.id.clone()])
         };
         experiment_repo.create(experiment).await.unwrap();
+        let data = wav_bytes(&[1, 2, 3, 4, 5, 6]);
+        let file = sut.file_storage.stage_bytes(data.clone()).await.unwrap();
+
+        let result = sut.replace_data(sample.id.clone(), file).await.unwrap();
+
+        let SampleReplacement::Replaced(replaced) = result else {
+            panic!("Sample without results was not replaced");
+        };
+        assert_ne!(replaced.sample.hash, sample.hash);
+        assert_eq!(replaced.sample.format.as_ref().unwrap().duration, 0.00075);
+        assert_eq!(sample_data(&sut, sample.id).await.unwrap(), data);
+        sut.file_storage.metadata(&old_hash).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn ambisonics_channels() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "ambix.wav".to_owned(),
+            ambisonics: Some(Ambisonics { order: 1 }),
+            ..Default::default()
+        };
+        let channels = [0.5; 100];
+        let data = Bytes::from(encode_wav(&[&channels[..]; 4], 48000, 16));
+
+        let mono = create_sample(&sut, info.clone(), wav_bytes(&[7, 6, 5, 4])).await;
+        let sample = create_sample(&sut, info, data).await.unwrap();
+
+        assert!(matches!(
+            mono,
+            Err(RepoError::Audio(AudioError::Channels(_)))
+        ));
+        assert_eq!(sample.ambisonics, Some(Ambisonics { order: 1 }));
+        let file = sut
+            .file_storage
+            .stage_bytes(wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+        let SampleReplacement::Replaced(replaced) =
+            sut.replace_data(sample.id.clone(), file).await.unwrap()
+        else {
+            panic!("Sample without results was not replaced");
+        };
+        assert_eq!(replaced.sample.ambisonics, None);
+        let update = SampleInfoUpdate {
+            name: Some("renamed.wav".to_owned()),
+            ambisonics: Some(Ambisonics { order: 1 }),
+            ..Default::default()
+        };
+        let result = sut.update_info(sample.id.clone(), update).await;
+        assert!(matches!(
+            result,
+            Err(RepoError::Audio(AudioError::Channels(_)))
+        ));
+        assert_eq!(sut.info(sample.id).await.unwrap().name, "ambix.wav");
+    }
+
+    #[tokio::test]
+    async fn replace_data_refused_with_results() {
+        let (sut, experiment_repo) = setup().await;
+        let info = SampleInfo {
+            name: "replace.wav".to_owned(),
+            ..Default::default()
+        };
+        let data = wav_bytes(&[7, 6, 5, 4]);
+        let sample = create_sample(&sut, info, data.clone()).await.unwrap();
+        let experiment = Experiment {
+            name: "replace-exp".to_owned(),
+            ..test_experiment(vec![sample.id.clone()])
+        };
+        let experiment = experiment_repo.create(experiment).await.unwrap();
+        let result = ExperimentResult {
+            training: false,
+            user: "user".to_owned(),
+            sequence_id: None,
+            sample_results: vec![SampleResult {
+                sample_id: sample.id.clone(),
+                azimuth: 0.0,
+                elevation: 0.0,
+                distance: None,
+                speaker_id: None,
+            }],
+        };
+        experiment_repo
+            .create_result(experiment.id.clone(), result)
+            .await
+            .unwrap();
+        let file = sut
+            .file_storage
+            .stage_bytes(wav_bytes(&[1, 2, 3, 4, 5, 6]))
+            .await
+            .unwrap();
+
+        let result = sut.replace_data(sample.id.clone(), file).await.unwrap();
+
+        let SampleReplacement::HasResults(experiments) = result else {
+            panic!("Sample with results was replaced");
+        };
+        assert_eq!(experiments[0].id, experiment.id);
+        assert_eq!(sample_data(&sut, sample.id).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn create_normalizes_position() {
+        let (sut, _) = setup().await;
+        let info = SampleInfo {
+            name: "behind_left.wav".to_owned(),
+            azimuth: -90.0,
+            elevation: 30.0,
+            distance: Some(1.5),
+            ..Default::default()
+        };
+
+        let sample = create_sample(&sut, info, wav_bytes(&[7, 6, 5, 4]))
+            .await
+            .unwrap();
+
+        let sample = sut.info(sample.id.clone()).await.unwrap();
+        assert_eq!(sample.azimuth, 270.0);
+        assert_eq!(sample.elevation, 30.0);
+        assert_eq!(sample.distance, Some(1.5));
+    }
+
+    #[test]
+    fn invalid_position() {
+        let info = SampleInfo {
+            name: "invalid.wav".to_owned(),
+            azimuth: f32::INFINITY,
+            elevation: 91.0,
+            distance: Some(-1.0),
+            ..Default::default()
+        };
+
+        let errors = info.validate().unwrap_err();
+
+        let fields = errors.field_errors();
+        assert!(fields.contains_key("azimuth"));
+        assert!(fields.contains_key("elevation"));
+        assert!(fields.contains_key("distance"));
+    }
+
+    #[tokio::test]
+    async fn positions_migration() {
+        let db = Database::setup(&DatabaseConfig {
+            address: "mem://".to_owned(),
+            namespace: "test".to_owned(),
+            database: "test".to_owned(),
+            migrations: MigratorConfig {
+                directory: "./migrations".into(),
+            },
+        })
+        .await
+        .unwrap();
+        db.query("create sample:old content { name: 'old.wav', azimuth: -15, elevation: 10 }")
+            .query("create sample:full content { name: 'full.wav', azimuth: 720, elevation: -45 }")
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        let migration = std::fs::read_to_string("./migrations/003__positions.surrealql").unwrap();
+        db.query(&migration).await.unwrap().validate().unwrap();
+
+        let mut result = db
+            .query("select value [azimuth, elevation] from [sample:old, sample:full]")
+            .await
+            .unwrap();
+        let positions = result.take::<Vec<Vec<f32>>>(0).unwrap();
+        assert_eq!(positions, vec![vec![345.0, 10.0], vec![0.0, -45.0]]);
+        db.query("update sample:old set azimuth = 400")
+            .await
+            .unwrap()
+            .validate()
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn positions_migration_reports_out_of_range() {
+        let db = Database::setup(&DatabaseConfig {
+            address: "mem://".to_owned(),
+            namespace: "test".to_owned(),
+            database: "test".to_owned(),
+            migrations: MigratorConfig {
+                directory: "./migrations".into(),
+            },
+        })
+        .await
+        .unwrap();
+        db.query("create sample:high content { name: 'high.wav', azimuth: -15, elevation: 100 }")
+            .query("create sample_result:text content { azimuth: 10, elevation: 'up' }")
+            .await
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        let migration = std::fs::read_to_string("./migrations/003__positions.surrealql").unwrap();
+        let error = db
+            .query("begin")
+            .query(migration)
+            .query("commit")
+            .await
+            .unwrap()
+            .validate()
+            .unwrap_err();
+
+        let error = error.to_string();
+        assert!(error.contains("sample:high"), "{error}");
+        assert!(error.contains("sample_result:text"), "{error}");
+        let mut result = db
+            .query("select value [azimuth, elevation] from sample:high")
+            .await
+            .unwrap();
+        let positions = result.take::<Vec<Vec<f32>>>(0).unwrap();
+        assert_eq!(positions, vec![vec![-15.0, 100.0]]);
+    }
+
+    #[tokio::test]
+    async fn adopt_legacy_files() {
+        let (sut, _) = setup().await;
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        sut.database
+            .query("create sample:legacy content { name: 'legacy.wav', azimuth: 0, elevation: 0 }")
+            .await
+            .unwrap();
+        let file = sut.file_storage.stage_bytes(data.clone()).await.unwrap();
+        file.commit("legacy").await.unwrap();
+
+        sut.adopt_legacy_files().await.unwrap();
+        // A move interrupted after the transaction leaves the file behind
+        let file = sut.file_storage.stage_bytes(data.clone()).await.unwrap();
+        file.commit("legacy").await.unwrap();
+        sut.adopt_legacy_files().await.unwrap();
+
+        let sample = sut.info("legacy".to_owned()).await.unwrap();
+        let hash = sample.hash.clone().unwrap();
+        assert_eq!(sample_data(&sut, sample.id).await.unwrap(), data);
+        sut.file_storage.metadata("legacy").await.unwrap_err();
+        let mut result = sut
+            .database
+            .query("select value refs from only type::thing('blob', $hash)")
+            .bind(("hash", hash))
+            .await
+            .unwrap();
+        assert_eq!(result.take::<Option<u64>>(0).unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn adopt_legacy_files_other_audio() {
+        let (sut, _) = setup().await;
+        sut.database
+            .query("create sample:legacy content { name: 'legacy.wav', azimuth: 0, elevation: 0, hash: 'other' }")
+            .await
+            .unwrap();
+        let data = wav_bytes(&[7, 6, 5, 4, 3, 2, 1, 0]);
+        let file = sut.file_storage.stage_bytes(data).await.unwrap();
+        file.commit("legacy").await.unwrap();
+
+        sut.adopt_legacy_files().await.unwrap();
 
-        let result = sut.delete(sample.id).await;
-        assert_eq!(result.unwrap(), false);
+        let sample = sut.info("legacy".to_owned()).await.unwrap();
+        assert_eq!(sample.hash.as_deref(), Some("other"));
+        sut.file_storage.metadata("legacy").await.unwrap();
     }
 }
@@ -0,0 +1,304 @@
+//! Binaural renderings of Ambisonic samples for a head orientation, cached in file storage
+
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use validator::Validate;
+
+use crate::services::{
+    audio::{
+        ambisonics::{render_ambisonics, Decoder, Rotation, Weighting},
+        binaural::{HrtfSet, Interpolation},
+        wav::encode_wav,
+        AudioError,
+    },
+    database::surreal::MapToNotFound,
+    file_storage::{cache_name, CachedFile},
+};
+
+use super::{hrtf::HrtfRepository, sample::SampleRepository, RepoError};
+
+/// Bit depth of rendered files
+const BIT_DEPTH: u16 = 24;
+
+/// Head orientation and decoder of a rendering, all fields except the HRTF set are optional
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct AmbisonicRender {
+    pub hrtf_id: String,
+    /// Degrees, see [`Rotation`] for the conventions
+    #[validate(range(min = -360.0, max = 360.0))]
+    #[serde(default)]
+    pub yaw: f64,
+    #[validate(range(min = -90.0, max = 90.0))]
+    #[serde(default)]
+    pub pitch: f64,
+    #[validate(range(min = -180.0, max = 180.0))]
+    #[serde(default)]
+    pub roll: f64,
+    /// Number of virtual loudspeakers, see [`Decoder`]
+    #[validate(range(min = 4, max = 1024))]
+    #[serde(default)]
+    pub speakers: Option<u16>,
+    #[serde(default)]
+    pub weighting: Weighting,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Gain in dB
+    #[validate(range(min = -60.0, max = 60.0))]
+    #[serde(default)]
+    pub gain: f64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AmbisonicError {
+    #[error("Sample is not an Ambisonic recording")]
+    NotAmbisonic,
+    #[error("Rendering clips at {peak:.1} dBFS, lower the gain")]
+    Clipping { peak: f64 },
+    #[error("{0}")]
+    Repo(#[from] RepoError),
+}
+
+impl From<AudioError> for AmbisonicError {
+    fn from(value: AudioError) -> Self {
+        Self::Repo(value.into())
+    }
+}
+
+impl SampleRepository {
+    /// Render an Ambisonic sample binaurally for a head orientation
+    ///
+    /// Renderings are cached under the hash of the sample audio and a digest of the HRTF file and parameters,
+    /// so replacing the audio renders it again and renderings of removed audio become stale.
+    pub async fn render_ambisonics(
+        &self,
+        id: String,
+        render: AmbisonicRender,
+    ) -> Result<CachedFile, AmbisonicError> {
+        let sample = self.info(id).await?;
+        let ambisonics = sample.ambisonics.ok_or(AmbisonicError::NotAmbisonic)?;
+        let blob = sample.hash.clone().found().map_err(RepoError::from)?;
+        let hrtfs = HrtfRepository {
+            database: self.database.clone(),
+            file_storage: self.file_storage.clone(),
+        };
+        let hrtf = hrtfs.info(render.hrtf_id.clone()).await?;
+        let key = AmbisonicRender {
+            hrtf_id: hrtf.hash.clone().found().map_err(RepoError::from)?,
+            ..render.clone()
+        };
+        let digest = Sha3_256::digest(serde_json::to_vec(&key).expect("Parameters serialize"));
+        let name = cache_name(&blob, &hex::encode(&digest[..16]));
+        if let Some(cached) = self
+            .file_storage
+            .cached(&name)
+            .await
+            .map_err(RepoError::from)?
+        {
+            return Ok(cached);
+        }
+
+        let audio = self.decode_blob(&blob).await?;
+        let (_, sofa) = hrtfs.data(render.hrtf_id.clone()).await?;
+        let rendered = tokio::task::spawn_blocking(move || {
+            if audio.channels.len() != ambisonics.channels() {
+                Err(AudioError::Channels(format!(
+                    "Ambisonic order {} needs {} channels, the audio has {}",
+                    ambisonics.order,
+                    ambisonics.channels(),
+                    audio.channels.len()
+                )))?
+            }
+            let set = HrtfSet::open(&sofa, audio.sample_rate as f32)?;
+            let decoder = Decoder {
+                speakers: render.speakers,
+                weighting: render.weighting,
+                interpolation: render.interpolation,
+            };
+            let rotation = Rotation {
+                yaw: render.yaw,
+                pitch: render.pitch,
+                roll: render.roll,
+            };
+            let mut channels =
+                render_ambisonics(&audio.channels, ambisonics.order, &set, &decoder, &rotation);
+            let gain = 10f64.powf(render.gain / 20.0);
+            channels
+                .iter_mut()
+                .flatten()
+                .for_each(|sample| *sample *= gain);
+            let peak = channels
+                .iter()
+                .flatten()
+                .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+            if peak > 1.0 {
+                return Err(AmbisonicError::Clipping {
+                    peak: 20.0 * peak.log10(),
+                });
+            }
+            Ok(encode_wav(
+                &[&channels[0], &channels[1]],
+                audio.sample_rate,
+                BIT_DEPTH,
+            ))
+        })
+        .await
+        .map_err(|e| AudioError::Malformed(e.to_string()))??;
+
+        let cached = self
+            .file_storage
+            .store_cached(name, rendered.into())
+            .await
+            .map_err(RepoError::from)?;
+        Ok(cached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bytes::Bytes;
+
+    use crate::services::{
+        audio::{
+            ambisonics::{spherical_harmonics, Ambisonics},
+            decode::decode,
+            tests::wav_bytes,
+            wav::encode_wav,
+        },
+        database::{error::DbError, surreal::tests::surreal_in_memory},
+        file_storage::{cache_source, tests::memory_storage},
+        repositories::{
+            hrtf::tests::create_hrtf,
+            sample::{tests::create_sample, SampleInfo, SampleRepository},
+            RepoError,
+        },
+    };
+
+    use super::{AmbisonicError, AmbisonicRender};
+
+    async fn setup() -> (SampleRepository, String, String) {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let hrtf_id = create_hrtf(&sut.database, &sut.file_storage, "kemar").await;
+        // First order plane wave from the left
+        let channels = spherical_harmonics(1, [0.0, 1.0, 0.0])
+            .into_iter()
+            .map(|b| vec![0.25 * b; 480])
+            .collect::<Vec<_>>();
+        let channels = channels.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let info = SampleInfo {
+            name: "ambix.wav".to_owned(),
+            ambisonics: Some(Ambisonics { order: 1 }),
+            ..Default::default()
+        };
+        let data = Bytes::from(encode_wav(&channels, 48000, 24));
+        let sample_id = create_sample(&sut, info, data).await.unwrap().id;
+        (sut, sample_id, hrtf_id)
+    }
+
+    fn render(hrtf_id: &str) -> AmbisonicRender {
+        AmbisonicRender {
+            hrtf_id: hrtf_id.to_owned(),
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 0.0,
+            speakers: None,
+            weighting: Default::default(),
+            interpolation: Default::default(),
+            gain: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn rendered() {
+        let (sut, sample_id, hrtf_id) = setup().await;
+        let hash = sut
+            .info(sample_id.clone())
+            .await
+            .unwrap()
+            .hash
+            .clone()
+            .unwrap();
+
+        let cached = sut
+            .render_ambisonics(sample_id, render(&hrtf_id))
+            .await
+            .unwrap();
+
+        assert_eq!(cache_source(&cached.name), Some(hash.as_str()));
+        let data = sut.file_storage.get(&cached.name).await.unwrap();
+        let audio = decode(Box::new(Cursor::new(data.to_vec()))).unwrap();
+        assert_eq!(audio.channels.len(), 2);
+        assert_eq!(audio.sample_rate, 48000);
+        assert!(audio.channels[0].len() >= 480);
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        let (sut, sample_id, hrtf_id) = setup().await;
+        let turned = AmbisonicRender {
+            yaw: 90.0,
+            ..render(&hrtf_id)
+        };
+
+        let first = sut
+            .render_ambisonics(sample_id.clone(), render(&hrtf_id))
+            .await
+            .unwrap();
+        let second = sut
+            .render_ambisonics(sample_id.clone(), render(&hrtf_id))
+            .await
+            .unwrap();
+        let other = sut.render_ambisonics(sample_id, turned).await.unwrap();
+
+        assert_eq!(first.name, second.name);
+        assert_eq!(first.metadata.modified, second.metadata.modified);
+        assert_ne!(first.name, other.name);
+    }
+
+    #[tokio::test]
+    async fn not_ambisonic() {
+        let (sut, _, hrtf_id) = setup().await;
+        let info = SampleInfo {
+            name: "mono.wav".to_owned(),
+            ..Default::default()
+        };
+        let sample_id = create_sample(&sut, info, wav_bytes(&[7, 6, 5, 4]))
+            .await
+            .unwrap()
+            .id;
+
+        let result = sut.render_ambisonics(sample_id, render(&hrtf_id)).await;
+
+        assert!(matches!(result, Err(AmbisonicError::NotAmbisonic)));
+    }
+
+    #[tokio::test]
+    async fn clipping() {
+        let (sut, sample_id, hrtf_id) = setup().await;
+        let loud = AmbisonicRender {
+            gain: 40.0,
+            ..render(&hrtf_id)
+        };
+
+        let result = sut.render_ambisonics(sample_id, loud).await;
+
+        assert!(matches!(result, Err(AmbisonicError::Clipping { .. })));
+    }
+
+    #[tokio::test]
+    async fn missing_hrtf() {
+        let (sut, sample_id, _) = setup().await;
+
+        let result = sut.render_ambisonics(sample_id, render("missing")).await;
+
+        assert!(matches!(
+            result,
+            Err(AmbisonicError::Repo(RepoError::Database(DbError::NotFound)))
+        ));
+    }
+}
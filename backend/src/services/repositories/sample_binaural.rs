@@ -0,0 +1,366 @@
+//! Samples rendered binaurally from a mono sample and an HRTF set
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use validator::Validate;
+
+use crate::services::{
+    audio::{
+        binaural::{render_binaural, HrtfSet, Interpolation},
+        wav::encode_wav,
+        AudioError,
+    },
+    database::{
+        error::{DbError, ValidateDbResponse},
+        identified::{Identified, TryIntoStringId},
+        surreal::MapToNotFound,
+    },
+    file_storage::StagedFile,
+};
+
+use super::{
+    hrtf::HrtfRepository,
+    non_unique_value_on_index,
+    position::{normalize_azimuth, validate_azimuth, validate_distance, validate_elevation},
+    sample::{CreatedSample, SampleInfo, SampleRepository},
+    tag::{normalize_tags, validate_tags},
+    IsViolatingUnique, RepoError,
+};
+
+/// Bit depth of rendered files
+const BIT_DEPTH: u16 = 24;
+
+/// Positions to render a mono sample at, each one becomes a new sample
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct BinauralRender {
+    pub hrtf_id: String,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Gain in dB applied to every rendered sample, levels between positions are kept
+    #[validate(range(min = -60.0, max = 60.0))]
+    #[serde(default)]
+    pub gain: f64,
+    /// Tags of every rendered sample
+    #[validate(custom = "validate_tags")]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[validate(length(max = 1000))]
+    #[serde(default)]
+    pub description: Option<String>,
+    #[validate(length(min = 1, max = 1000))]
+    #[validate]
+    pub targets: Vec<BinauralTarget>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct BinauralTarget {
+    #[validate(length(min = 1, max = 63))]
+    pub name: String,
+    #[validate(custom = "validate_azimuth")]
+    pub azimuth: f32,
+    #[validate(custom = "validate_elevation")]
+    pub elevation: f32,
+    /// Median distance of the HRTF measurements if missing
+    #[validate(custom = "validate_distance")]
+    #[serde(default)]
+    pub distance: Option<f32>,
+}
+
+/// Origin of a binaurally rendered sample
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BinauralSource {
+    pub sample_id: String,
+    pub hrtf_id: String,
+    pub interpolation: Interpolation,
+    pub gain: f64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BinauralError {
+    #[error("Source sample must have a single channel, it has {0}")]
+    NotMono(usize),
+    #[error("Rendered sample `{name}` clips at {peak:.1} dBFS, lower the gain")]
+    Clipping { name: String, peak: f64 },
+    #[error("{0}")]
+    Repo(#[from] RepoError),
+}
+
+impl From<AudioError> for BinauralError {
+    fn from(value: AudioError) -> Self {
+        Self::Repo(value.into())
+    }
+}
+
+impl<T> IsViolatingUnique<T> for Result<T, BinauralError> {
+    fn is_violating_unique(&self) -> bool {
+        matches!(self, Err(BinauralError::Repo(RepoError::Database(DbError::Query(e)))) if non_unique_value_on_index(e))
+    }
+}
+
+impl SampleRepository {
+    /// Render a mono sample at the target positions with an HRTF set
+    ///
+    /// All targets are rendered before anything is written, then the samples are created in one transaction.
+    /// Rendered samples keep the positions of their targets and record the source sample and HRTF set.
+    pub async fn render_binaural(
+        &self,
+        source_id: String,
+        render: BinauralRender,
+    ) -> Result<Vec<CreatedSample>, BinauralError> {
+        let source = self.file(source_id).await?;
+        let audio = self.decode_blob(&source.blob).await?;
+        let hrtfs = HrtfRepository {
+            database: self.database.clone(),
+            file_storage: self.file_storage.clone(),
+        };
+        let (hrtf, sofa) = hrtfs.data(render.hrtf_id.clone()).await?;
+
+        let targets = render.targets.clone();
+        let (interpolation, gain) = (render.interpolation, render.gain);
+        let rendered = tokio::task::spawn_blocking(move || {
+            if audio.channels.len() != 1 {
+                return Err(BinauralError::NotMono(audio.channels.len()));
+            }
+            let set = HrtfSet::open(&sofa, audio.sample_rate as f32)?;
+            let gain = 10f64.powf(gain / 20.0);
+            targets
+                .iter()
+                .map(|target| {
+                    let impulse_responses = set.impulse_responses(
+                        target.azimuth,
+                        target.elevation,
+                        target.distance,
+                        interpolation,
+                    );
+                    let mut channels = render_binaural(&audio.channels[0], &impulse_responses);
+                    channels
+                        .iter_mut()
+                        .flatten()
+                        .for_each(|sample| *sample *= gain);
+                    let peak = channels
+                        .iter()
+                        .flatten()
+                        .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+                    if peak > 1.0 {
+                        return Err(BinauralError::Clipping {
+                            name: target.name.clone(),
+                            peak: 20.0 * peak.log10(),
+                        });
+                    }
+                    Ok(encode_wav(
+                        &[&channels[0], &channels[1]],
+                        audio.sample_rate,
+                        BIT_DEPTH,
+                    ))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .map_err(|e| AudioError::Malformed(e.to_string()))??;
+
+        let binaural = BinauralSource {
+            sample_id: source.sample.id.clone(),
+            hrtf_id: hrtf.id.clone(),
+            interpolation,
+            gain: render.gain,
+        };
+        let mut samples = Vec::with_capacity(rendered.len());
+        for (target, data) in render.targets.into_iter().zip(rendered) {
+            let mut file = self.file_storage.stage().await.map_err(RepoError::from)?;
+            file.write(&data).await.map_err(RepoError::from)?;
+            let format = Self::probe_staged(&mut file).await?;
+            let info = SampleInfo {
+                name: target.name,
+                azimuth: normalize_azimuth(target.azimuth),
+                elevation: target.elevation,
+                distance: target.distance,
+                tags: normalize_tags(render.tags.clone()),
+                description: render.description.clone(),
+                format: Some(format),
+                hash: Some(file.hash()),
+                binaural: Some(Box::new(binaural.clone())),
+                ..Default::default()
+            };
+            samples.push((info, file));
+        }
+        Ok(self.create_rendered(samples).await?)
+    }
+
+    /// Create samples in one transaction and move their files into place
+    async fn create_rendered(
+        &self,
+        samples: Vec<(SampleInfo, StagedFile)>,
+    ) -> Result<Vec<CreatedSample>, RepoError> {
+        let mut query = self.database.query("begin");
+        for (n, (info, file)) in samples.iter().enumerate() {
+            query = query
+                .query(format!(
+                    "select value name from sample where hash is $hash_{n}"
+                ))
+                .query(format!("create only sample content $info_{n}"))
+                .query(format!("fn::retain_blob($hash_{n}, $size_{n})"))
+                .bind((format!("info_{n}"), info.clone()))
+                .bind((format!("hash_{n}"), info.hash.clone()))
+                .bind((format!("size_{n}"), file.size()));
+        }
+        let mut result = query.query("commit").await?.validate()?;
+        let mut created = Vec::with_capacity(samples.len());
+        for n in 0..samples.len() {
+            let duplicates = result.take::<Vec<String>>(3 * n)?;
+            let sample = result
+                .take::<Option<Identified<SampleInfo>>>(3 * n + 1)?
+                .found()?
+                .try_into_string_id()?;
+            created.push(CreatedSample { sample, duplicates });
+        }
+
+        // Overwriting a blob that already exists is harmless, the content is the same
+        for (info, file) in samples {
+            let hash = info.hash.expect("Rendered file is hashed");
+            if let Err(e) = file.commit(&hash).await {
+                for sample in created.iter() {
+                    if let Err(e) = self.delete(sample.sample.id.clone()).await {
+                        warn!({error = ?e}, "Failed to remove a rendered sample");
+                    }
+                }
+                Err(e)?
+            }
+        }
+        Ok(created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::services::{
+        audio::{binaural::Interpolation, decode::decode, tests::wav_bytes, wav::encode_wav},
+        database::{error::DbError, surreal::tests::surreal_in_memory},
+        file_storage::tests::memory_storage,
+        repositories::{
+            hrtf::tests::create_hrtf,
+            sample::{tests::create_sample, tests::sample_data, SampleInfo, SampleRepository},
+            IsViolatingUnique, RepoError,
+        },
+    };
+
+    use super::{BinauralError, BinauralRender, BinauralTarget};
+
+    async fn setup() -> (SampleRepository, String, String) {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let hrtf = create_hrtf(&sut.database, &sut.file_storage, "kemar").await;
+        let info = SampleInfo {
+            name: "dry".to_owned(),
+            ..Default::default()
+        };
+        let source = create_sample(&sut, info, wav_bytes(&[8000, -8000, 4000, 0, 0, 0]))
+            .await
+            .unwrap()
+            .id;
+        (sut, source, hrtf)
+    }
+
+    fn render(hrtf_id: &str, names: &[&str]) -> BinauralRender {
+        BinauralRender {
+            hrtf_id: hrtf_id.to_owned(),
+            interpolation: Interpolation::Nearest,
+            gain: 0.0,
+            tags: vec!["Binaural".to_owned()],
+            description: None,
+            targets: names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| BinauralTarget {
+                    name: name.to_string(),
+                    azimuth: -90.0 * i as f32,
+                    elevation: 0.0,
+                    distance: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn rendered() {
+        let (sut, source, hrtf) = setup().await;
+
+        let created = sut
+            .render_binaural(source.clone(), render(&hrtf, &["front", "left"]))
+            .await
+            .unwrap();
+
+        assert_eq!(created.len(), 2);
+        let left = sut.info(created[1].sample.id.clone()).await.unwrap();
+        assert_eq!(left.azimuth, 270.0);
+        assert_eq!(left.tags, vec!["binaural"]);
+        let origin = left.binaural.clone().unwrap();
+        assert_eq!(origin.sample_id, source);
+        assert_eq!(origin.hrtf_id, hrtf);
+        assert_eq!(left.format.as_ref().unwrap().channels, 2);
+        let data = sample_data(&sut, left.id.clone()).await.unwrap();
+        let audio = decode(Box::new(Cursor::new(data.to_vec()))).unwrap();
+        assert_eq!(audio.sample_rate, 8000);
+        assert!(audio.channels[0].len() >= 6);
+    }
+
+    #[tokio::test]
+    async fn not_mono() {
+        let (sut, _, hrtf) = setup().await;
+        let channel = [0.1; 100];
+        let stereo = encode_wav(&[&channel, &channel], 8000, 16);
+        let info = SampleInfo {
+            name: "stereo".to_owned(),
+            ..Default::default()
+        };
+        let source = create_sample(&sut, info, stereo.into()).await.unwrap().id;
+
+        let result = sut.render_binaural(source, render(&hrtf, &["front"])).await;
+
+        assert!(matches!(result, Err(BinauralError::NotMono(2))));
+    }
+
+    #[tokio::test]
+    async fn clipping() {
+        let (sut, source, hrtf) = setup().await;
+        let mut loud = render(&hrtf, &["front"]);
+        loud.gain = 40.0;
+
+        let result = sut.render_binaural(source, loud).await;
+
+        assert!(matches!(result, Err(BinauralError::Clipping { .. })));
+        assert_eq!(sut.infos().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn taken_name() {
+        let (sut, source, hrtf) = setup().await;
+
+        let result = sut
+            .render_binaural(source, render(&hrtf, &["new", "dry"]))
+            .await;
+
+        assert!(result.is_violating_unique());
+        assert_eq!(sut.infos().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn missing() {
+        let (sut, source, hrtf) = setup().await;
+
+        for (source, hrtf) in [(source, "missing".to_owned()), ("missing".to_owned(), hrtf)] {
+            let result = sut.render_binaural(source, render(&hrtf, &["front"])).await;
+
+            assert!(matches!(
+                result,
+                Err(BinauralError::Repo(RepoError::Database(DbError::NotFound)))
+            ));
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Exporting samples as a ZIP archive in the format accepted by the import
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{ErrorKind, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use chrono::Utc;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    runtime::Handle,
+};
+use tokio_util::io::SyncIoBridge;
+use zip::{
+    write::{SimpleFileOptions, ZipWriter},
+    CompressionMethod,
+};
+
+use crate::services::{
+    database::{
+        error::DbError,
+        identified::{Identified, StringIdentified, TryIntoStringId},
+        surreal::MapToNotFound,
+    },
+    file_storage::{FileStorage, FsError, FsResult, StagedFile},
+};
+
+use super::{
+    manifest::{write_manifest, ManifestError, ManifestFormat, ManifestRow},
+    sample::{SampleInfo, SampleRepository},
+    RepoError,
+};
+
+/// Folder of audio files inside exported archives
+const AUDIO_FOLDER: &str = "audio";
+
+/// Samples to export, all of them if no filter is given
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExportQuery {
+    /// Comma separated sample ids
+    pub ids: Option<String>,
+    /// Experiment whose samples are exported
+    pub experiment: Option<String>,
+    pub manifest: ManifestFormat,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("{0}")]
+    Manifest(#[from] ManifestError),
+    #[error("{0}")]
+    Repo(#[from] RepoError),
+}
+
+impl From<DbError> for ExportError {
+    fn from(value: DbError) -> Self {
+        Self::Repo(value.into())
+    }
+}
+
+impl From<FsError> for ExportError {
+    fn from(value: FsError) -> Self {
+        Self::Repo(value.into())
+    }
+}
+
+impl From<surrealdb::Error> for ExportError {
+    fn from(value: surrealdb::Error) -> Self {
+        Self::Repo(value.into())
+    }
+}
+
+/// Manifest and files of an archive ready to be written
+pub struct SampleExport {
+    format: ManifestFormat,
+    manifest: Vec<u8>,
+    files: Vec<ExportedFile>,
+}
+
+struct ExportedFile {
+    path: String,
+    blob: String,
+    size: u64,
+}
+
+impl SampleRepository {
+    /// Collect samples and check that their files exist before the archive is written
+    ///
+    /// Samples sharing a blob share a file in the archive.
+    pub async fn export(&self, query: ExportQuery) -> Result<SampleExport, ExportError> {
+        let samples = match (query.ids, query.experiment) {
+            (Some(ids), _) => {
+                let ids = ids
+                    .split(',')
+                    .map(|id| id.trim().to_owned())
+                    .filter(|id| !id.is_empty())
+                    .collect::<HashSet<_>>();
+                let mut result = self
+                    .database
+                    .query("select * from sample where record::id(id) in $ids order by name")
+                    .bind(("ids", ids.clone()))
+                    .await?;
+                let samples = result.take::<Vec<Identified<SampleInfo>>>(0)?;
+                if samples.len() != ids.len() {
+                    Err(DbError::NotFound)?
+                }
+                samples
+            }
+            (None, Some(experiment_id)) => {
+                let mut result = self
+                    .database
+                    .query("select value id from experiment where record::id(id) is $experiment_id")
+                    .query("select * from (select value out from experiment_sample where record::id(in) is $experiment_id) order by name")
+                    .bind(("experiment_id", experiment_id))
+                    .await?;
+                result.take::<Option<surrealdb::sql::Thing>>(0)?.found()?;
+                result.take::<Vec<Identified<SampleInfo>>>(1)?
+            }
+            (None, None) => {
+                let mut result = self
+                    .database
+                    .query("select * from sample order by name")
+                    .await?;
+                result.take::<Vec<Identified<SampleInfo>>>(0)?
+            }
+        };
+        let samples: Vec<StringIdentified<SampleInfo>> =
+            samples.try_into_string_id().map_err(RepoError::from)?;
+
+        let mut paths = HashMap::<String, String>::new();
+        let mut used = HashSet::new();
+        let mut files = Vec::new();
+        let mut rows = Vec::with_capacity(samples.len());
+        for sample in samples {
+            let blob = sample.hash.clone().found()?;
+            let path = match paths.get(&blob) {
+                Some(path) => path.clone(),
+                None => {
+                    let metadata = self.file_storage.metadata(&blob).await?;
+                    let path = unique_path(&sample.name, &mut used);
+                    paths.insert(blob.clone(), path.clone());
+                    files.push(ExportedFile {
+                        path: path.clone(),
+                        blob,
+                        size: metadata.size,
+                    });
+                    path
+                }
+            };
+            rows.push(ManifestRow::new(path, sample.data));
+        }
+        Ok(SampleExport {
+            format: query.manifest,
+            manifest: write_manifest(query.manifest, rows)?,
+            files,
+        })
+    }
+}
+
+impl SampleExport {
+    /// Write the whole archive into a staged file
+    ///
+    /// The archive is complete before it is sent, so storage errors are reported instead of truncating it.
+    /// Audio files are stored without compression, the manifest is deflated.
+    pub async fn build(self, file_storage: &FileStorage) -> FsResult<ExportedArchive> {
+        let mut staged = file_storage.stage().await?;
+        let file = staged.open_write().await?;
+        let file_storage = file_storage.clone();
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || self.write(&file_storage, handle, file)).await??;
+        let file = tokio::fs::File::from_std(staged.open_read().await?);
+        Ok(ExportedArchive {
+            size: file.metadata().await?.len(),
+            _staged: staged,
+            file,
+        })
+    }
+
+    /// Blocking, reads stored files through the runtime handle
+    fn write(
+        self,
+        file_storage: &FileStorage,
+        handle: Handle,
+        file: std::fs::File,
+    ) -> FsResult<()> {
+        let modified = zip::DateTime::try_from(Utc::now().naive_utc()).unwrap_or_default();
+        let mut zip = ZipWriter::new(file);
+        zip.start_file(
+            self.format.file_name(),
+            SimpleFileOptions::default().last_modified_time(modified),
+        )?;
+        zip.write_all(&self.manifest)?;
+        for file in self.files {
+            let options = SimpleFileOptions::default()
+                .last_modified_time(modified)
+                .compression_method(CompressionMethod::Stored)
+                .large_file(file.size >= u32::MAX as u64);
+            zip.start_file(file.path.as_str(), options)?;
+            let data = handle.block_on(file_storage.read(&file.blob, 0..file.size))?;
+            let copied = std::io::copy(
+                &mut SyncIoBridge::new_with_handle(data, handle.clone()),
+                &mut zip,
+            )?;
+            if copied != file.size {
+                return Err(FsError::new(
+                    ErrorKind::UnexpectedEof,
+                    format!(
+                        "File `{}` has {copied} bytes instead of {}",
+                        file.blob, file.size
+                    ),
+                ));
+            }
+        }
+        zip.finish()?.sync_all()
+    }
+}
+
+/// Archive written into a staged file, removed once the archive is dropped
+pub struct ExportedArchive {
+    pub size: u64,
+    _staged: StagedFile,
+    file: tokio::fs::File,
+}
+
+impl AsyncRead for ExportedArchive {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.file).poll_read(cx, buf)
+    }
+}
+
+/// Archive path based on the sample name, made unique with a numbered suffix
+///
+/// Paths differing only in case are considered equal, as they collide on some file systems.
+fn unique_path(name: &str, used: &mut HashSet<String>) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || " ._-()".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let name = name.trim().trim_start_matches('.');
+    let name = if name.is_empty() { "sample" } else { name };
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    let mut path = format!("{AUDIO_FOLDER}/{stem}{extension}");
+    let mut n = 1;
+    while !used.insert(path.to_lowercase()) {
+        n += 1;
+        path = format!("{AUDIO_FOLDER}/{stem}-{n}{extension}");
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io::Cursor};
+
+    use tokio::io::AsyncReadExt;
+    use zip::ZipArchive;
+
+    use crate::services::{
+        audio::tests::wav_bytes,
+        database::{error::DbError, surreal::tests::surreal_in_memory},
+        file_storage::tests::memory_storage,
+        repositories::{
+            experiment::{tests::test_experiment, ExperimentRepository},
+            manifest::{parse_manifest, ManifestFormat},
+            sample::{tests::create_sample, SampleInfo, SampleRepository},
+            RepoError,
+        },
+    };
+
+    use super::{unique_path, ExportError, ExportQuery};
+
+    async fn setup() -> (SampleRepository, Vec<String>) {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let mut ids = Vec::new();
+        for (name, data) in [
+            ("front.wav", wav_bytes(&[7, 6, 5, 4])),
+            ("Front.wav", wav_bytes(&[7, 6, 5, 4])),
+            ("back/left.wav", wav_bytes(&[1, 2, 3])),
+        ] {
+            let info = SampleInfo {
+                name: name.to_owned(),
+                azimuth: 90.0,
+                tags: vec!["noise".to_owned()],
+                ..Default::default()
+            };
+            ids.push(create_sample(&sut, info, data).await.unwrap().id);
+        }
+        (sut, ids)
+    }
+
+    async fn archive(sut: &SampleRepository, query: ExportQuery) -> ZipArchive<Cursor<Vec<u8>>> {
+        let export = sut.export(query).await.unwrap();
+        let mut data = Vec::new();
+        export
+            .build(&sut.file_storage)
+            .await
+            .unwrap()
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        ZipArchive::new(Cursor::new(data)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_all() {
+        let (sut, _) = setup().await;
+
+        let mut zip = archive(&sut, ExportQuery::default()).await;
+
+        let mut names = zip.file_names().map(str::to_owned).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["audio/Front.wav", "audio/back_left.wav", "manifest.csv"]
+        );
+        let mut manifest = Vec::new();
+        std::io::Read::read_to_end(&mut zip.by_name("manifest.csv").unwrap(), &mut manifest)
+            .unwrap();
+        let rows = parse_manifest(ManifestFormat::Csv, &manifest).unwrap();
+        let files = rows.iter().map(|row| row.file.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec!["audio/Front.wav", "audio/back_left.wav", "audio/Front.wav"]
+        );
+        assert_eq!(rows[1].tags, vec!["noise"]);
+        assert_eq!(rows[1].azimuth, 90.0);
+    }
+
+    #[tokio::test]
+    async fn export_filtered() {
+        let (sut, ids) = setup().await;
+        let experiments = ExperimentRepository {
+            surreal: sut.database.clone(),
+        };
+        let experiment = test_experiment(vec![ids[2].clone()]);
+        let experiment = experiments.create(experiment).await.unwrap();
+
+        let by_ids = ExportQuery {
+            ids: Some(format!("{},{}", ids[0], ids[2])),
+            manifest: ManifestFormat::Json,
+            ..Default::default()
+        };
+        let zip = archive(&sut, by_ids).await;
+        assert_eq!(zip.len(), 3);
+        assert!(zip.index_for_name("manifest.json").is_some());
+
+        let by_experiment = ExportQuery {
+            experiment: Some(experiment.id),
+            ..Default::default()
+        };
+        let zip = archive(&sut, by_experiment).await;
+        assert_eq!(zip.len(), 2);
+        assert!(zip.index_for_name("audio/back_left.wav").is_some());
+    }
+
+    #[tokio::test]
+    async fn export_not_found() {
+        let (sut, ids) = setup().await;
+
+        for query in [
+            ExportQuery {
+                ids: Some(format!("{},missing", ids[0])),
+                ..Default::default()
+            },
+            ExportQuery {
+                experiment: Some("missing".to_owned()),
+                ..Default::default()
+            },
+        ] {
+            let result = sut.export(query).await;
+
+            assert!(matches!(
+                result,
+                Err(ExportError::Repo(RepoError::Database(DbError::NotFound)))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn export_missing_file() {
+        let (sut, ids) = setup().await;
+        let query = ExportQuery {
+            ids: Some(ids[2].clone()),
+            ..Default::default()
+        };
+        let export = sut.export(query).await.unwrap();
+        let blob = sut
+            .info(ids[2].clone())
+            .await
+            .unwrap()
+            .hash
+            .clone()
+            .unwrap();
+        sut.file_storage.delete(&blob).await.unwrap();
+
+        let result = export.build(&sut.file_storage).await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unique_paths() {
+        let mut used = HashSet::new();
+
+        let paths = ["tone.wav", "Tone.wav", "tone.wav", "../x", ".hidden", "?"]
+            .map(|name| unique_path(name, &mut used));
+
+        assert_eq!(
+            paths,
+            [
+                "audio/tone.wav",
+                "audio/Tone-2.wav",
+                "audio/tone-3.wav",
+                "audio/_x",
+                "audio/hidden",
+                "audio/_"
+            ]
+        );
+    }
+}
@@ -0,0 +1,644 @@
+//! Importing many samples at once from a ZIP archive
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use validator::Validate;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::services::{
+    database::{
+        error::{DbError, ValidateDbResponse},
+        identified::{Identified, TryIntoStringId},
+        surreal::MapToNotFound,
+    },
+    file_storage::{FsError, StagedFile},
+};
+
+use super::{
+    manifest::{parse_manifest, ManifestError, ManifestFormat, ManifestRow},
+    non_unique_value_on_index,
+    position::normalize_azimuth,
+    sample::{SampleInfo, SampleRepository},
+    tag::normalize_tags,
+    IsViolatingUnique, RepoError, RepoResult,
+};
+
+/// Size of chunks copied from the archive into staged files
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest manifest read from an archive, sizes declared in the archive cannot be trusted
+const MAX_MANIFEST_BYTES: u64 = 16 * 1024 * 1024;
+
+/// What to do with rows whose name is already taken by a sample or an earlier row
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    /// Leave the row out of the import
+    Skip,
+    /// Reject the whole import
+    #[default]
+    Fail,
+    /// Append the first free ` (n)` suffix to the name
+    Rename,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImportOptions {
+    pub on_conflict: ConflictPolicy,
+    /// Maximum total size of extracted audio files in bytes, unlimited if missing
+    #[serde(skip)]
+    pub extracted_size_limit: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("Invalid ZIP archive: {0}")]
+    Archive(#[from] ZipError),
+    #[error("Archive must contain either `manifest.csv` or `manifest.json`")]
+    MissingManifest,
+    #[error("{0}")]
+    Manifest(#[from] ManifestError),
+    #[error("Extracted files exceed the size limit")]
+    TooLarge,
+    #[error("Manifest exceeds {MAX_MANIFEST_BYTES} bytes")]
+    ManifestTooLarge,
+    #[error("{0}")]
+    Repo(#[from] RepoError),
+}
+
+impl<T> IsViolatingUnique<T> for Result<T, ImportError> {
+    fn is_violating_unique(&self) -> bool {
+        matches!(self, Err(ImportError::Repo(RepoError::Database(DbError::Query(e)))) if non_unique_value_on_index(e))
+    }
+}
+
+/// Outcome of every manifest row
+///
+/// Samples are created only if no row failed, otherwise valid rows are reported but not imported.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: bool,
+    pub rows: Vec<ImportRow>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRow {
+    /// Position in the manifest, starting at 1
+    pub row: usize,
+    pub file: String,
+    /// Name of the sample, possibly changed by the conflict policy
+    pub name: String,
+    #[serde(flatten)]
+    pub status: ImportStatus,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum ImportStatus {
+    Created {
+        id: String,
+    },
+    /// Passed validation, not imported because other rows failed
+    Valid,
+    Skipped {
+        reason: String,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+/// Audio file extracted from the archive, shared by all rows referencing it
+struct ExtractedFile {
+    file: StagedFile,
+    info: SampleInfo,
+}
+
+impl SampleRepository {
+    /// Create samples described by the manifest of a staged ZIP archive
+    ///
+    /// Every row is validated and its audio decoded before anything is written, then all samples are created in one transaction.
+    /// Files are moved into place after the transaction, samples are deleted again if that fails.
+    pub async fn import(
+        &self,
+        mut archive: StagedFile,
+        options: ImportOptions,
+    ) -> Result<ImportReport, ImportError> {
+        let source = archive.open_read().await.map_err(RepoError::from)?;
+        let (mut zip, format) = tokio::task::spawn_blocking(move || open_archive(source))
+            .await
+            .map_err(|e| ZipError::Io(e.into()))??;
+        let manifest_name = format.file_name().to_owned();
+        let (returned, manifest) = read_manifest(zip, manifest_name).await?;
+        zip = returned;
+        let rows = parse_manifest(format, &manifest)?;
+
+        let mut report = Vec::with_capacity(rows.len());
+        let mut extracted = HashMap::<String, Result<ExtractedFile, String>>::new();
+        let mut remaining = options.extracted_size_limit;
+        for row in rows.iter() {
+            if extracted.contains_key(&row.file) {
+                continue;
+            }
+            let (returned, file) = self.extract(zip, row.file.clone(), remaining).await?;
+            zip = returned;
+            if let (Ok(file), Some(remaining)) = (&file, remaining.as_mut()) {
+                *remaining -= file.file.size();
+            }
+            extracted.insert(row.file.clone(), file);
+        }
+
+        let mut names = HashSet::new();
+        for (i, row) in rows.iter().enumerate() {
+            let status = match Self::check_row(row, &extracted) {
+                Ok(()) => ImportStatus::Valid,
+                Err(reason) => ImportStatus::Failed { reason },
+            };
+            names.insert(row.name.clone());
+            report.push(ImportRow {
+                row: i + 1,
+                file: row.file.clone(),
+                name: row.name.clone(),
+                status,
+            });
+        }
+        self.resolve_conflicts(&mut report, names, options.on_conflict)
+            .await?;
+
+        if report
+            .iter()
+            .any(|row| matches!(row.status, ImportStatus::Failed { .. }))
+        {
+            return Ok(ImportReport {
+                imported: false,
+                rows: report,
+            });
+        }
+        self.create_imported(&rows, &mut report, extracted).await?;
+        Ok(ImportReport {
+            imported: true,
+            rows: report,
+        })
+    }
+
+    /// Stage and probe an archive entry, failures of the entry are returned as the inner error
+    async fn extract(
+        &self,
+        zip: ZipArchive<File>,
+        name: String,
+        size_limit: Option<u64>,
+    ) -> Result<(ZipArchive<File>, Result<ExtractedFile, String>), ImportError> {
+        let mut file = self.file_storage.stage().await.map_err(RepoError::from)?;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<Result<Vec<u8>, String>>(4);
+        let reader = tokio::task::spawn_blocking(move || {
+            let mut zip = zip;
+            let result = (|| {
+                let mut entry = zip.by_name(&name)?;
+                if entry.is_dir() {
+                    Err(ZipError::FileNotFound)?
+                }
+                let mut buffer = vec![0; CHUNK_SIZE];
+                loop {
+                    let read = entry.read(&mut buffer)?;
+                    if read == 0 {
+                        return Ok(());
+                    }
+                    if sender.blocking_send(Ok(buffer[..read].to_vec())).is_err() {
+                        return Ok(());
+                    }
+                }
+            })();
+            if let Err(e) = result {
+                let e: ZipError = e;
+                let _ = sender.blocking_send(Err(e.to_string()));
+            }
+            zip
+        });
+        let mut entry_error = None;
+        let mut write_error = None;
+        while let Some(chunk) = receiver.recv().await {
+            match chunk {
+                Ok(chunk) => {
+                    if let Err(e) = file.write(&chunk).await {
+                        write_error = Some(RepoError::from(e).into());
+                        break;
+                    }
+                    // Sizes declared in the archive cannot be trusted
+                    if size_limit.is_some_and(|limit| file.size() > limit) {
+                        write_error = Some(ImportError::TooLarge);
+                        break;
+                    }
+                }
+                Err(e) => entry_error = Some(e),
+            }
+        }
+        drop(receiver);
+        let zip = reader
+            .await
+            .map_err(|e| RepoError::from(FsError::from(e)))?;
+        if let Some(e) = write_error {
+            return Err(e);
+        }
+        if let Some(e) = entry_error {
+            return Ok((zip, Err(e)));
+        }
+        let result = match Self::probe_staged(&mut file).await {
+            Ok(format) => {
+                let info = SampleInfo {
+                    format: Some(format),
+                    hash: Some(file.hash()),
+                    ..Default::default()
+                };
+                Ok(ExtractedFile { file, info })
+            }
+            Err(RepoError::Audio(e)) => Err(e.to_string()),
+            Err(e) => Err(e)?,
+        };
+        Ok((zip, result))
+    }
+
+    fn check_row(
+        row: &ManifestRow,
+        extracted: &HashMap<String, Result<ExtractedFile, String>>,
+    ) -> Result<(), String> {
+        row.info()
+            .validate()
+            .map_err(|e| e.to_string().replace('\n', "; "))?;
+        match &extracted[&row.file] {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    /// Apply the conflict policy to valid rows whose names are taken by samples or earlier rows
+    async fn resolve_conflicts(
+        &self,
+        report: &mut [ImportRow],
+        names: HashSet<String>,
+        policy: ConflictPolicy,
+    ) -> RepoResult<()> {
+        let mut result = self
+            .database
+            .query("select value name from sample where name in $names")
+            .bind(("names", names))
+            .await?;
+        let mut taken = result
+            .take::<Vec<String>>(0)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        for row in report.iter_mut() {
+            if row.status != ImportStatus::Valid {
+                continue;
+            }
+            if !taken.contains(&row.name) {
+                taken.insert(row.name.clone());
+                continue;
+            }
+            match policy {
+                ConflictPolicy::Skip => {
+                    row.status = ImportStatus::Skipped {
+                        reason: "Name already taken".to_owned(),
+                    }
+                }
+                ConflictPolicy::Fail => {
+                    row.status = ImportStatus::Failed {
+                        reason: "Name already taken".to_owned(),
+                    }
+                }
+                ConflictPolicy::Rename => {
+                    let mut result = self
+                        .database
+                        .query(
+                            "select value name from sample where string::starts_with(name, $name)",
+                        )
+                        .bind(("name", row.name.clone()))
+                        .await?;
+                    taken.extend(result.take::<Vec<String>>(0)?);
+                    let name = (2..)
+                        .map(|n| format!("{} ({n})", row.name))
+                        .find(|name| !taken.contains(name))
+                        .expect("Some suffix is free");
+                    if name.chars().count() > 63 {
+                        row.status = ImportStatus::Failed {
+                            reason: "Name already taken and too long to rename".to_owned(),
+                        };
+                        continue;
+                    }
+                    taken.insert(name.clone());
+                    row.name = name;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_imported(
+        &self,
+        rows: &[ManifestRow],
+        report: &mut [ImportRow],
+        extracted: HashMap<String, Result<ExtractedFile, String>>,
+    ) -> RepoResult<()> {
+        let created = report
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| row.status == ImportStatus::Valid)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let mut extracted = extracted
+            .into_iter()
+            .filter_map(|(name, file)| Some((name, file.ok()?)))
+            .collect::<HashMap<_, _>>();
+        if created.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = self.database.query("begin");
+        for (n, &i) in created.iter().enumerate() {
+            let file = &extracted[&rows[i].file];
+            let mut info = rows[i].info();
+            info.name = report[i].name.clone();
+            info.azimuth = normalize_azimuth(info.azimuth);
+            info.tags = normalize_tags(info.tags);
+            info.format = file.info.format.clone();
+            info.hash = file.info.hash.clone();
+            query = query
+                .query(format!("create only sample content $info_{n}"))
+                .query(format!("fn::retain_blob($hash_{n}, $size_{n})"))
+                .bind((format!("info_{n}"), info))
+                .bind((format!("hash_{n}"), file.info.hash.clone()))
+                .bind((format!("size_{n}"), file.file.size()));
+        }
+        let mut result = query.query("commit").await?.validate()?;
+        let mut ids = Vec::with_capacity(created.len());
+        for (n, &i) in created.iter().enumerate() {
+            let sample = result
+                .take::<Option<Identified<SampleInfo>>>(2 * n)?
+                .found()?
+                .try_into_string_id()?;
+            report[i].status = ImportStatus::Created {
+                id: sample.id.clone(),
+            };
+            ids.push(sample.id);
+        }
+
+        // Overwriting a blob that already exists is harmless, the content is the same
+        let used = created
+            .iter()
+            .map(|&i| rows[i].file.clone())
+            .collect::<HashSet<_>>();
+        for name in used {
+            let file = extracted.remove(&name).expect("Extracted file exists");
+            let hash = file.info.hash.clone().expect("Extracted file is hashed");
+            if let Err(e) = file.file.commit(&hash).await {
+                for id in ids.iter() {
+                    if let Err(e) = self.delete(id.clone()).await {
+                        warn!({error = ?e}, "Failed to remove an imported sample");
+                    }
+                }
+                Err(e)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Open an archive and find its manifest
+fn open_archive(source: File) -> Result<(ZipArchive<File>, ManifestFormat), ImportError> {
+    let zip = ZipArchive::new(source)?;
+    let csv = zip
+        .index_for_name(ManifestFormat::Csv.file_name())
+        .is_some();
+    let json = zip
+        .index_for_name(ManifestFormat::Json.file_name())
+        .is_some();
+    match (csv, json) {
+        (true, false) => Ok((zip, ManifestFormat::Csv)),
+        (false, true) => Ok((zip, ManifestFormat::Json)),
+        _ => Err(ImportError::MissingManifest),
+    }
+}
+
+/// Read the manifest of an archive on a blocking thread
+///
+/// At most one byte more than [`MAX_MANIFEST_BYTES`] is decompressed, so small archives cannot expand into huge manifests.
+async fn read_manifest(
+    zip: ZipArchive<File>,
+    name: String,
+) -> Result<(ZipArchive<File>, Vec<u8>), ImportError> {
+    let (zip, data) = tokio::task::spawn_blocking(move || {
+        let mut zip = zip;
+        let mut data = Vec::new();
+        let result = zip
+            .by_name(&name)
+            .and_then(|entry| Ok(entry.take(MAX_MANIFEST_BYTES + 1).read_to_end(&mut data)?))
+            .map(|_| data);
+        (zip, result)
+    })
+    .await
+    .map_err(|e| RepoError::from(FsError::from(e)))?;
+    let data = data?;
+    if data.len() as u64 > MAX_MANIFEST_BYTES {
+        Err(ImportError::ManifestTooLarge)?
+    }
+    Ok((zip, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use bytes::Bytes;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use crate::services::{
+        audio::tests::wav_bytes,
+        database::surreal::tests::surreal_in_memory,
+        file_storage::tests::memory_storage,
+        repositories::sample::{tests::create_sample, SampleInfo, SampleRepository},
+    };
+
+    use super::{ConflictPolicy, ImportError, ImportOptions, ImportStatus};
+
+    async fn setup() -> SampleRepository {
+        SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        }
+    }
+
+    fn zip(files: &[(&str, Bytes)]) -> Bytes {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner().into()
+    }
+
+    async fn import(
+        sut: &SampleRepository,
+        files: &[(&str, Bytes)],
+        on_conflict: ConflictPolicy,
+    ) -> Result<super::ImportReport, ImportError> {
+        let archive = sut.file_storage.stage_bytes(zip(files)).await.unwrap();
+        let options = ImportOptions {
+            on_conflict,
+            extracted_size_limit: Some(1024),
+        };
+        sut.import(archive, options).await
+    }
+
+    #[tokio::test]
+    async fn import_csv() {
+        let sut = setup().await;
+        let manifest = "file,name,azimuth,elevation,distance,tags,description\n\
+                        front.wav,Front,0,0,1.5,\"noise,hrtf\",\n\
+                        front.wav,Front again,-90,0,,,same file\n\
+                        stimuli/back.wav,Back,180,15,,,\n";
+        let files = [
+            ("manifest.csv", Bytes::from(manifest)),
+            ("front.wav", wav_bytes(&[7, 6, 5, 4])),
+            ("stimuli/back.wav", wav_bytes(&[1, 2, 3])),
+        ];
+
+        let report = import(&sut, &files, ConflictPolicy::Fail).await.unwrap();
+
+        assert!(report.imported);
+        assert_eq!(report.rows.len(), 3);
+        let ImportStatus::Created { id } = &report.rows[1].status else {
+            panic!("Row was not created: {:?}", report.rows[1]);
+        };
+        let sample = sut.info(id.clone()).await.unwrap();
+        assert_eq!(sample.azimuth, 270.0);
+        assert_eq!(sample.description.as_deref(), Some("same file"));
+        let samples = sut.infos().await.unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(sut.file_storage.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn manifest_too_large() {
+        let sut = setup().await;
+        let manifest = " ".repeat(super::MAX_MANIFEST_BYTES as usize + 1);
+        let files = [("manifest.csv", Bytes::from(manifest))];
+
+        let result = import(&sut, &files, ConflictPolicy::Fail).await;
+
+        assert!(matches!(result, Err(ImportError::ManifestTooLarge)));
+    }
+
+    #[tokio::test]
+    async fn import_invalid_rows() {
+        let sut = setup().await;
+        let manifest = r#"[
+            {"file": "front.wav", "name": "Front", "azimuth": 0, "elevation": 0},
+            {"file": "front.wav", "name": "Above", "azimuth": 0, "elevation": 100},
+            {"file": "missing.wav", "name": "Missing", "azimuth": 0, "elevation": 0},
+            {"file": "noise.txt", "name": "Text", "azimuth": 0, "elevation": 0}
+        ]"#;
+        let files = [
+            ("manifest.json", Bytes::from(manifest)),
+            ("front.wav", wav_bytes(&[7, 6, 5, 4])),
+            ("noise.txt", Bytes::from("not audio")),
+        ];
+
+        let report = import(&sut, &files, ConflictPolicy::Fail).await.unwrap();
+
+        assert!(!report.imported);
+        assert_eq!(report.rows[0].status, ImportStatus::Valid);
+        for row in &report.rows[1..] {
+            assert!(
+                matches!(row.status, ImportStatus::Failed { .. }),
+                "Row did not fail: {row:?}"
+            );
+        }
+        assert!(sut.infos().await.unwrap().is_empty());
+        assert!(sut.file_storage.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn import_conflicts() {
+        let sut = setup().await;
+        let info = SampleInfo {
+            name: "Front".to_owned(),
+            ..Default::default()
+        };
+        create_sample(&sut, info, wav_bytes(&[1, 1, 1]))
+            .await
+            .unwrap();
+        let manifest = "file,name,azimuth,elevation\n\
+                        front.wav,Front,0,0\n\
+                        front.wav,Side,90,0\n\
+                        front.wav,Side,270,0\n";
+        let files = [
+            ("manifest.csv", Bytes::from(manifest)),
+            ("front.wav", wav_bytes(&[7, 6, 5, 4])),
+        ];
+
+        let report = import(&sut, &files, ConflictPolicy::Fail).await.unwrap();
+        assert!(!report.imported);
+        assert!(matches!(report.rows[0].status, ImportStatus::Failed { .. }));
+        assert_eq!(report.rows[1].status, ImportStatus::Valid);
+        assert!(matches!(report.rows[2].status, ImportStatus::Failed { .. }));
+
+        let report = import(&sut, &files, ConflictPolicy::Skip).await.unwrap();
+        assert!(report.imported);
+        assert!(matches!(
+            report.rows[0].status,
+            ImportStatus::Skipped { .. }
+        ));
+        assert!(matches!(
+            report.rows[1].status,
+            ImportStatus::Created { .. }
+        ));
+        assert!(matches!(
+            report.rows[2].status,
+            ImportStatus::Skipped { .. }
+        ));
+
+        let report = import(&sut, &files, ConflictPolicy::Rename).await.unwrap();
+        assert!(report.imported);
+        let names = report
+            .rows
+            .iter()
+            .map(|row| row.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Front (2)", "Side (2)", "Side (3)"]);
+        assert_eq!(sut.infos().await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn import_without_manifest() {
+        let sut = setup().await;
+        let files = [("front.wav", wav_bytes(&[7, 6, 5, 4]))];
+
+        let result = import(&sut, &files, ConflictPolicy::Fail).await;
+
+        assert!(matches!(result, Err(ImportError::MissingManifest)));
+    }
+
+    #[tokio::test]
+    async fn import_too_large() {
+        let sut = setup().await;
+        let manifest = "file,name,azimuth,elevation\nlong.wav,Long,0,0\n";
+        let files = [
+            ("manifest.csv", Bytes::from(manifest)),
+            ("long.wav", wav_bytes(&[0; 1024])),
+        ];
+
+        let result = import(&sut, &files, ConflictPolicy::Fail).await;
+
+        assert!(matches!(result, Err(ImportError::TooLarge)));
+    }
+}
@@ -0,0 +1,150 @@
+//! Copies of samples normalized to a target loudness, cached in file storage
+
+use crate::services::{
+    audio::{loudness::normalization_gain, Encoding},
+    database::surreal::MapToNotFound,
+    file_storage::CachedFile,
+};
+
+use super::{sample::SampleRepository, RepoResult};
+
+/// Bit depth of normalized copies
+const BIT_DEPTH: u16 = 24;
+
+impl SampleRepository {
+    /// Get a copy of a sample normalized to the target loudness in LUFS, writing it if needed
+    ///
+    /// Copies are cached next to the sample file, so replacing the audio writes a new one.
+    /// Missing when the loudness of the sample is unknown, the original is served as it is then.
+    pub async fn normalized(
+        &self,
+        id: String,
+        target: f64,
+        encoding: Encoding,
+    ) -> RepoResult<Option<CachedFile>> {
+        let sample = self.info(id).await?;
+        let blob = sample.hash.clone().found()?;
+        let Some(gain) = sample
+            .format
+            .as_ref()
+            .and_then(|format| format.loudness.as_ref())
+            .and_then(|loudness| normalization_gain(loudness, target))
+        else {
+            return Ok(None);
+        };
+        // WAV copies were the only ones before FLAC delivery and keep their names
+        let suffix = match encoding {
+            Encoding::Wav => "",
+            Encoding::Flac => "flac",
+        };
+        let key = format!("lufs{}{suffix}", (target * 10.0).round() as i64);
+        let cached = self
+            .derived(&blob, &key, move |mut audio| {
+                let gain = 10f64.powf(gain / 20.0);
+                audio
+                    .channels
+                    .iter_mut()
+                    .flatten()
+                    .for_each(|sample| *sample *= gain);
+                let channels = audio.channels.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                encoding.encode(&channels, audio.sample_rate, BIT_DEPTH)
+            })
+            .await?;
+        Ok(Some(cached))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bytes::Bytes;
+
+    use crate::services::{
+        audio::{probe::probe, tests::wav_bytes, wav::encode_wav, Encoding},
+        database::surreal::tests::surreal_in_memory,
+        file_storage::{cache_source, tests::memory_storage},
+        repositories::sample::{tests::create_sample, SampleInfo, SampleRepository},
+    };
+
+    async fn setup() -> SampleRepository {
+        SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        }
+    }
+
+    /// One second of a 1 kHz tone with the given peak level in dBFS
+    fn tone(level: f64) -> Bytes {
+        let amplitude = 10f64.powf(level / 20.0);
+        let samples = (0..48000)
+            .map(|i| amplitude * (i as f64 * 1000.0 * std::f64::consts::TAU / 48000.0).sin())
+            .collect::<Vec<_>>();
+        Bytes::from(encode_wav(&[&samples], 48000, 24))
+    }
+
+    #[tokio::test]
+    async fn measured() {
+        let sut = setup().await;
+        let info = SampleInfo {
+            name: "tone.wav".to_owned(),
+            ..Default::default()
+        };
+
+        let sample = create_sample(&sut, info, tone(-20.0)).await.unwrap();
+
+        let loudness = sample.data.format.unwrap().loudness.unwrap();
+        // A mono tone is 3 LU quieter than the same tone on both channels
+        assert!((loudness.integrated.unwrap() + 23.0).abs() < 0.2);
+        assert!((loudness.true_peak.unwrap() + 20.0).abs() < 0.2);
+        assert!((loudness.rms.unwrap() + 23.0).abs() < 0.1);
+    }
+
+    #[tokio::test]
+    async fn normalized() {
+        let sut = setup().await;
+        let info = SampleInfo {
+            name: "tone.wav".to_owned(),
+            ..Default::default()
+        };
+        let sample = create_sample(&sut, info, tone(-20.0)).await.unwrap();
+        let hash = sample.data.hash.clone().unwrap();
+
+        let first = sut
+            .normalized(sample.id.clone(), -30.0, Encoding::Wav)
+            .await
+            .unwrap()
+            .unwrap();
+        let second = sut
+            .normalized(sample.id.clone(), -30.0, Encoding::Wav)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(first.name, second.name);
+        assert_eq!(cache_source(&first.name), Some(hash.as_str()));
+        let data = sut.file_storage.get(&first.name).await.unwrap();
+        let format = probe(Box::new(Cursor::new(data.to_vec()))).unwrap();
+        let loudness = format.loudness.unwrap();
+        assert!((loudness.integrated.unwrap() + 30.0).abs() < 0.2);
+    }
+
+    #[tokio::test]
+    async fn unknown_loudness() {
+        let sut = setup().await;
+        let info = SampleInfo {
+            name: "silence.wav".to_owned(),
+            ..Default::default()
+        };
+        let sample = create_sample(&sut, info, wav_bytes(&[0; 800]))
+            .await
+            .unwrap();
+
+        let result = sut
+            .normalized(sample.id.clone(), -23.0, Encoding::Wav)
+            .await
+            .unwrap();
+
+        assert!(result.is_none());
+    }
+}
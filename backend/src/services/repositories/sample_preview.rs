@@ -0,0 +1,166 @@
+//! Waveform peaks and spectrograms of samples, cached in file storage
+
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::services::{
+    audio::preview::{peaks, spectrogram},
+    database::surreal::MapToNotFound,
+    file_storage::CachedFile,
+};
+
+use super::{sample::SampleRepository, RepoResult};
+
+#[derive(Debug, Clone, Copy, Deserialize, Validate)]
+pub struct PeaksQuery {
+    /// Number of peaks per channel
+    #[validate(range(min = 1, max = 10000))]
+    #[serde(default = "default_resolution")]
+    pub resolution: usize,
+}
+
+fn default_resolution() -> usize {
+    1000
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Validate)]
+pub struct SpectrogramQuery {
+    /// Image size in pixels
+    #[validate(range(min = 16, max = 4096))]
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[validate(range(min = 16, max = 2048))]
+    #[serde(default = "default_height")]
+    pub height: u32,
+}
+
+fn default_width() -> u32 {
+    800
+}
+
+fn default_height() -> u32 {
+    256
+}
+
+impl SampleRepository {
+    /// Get waveform peaks of a sample as JSON, computing them if needed
+    pub async fn peaks(&self, id: String, query: PeaksQuery) -> RepoResult<CachedFile> {
+        let blob = self.info(id).await?.hash.clone().found()?;
+        let key = format!("peaks{}", query.resolution);
+        self.derived(&blob, &key, move |audio| {
+            Ok(serde_json::to_vec(&peaks(&audio, query.resolution)).expect("Peaks serialize"))
+        })
+        .await
+    }
+
+    /// Get a spectrogram of a sample as a PNG image, rendering it if needed
+    pub async fn spectrogram(&self, id: String, query: SpectrogramQuery) -> RepoResult<CachedFile> {
+        let blob = self.info(id).await?.hash.clone().found()?;
+        let key = format!("spectrogram{}x{}", query.width, query.height);
+        self.derived(&blob, &key, move |audio| {
+            Ok(spectrogram(&audio, query.width, query.height))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{
+        audio::{preview::Peaks, tests::wav_bytes},
+        database::{error::DbError, surreal::tests::surreal_in_memory},
+        file_storage::{cache_source, tests::memory_storage},
+        repositories::{
+            sample::{tests::create_sample, SampleInfo, SampleReplacement, SampleRepository},
+            RepoError,
+        },
+    };
+
+    use super::{PeaksQuery, SpectrogramQuery};
+
+    async fn setup() -> (SampleRepository, String) {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let info = SampleInfo {
+            name: "sample.wav".to_owned(),
+            ..Default::default()
+        };
+        let id = create_sample(&sut, info, wav_bytes(&[0, 16384, -16384, 0]))
+            .await
+            .unwrap()
+            .id;
+        (sut, id)
+    }
+
+    #[tokio::test]
+    async fn peaks() {
+        let (sut, id) = setup().await;
+
+        let cached = sut.peaks(id, PeaksQuery { resolution: 2 }).await.unwrap();
+
+        let data = sut.file_storage.get(&cached.name).await.unwrap();
+        let peaks: Peaks = serde_json::from_slice(&data).unwrap();
+        assert_eq!(peaks.frames_per_peak, 2);
+        assert_eq!(peaks.channels, vec![vec![[0.0, 0.5], [-0.5, 0.0]]]);
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        let (sut, id) = setup().await;
+        let query = SpectrogramQuery {
+            width: 16,
+            height: 16,
+        };
+
+        let first = sut.spectrogram(id.clone(), query).await.unwrap();
+        let second = sut.spectrogram(id.clone(), query).await.unwrap();
+        let other = sut
+            .spectrogram(id, SpectrogramQuery { width: 32, ..query })
+            .await
+            .unwrap();
+
+        assert_eq!(first.name, second.name);
+        assert_eq!(first.metadata.modified, second.metadata.modified);
+        assert_ne!(first.name, other.name);
+    }
+
+    #[tokio::test]
+    async fn invalidated_by_replaced_audio() {
+        let (sut, id) = setup().await;
+        let before = sut
+            .peaks(id.clone(), PeaksQuery { resolution: 10 })
+            .await
+            .unwrap();
+        let file = sut
+            .file_storage
+            .stage_bytes(wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+        let SampleReplacement::Replaced(replaced) =
+            sut.replace_data(id.clone(), file).await.unwrap()
+        else {
+            panic!("Sample has no results");
+        };
+
+        let after = sut.peaks(id, PeaksQuery { resolution: 10 }).await.unwrap();
+
+        assert_ne!(before.name, after.name);
+        assert_eq!(cache_source(&after.name), replaced.sample.hash.as_deref());
+    }
+
+    #[tokio::test]
+    async fn missing_sample() {
+        let (sut, _) = setup().await;
+
+        let result = sut
+            .peaks("missing".to_owned(), PeaksQuery { resolution: 10 })
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(RepoError::Database(DbError::NotFound))
+        ));
+    }
+}
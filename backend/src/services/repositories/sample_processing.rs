@@ -0,0 +1,242 @@
+//! Samples derived from another sample by a chain of processing operations
+
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::services::{
+    audio::{
+        process::{process, validate_operations, Operation},
+        wav::encode_wav,
+        AudioError,
+    },
+    database::error::DbError,
+};
+
+use super::{
+    non_unique_value_on_index,
+    sample::{CreatedSample, SampleInfo, SampleRepository},
+    IsViolatingUnique, RepoError,
+};
+
+/// Bit depth of processed files
+const BIT_DEPTH: u16 = 24;
+
+/// Operations producing a new sample, the other fields of the parent are copied to it
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessedSample {
+    #[validate(length(min = 1, max = 63))]
+    pub name: String,
+    #[validate(custom = "validate_operations")]
+    pub operations: Vec<Operation>,
+}
+
+/// Parent and operations of a processed sample
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Derivation {
+    pub parent_id: String,
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessingError {
+    #[error("Processing leaves no audio")]
+    Empty,
+    #[error("Processed sample clips at {peak:.1} dBFS, lower the gain")]
+    Clipping { peak: f64 },
+    #[error("{0}")]
+    Repo(#[from] RepoError),
+}
+
+impl From<AudioError> for ProcessingError {
+    fn from(value: AudioError) -> Self {
+        Self::Repo(value.into())
+    }
+}
+
+impl<T> IsViolatingUnique<T> for Result<T, ProcessingError> {
+    fn is_violating_unique(&self) -> bool {
+        matches!(self, Err(ProcessingError::Repo(RepoError::Database(DbError::Query(e)))) if non_unique_value_on_index(e))
+    }
+}
+
+impl SampleRepository {
+    /// Apply processing operations to a sample and create a new sample from the result
+    ///
+    /// The parent is left untouched, the new sample copies its position, tags, description and Ambisonic order,
+    /// and records the parent and operations in `derivation`.
+    pub async fn process(
+        &self,
+        parent_id: String,
+        processed: ProcessedSample,
+    ) -> Result<CreatedSample, ProcessingError> {
+        let parent = self.file(parent_id).await?;
+        let audio = self.decode_blob(&parent.blob).await?;
+        let operations = processed.operations.clone();
+        let rendered = tokio::task::spawn_blocking(move || {
+            let audio = process(audio, &operations)?;
+            if audio.channels.first().is_none_or(Vec::is_empty) {
+                return Err(ProcessingError::Empty);
+            }
+            let peak = audio
+                .channels
+                .iter()
+                .flatten()
+                .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+            if peak > 1.0 {
+                return Err(ProcessingError::Clipping {
+                    peak: 20.0 * peak.log10(),
+                });
+            }
+            let channels = audio.channels.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            Ok(encode_wav(&channels, audio.sample_rate, BIT_DEPTH))
+        })
+        .await
+        .map_err(|e| AudioError::Malformed(e.to_string()))??;
+
+        let mut file = self.file_storage.stage().await.map_err(RepoError::from)?;
+        file.write(&rendered).await.map_err(RepoError::from)?;
+        let info = SampleInfo {
+            name: processed.name,
+            generator: None,
+            binaural: None,
+            derivation: Some(Box::new(Derivation {
+                parent_id: parent.sample.id.clone(),
+                operations: processed.operations,
+            })),
+            ..parent.sample.data.clone()
+        };
+        Ok(self.insert_staged(info, file).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::services::{
+        audio::{decode::decode, process::Operation, tests::wav_bytes},
+        database::{error::DbError, surreal::tests::surreal_in_memory},
+        file_storage::tests::memory_storage,
+        repositories::{
+            sample::{tests::create_sample, tests::sample_data, SampleInfo, SampleRepository},
+            IsViolatingUnique, RepoError,
+        },
+    };
+
+    use super::{Derivation, ProcessedSample, ProcessingError};
+
+    async fn setup() -> (SampleRepository, String) {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let info = SampleInfo {
+            name: "parent".to_owned(),
+            azimuth: 30.0,
+            tags: vec!["speech".to_owned()],
+            ..Default::default()
+        };
+        let parent = create_sample(
+            &sut,
+            info,
+            wav_bytes(&[0, 0, 0, 0, 8000, -8000, 8000, -8000]),
+        )
+        .await
+        .unwrap()
+        .id;
+        (sut, parent)
+    }
+
+    fn trimmed(name: &str) -> ProcessedSample {
+        ProcessedSample {
+            name: name.to_owned(),
+            operations: vec![
+                Operation::TrimSilence {
+                    threshold: -60.0,
+                    trailing: false,
+                },
+                Operation::Gain { gain: -6.0 },
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn processed() {
+        let (sut, parent) = setup().await;
+        let parent_hash = sut.info(parent.clone()).await.unwrap().hash.clone();
+
+        let created = sut.process(parent.clone(), trimmed("child")).await.unwrap();
+
+        let child = created.sample;
+        assert_eq!(child.name, "child");
+        assert_eq!(child.azimuth, 30.0);
+        assert_eq!(child.tags, vec!["speech".to_owned()]);
+        assert_eq!(
+            child.derivation.as_deref(),
+            Some(&Derivation {
+                parent_id: parent.clone(),
+                operations: trimmed("child").operations,
+            })
+        );
+        let data = sample_data(&sut, child.id.clone()).await.unwrap();
+        let audio = decode(Box::new(Cursor::new(data.to_vec()))).unwrap();
+        assert_eq!(audio.channels[0].len(), 4);
+        assert!((audio.channels[0][0] - 8000.0 / 32768.0 / 2.0).abs() < 1e-3);
+        // The parent keeps its audio
+        assert_eq!(sut.info(parent).await.unwrap().hash, parent_hash);
+    }
+
+    #[tokio::test]
+    async fn empty() {
+        let (sut, parent) = setup().await;
+        let processed = ProcessedSample {
+            name: "silence".to_owned(),
+            operations: vec![Operation::TrimSilence {
+                threshold: 0.0,
+                trailing: true,
+            }],
+        };
+
+        let result = sut.process(parent, processed).await;
+
+        assert!(matches!(result, Err(ProcessingError::Empty)));
+    }
+
+    #[tokio::test]
+    async fn clipping() {
+        let (sut, parent) = setup().await;
+        let processed = ProcessedSample {
+            name: "loud".to_owned(),
+            operations: vec![Operation::Gain { gain: 20.0 }],
+        };
+
+        let result = sut.process(parent, processed).await;
+
+        assert!(matches!(result, Err(ProcessingError::Clipping { .. })));
+    }
+
+    #[tokio::test]
+    async fn taken_name() {
+        let (sut, parent) = setup().await;
+
+        let result = sut.process(parent, trimmed("parent")).await;
+
+        assert!(result.is_violating_unique());
+    }
+
+    #[tokio::test]
+    async fn missing_parent() {
+        let (sut, _) = setup().await;
+
+        let result = sut.process("missing".to_owned(), trimmed("child")).await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessingError::Repo(RepoError::Database(
+                DbError::NotFound
+            )))
+        ));
+    }
+}
@@ -0,0 +1,481 @@
+//! Searching, filtering and paging through samples
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::{Validate, ValidationError};
+
+use crate::services::{
+    audio::loudness::LoudnessConfig,
+    database::identified::{Identified, StringIdentified, TryIntoStringId},
+};
+
+use super::{
+    sample::{SampleInfo, SampleRepository},
+    tag::parse_tags,
+    RepoResult,
+};
+
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Sample list query parameters
+///
+/// Azimuth ranges with a minimum above the maximum wrap around 0°, so `330..30` selects samples in front of the listener.
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", default)]
+#[validate(schema(function = "validate_query"))]
+pub struct SampleQuery {
+    /// Words matched against prefixes of words in sample names, case-insensitive
+    #[validate(length(min = 1, max = 63))]
+    pub name: Option<String>,
+    /// Comma separated tags which all have to be present
+    pub tags: Option<String>,
+    #[validate(range(min = 0.0, max = 360.0))]
+    pub azimuth_min: Option<f32>,
+    #[validate(range(min = 0.0, max = 360.0))]
+    pub azimuth_max: Option<f32>,
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub elevation_min: Option<f32>,
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub elevation_max: Option<f32>,
+    pub sort: SampleSort,
+    pub order: SortOrder,
+    /// Page size, 50 by default
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<u32>,
+    /// Position after the last sample of the previous page
+    pub cursor: Option<SampleCursor>,
+    /// Loudness units from the median loudness of all matching samples above which a sample is an outlier
+    #[validate(range(min = 0.0, max = 60.0))]
+    pub loudness_threshold: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SampleSort {
+    /// Case-insensitive
+    #[default]
+    Name,
+    Azimuth,
+    Elevation,
+}
+
+impl SampleSort {
+    fn expression(self) -> &'static str {
+        match self {
+            SampleSort::Name => "string::lowercase(name)",
+            SampleSort::Azimuth => "azimuth",
+            SampleSort::Elevation => "elevation",
+        }
+    }
+
+    fn key(self, sample: &SampleInfo) -> serde_json::Value {
+        match self {
+            SampleSort::Name => json!(sample.name.to_lowercase()),
+            SampleSort::Azimuth => json!(sample.azimuth),
+            SampleSort::Elevation => json!(sample.elevation),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Opaque position in a sorted sample list
+///
+/// Holds the sort key and id of the last returned sample, so pages stay consistent when samples are added or removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SampleCursor {
+    sort: SampleSort,
+    order: SortOrder,
+    key: serde_json::Value,
+    id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawCursor(SampleSort, SortOrder, serde_json::Value, String);
+
+impl TryFrom<String> for SampleCursor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let bytes = hex::decode(value).map_err(|_| "Invalid cursor")?;
+        let RawCursor(sort, order, key, id) =
+            serde_json::from_slice(&bytes).map_err(|_| "Invalid cursor")?;
+        Ok(Self {
+            sort,
+            order,
+            key,
+            id,
+        })
+    }
+}
+
+impl From<SampleCursor> for String {
+    fn from(value: SampleCursor) -> Self {
+        let raw = RawCursor(value.sort, value.order, value.key, value.id);
+        hex::encode(serde_json::to_vec(&raw).expect("Cursor is serializable"))
+    }
+}
+
+fn validate_query(query: &SampleQuery) -> Result<(), ValidationError> {
+    if let (Some(min), Some(max)) = (query.elevation_min, query.elevation_max) {
+        if min > max {
+            return Err(error("elevation", "Minimum elevation is above the maximum"));
+        }
+    }
+    if let Some(cursor) = &query.cursor {
+        if cursor.sort != query.sort || cursor.order != query.order {
+            return Err(error("cursor", "Cursor belongs to a different sort order"));
+        }
+    }
+    Ok(())
+}
+
+fn filter(conditions: &[&str]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("where {}", conditions.join(" and "))
+    }
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamplePage {
+    pub samples: Vec<StringIdentified<SampleInfo>>,
+    /// Missing on the last page
+    pub next_cursor: Option<SampleCursor>,
+    /// Median integrated loudness in LUFS of all samples matching the query, missing if none was measured
+    pub median_loudness: Option<f64>,
+    /// Samples of the page deviating from the median loudness by more than the threshold
+    pub loudness_outliers: Vec<String>,
+}
+
+impl SampleRepository {
+    /// Get a page of samples matching the query
+    ///
+    /// Name search uses a full-text index, samples with equal sort keys are ordered by id.
+    pub async fn search(&self, query: SampleQuery) -> RepoResult<SamplePage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        let mut conditions = Vec::new();
+        if query.name.is_some() {
+            conditions.push("name @@ $name");
+        }
+        let tags = query.tags.as_deref().map(parse_tags).unwrap_or_default();
+        if !tags.is_empty() {
+            conditions.push("tags containsall $tags");
+        }
+        match (query.azimuth_min, query.azimuth_max) {
+            (Some(min), Some(max)) if min > max => {
+                conditions.push("(azimuth >= $azimuth_min or azimuth <= $azimuth_max)")
+            }
+            (min, max) => {
+                if min.is_some() {
+                    conditions.push("azimuth >= $azimuth_min");
+                }
+                if max.is_some() {
+                    conditions.push("azimuth <= $azimuth_max");
+                }
+            }
+        }
+        if query.elevation_min.is_some() {
+            conditions.push("elevation >= $elevation_min");
+        }
+        if query.elevation_max.is_some() {
+            conditions.push("elevation <= $elevation_max");
+        }
+        let mut measured = conditions.clone();
+        measured.push("format.loudness.integrated != none");
+        let measured_filter = filter(&measured);
+        let key = query.sort.expression();
+        let (direction, after) = match query.order {
+            SortOrder::Asc => ("asc", ">"),
+            SortOrder::Desc => ("desc", "<"),
+        };
+        let after_cursor = format!(
+            "({key} {after} $cursor_key or ({key} = $cursor_key and record::id(id) {after} $cursor_id))"
+        );
+        if query.cursor.is_some() {
+            conditions.push(&after_cursor);
+        }
+        let filter = filter(&conditions);
+
+        let (cursor_key, cursor_id) = query.cursor.map(|cursor| (cursor.key, cursor.id)).unzip();
+        // One more sample is fetched to know whether there is a next page
+        let mut result = self
+            .database
+            .query(format!(
+                "select *, {key} as sort_key, record::id(id) as sort_id from sample {filter} order by sort_key {direction}, sort_id {direction} limit $limit"
+            ))
+            // The median over all matches is computed in the database, only a single value is returned
+            .query(format!(
+                "return math::median(select value format.loudness.integrated from sample {measured_filter})"
+            ))
+            .bind(("name", query.name))
+            .bind(("tags", tags))
+            .bind(("azimuth_min", query.azimuth_min))
+            .bind(("azimuth_max", query.azimuth_max))
+            .bind(("elevation_min", query.elevation_min))
+            .bind(("elevation_max", query.elevation_max))
+            .bind(("cursor_key", cursor_key))
+            .bind(("cursor_id", cursor_id))
+            .bind(("limit", limit + 1))
+            .await?;
+        let mut samples = result
+            .take::<Vec<Identified<SampleInfo>>>(0)?
+            .try_into_string_id()?;
+        let median_loudness = result.take::<Option<f64>>(1)?;
+
+        let next_cursor = if samples.len() > limit as usize {
+            samples.truncate(limit as usize);
+            samples.last().map(|sample| SampleCursor {
+                sort: query.sort,
+                order: query.order,
+                key: query.sort.key(sample),
+                id: sample.id.clone(),
+            })
+        } else {
+            None
+        };
+        let threshold = query
+            .loudness_threshold
+            .unwrap_or(LoudnessConfig::default().outlier_threshold);
+        let loudness_outliers = samples
+            .iter()
+            .filter(|sample| {
+                let integrated = sample
+                    .format
+                    .as_ref()
+                    .and_then(|format| format.loudness.as_ref())
+                    .and_then(|loudness| loudness.integrated);
+                matches!((integrated, median_loudness), (Some(x), Some(median)) if (x - median).abs() > threshold)
+            })
+            .map(|sample| sample.id.clone())
+            .collect();
+        Ok(SamplePage {
+            samples,
+            next_cursor,
+            median_loudness,
+            loudness_outliers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use validator::Validate;
+
+    use crate::services::{
+        audio::{tests::wav_bytes, wav::encode_wav},
+        database::surreal::tests::surreal_in_memory,
+        file_storage::tests::memory_storage,
+        repositories::sample::{tests::create_sample, SampleInfo, SampleRepository},
+    };
+
+    use super::{SampleCursor, SampleQuery, SampleSort, SortOrder};
+
+    async fn setup() -> SampleRepository {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let samples = [
+            (
+                "White_Noise_500Hz.wav",
+                10.0,
+                0.0,
+                vec!["noise", "Broadband"],
+            ),
+            ("pinkNoise.wav", 350.0, 30.0, vec!["noise"]),
+            ("tone 1k.wav", 90.0, -30.0, vec!["tone"]),
+            ("Tone 2k.wav", 90.0, 60.0, vec![]),
+        ];
+        for (i, (name, azimuth, elevation, tags)) in samples.into_iter().enumerate() {
+            let info = SampleInfo {
+                name: name.to_owned(),
+                azimuth,
+                elevation,
+                tags: tags.into_iter().map(str::to_owned).collect(),
+                ..Default::default()
+            };
+            create_sample(&sut, info, wav_bytes(&[i as i16, 1, 2, 3]))
+                .await
+                .unwrap();
+        }
+        sut
+    }
+
+    async fn names(sut: &SampleRepository, query: SampleQuery) -> Vec<String> {
+        query.validate().unwrap();
+        let page = sut.search(query).await.unwrap();
+        page.samples
+            .into_iter()
+            .map(|sample| sample.data.name)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sorted_by_name() {
+        let sut = setup().await;
+
+        let names = names(&sut, SampleQuery::default()).await;
+
+        assert_eq!(
+            names,
+            vec![
+                "pinkNoise.wav",
+                "tone 1k.wav",
+                "Tone 2k.wav",
+                "White_Noise_500Hz.wav"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let sut = setup().await;
+
+        let by_name = SampleQuery {
+            name: Some("NOI".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&sut, by_name).await,
+            vec!["pinkNoise.wav", "White_Noise_500Hz.wav"]
+        );
+        let by_tags = SampleQuery {
+            tags: Some("noise,broadband".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(names(&sut, by_tags).await, vec!["White_Noise_500Hz.wav"]);
+        let in_front = SampleQuery {
+            azimuth_min: Some(330.0),
+            azimuth_max: Some(30.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&sut, in_front).await,
+            vec!["pinkNoise.wav", "White_Noise_500Hz.wav"]
+        );
+        let above = SampleQuery {
+            elevation_min: Some(0.0),
+            elevation_max: Some(45.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&sut, above).await,
+            vec!["pinkNoise.wav", "White_Noise_500Hz.wav"]
+        );
+    }
+
+    #[tokio::test]
+    async fn pages() {
+        let sut = setup().await;
+        let mut query = SampleQuery {
+            sort: SampleSort::Azimuth,
+            order: SortOrder::Desc,
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        let first = sut.search(query.clone()).await.unwrap();
+        query.cursor = first.next_cursor;
+        let second = sut.search(query.clone()).await.unwrap();
+
+        let azimuths = first
+            .samples
+            .iter()
+            .chain(second.samples.iter())
+            .map(|sample| sample.azimuth)
+            .collect::<Vec<_>>();
+        assert_eq!(azimuths, vec![350.0, 90.0, 90.0, 10.0]);
+        assert_ne!(first.samples[1].id, second.samples[0].id);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn loudness_outliers() {
+        let sut = SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        };
+        let mut ids = Vec::new();
+        for (name, level) in [("a.wav", -20.0), ("b.wav", -21.0), ("c.wav", -30.0)] {
+            let amplitude = 10f64.powf(level / 20.0);
+            let samples = (0..48000)
+                .map(|i| amplitude * (i as f64 * 1000.0 * std::f64::consts::TAU / 48000.0).sin())
+                .collect::<Vec<_>>();
+            let info = SampleInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            };
+            let data = Bytes::from(encode_wav(&[&samples], 48000, 24));
+            ids.push(create_sample(&sut, info, data).await.unwrap().id);
+        }
+        // Uploaded before loudness was measured, not part of the median
+        sut.database
+            .query("create sample:unmeasured content { name: '0.wav', azimuth: 0, elevation: 0 }")
+            .await
+            .unwrap();
+        let query = SampleQuery {
+            limit: Some(1),
+            sort: SampleSort::Name,
+            order: SortOrder::Desc,
+            loudness_threshold: Some(3.0),
+            ..Default::default()
+        };
+
+        let page = sut.search(query).await.unwrap();
+
+        let median = page.median_loudness.unwrap();
+        assert!((median + 24.0).abs() < 0.3, "{median}");
+        assert_eq!(page.loudness_outliers, vec![ids[2].clone()]);
+    }
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = SampleCursor {
+            sort: SampleSort::Name,
+            order: SortOrder::Asc,
+            key: "tone 1k.wav".into(),
+            id: "abc".to_owned(),
+        };
+
+        let encoded = String::from(cursor.clone());
+
+        assert_eq!(SampleCursor::try_from(encoded), Ok(cursor));
+        assert!(SampleCursor::try_from("zz".to_owned()).is_err());
+    }
+
+    #[test]
+    fn cursor_of_other_sort() {
+        let cursor = SampleCursor {
+            sort: SampleSort::Name,
+            order: SortOrder::Asc,
+            key: "tone 1k.wav".into(),
+            id: "abc".to_owned(),
+        };
+        let query = SampleQuery {
+            sort: SampleSort::Azimuth,
+            cursor: Some(cursor),
+            ..Default::default()
+        };
+
+        assert!(query.validate().is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Samples rendered from a parametric stimulus description
+
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::services::audio::{
+    synth::{Signal, Stimulus},
+    AudioError,
+};
+
+use super::{
+    sample::{CreatedSample, SampleInfo, SampleRepository},
+    RepoResult,
+};
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratedSample {
+    #[validate]
+    pub info: SampleInfo,
+    #[validate]
+    pub stimulus: Stimulus,
+}
+
+impl SampleRepository {
+    /// Render a stimulus as a WAV file and create a sample from it
+    ///
+    /// The stimulus is stored with the sample, noise without a seed gets a random one first so it can be rendered again.
+    pub async fn generate(&self, sample: GeneratedSample) -> RepoResult<CreatedSample> {
+        let GeneratedSample {
+            mut info,
+            mut stimulus,
+        } = sample;
+        if matches!(stimulus.signal, Signal::Noise { .. }) && stimulus.seed.is_none() {
+            stimulus.seed = Some(uuid::Uuid::new_v4().as_u128() as u32);
+        }
+        let render = stimulus.clone();
+        let data = tokio::task::spawn_blocking(move || render.render_wav())
+            .await
+            .map_err(|e| AudioError::Malformed(e.to_string()))?;
+        let mut file = self.file_storage.stage().await?;
+        file.write(&data).await?;
+        info.generator = Some(Box::new(stimulus));
+        self.insert_staged(info, file).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{
+        audio::synth::{Signal, Stimulus},
+        database::surreal::tests::surreal_in_memory,
+        file_storage::tests::memory_storage,
+        repositories::sample::{
+            tests::create_sample, tests::sample_data, SampleInfo, SampleRepository,
+        },
+    };
+
+    use super::GeneratedSample;
+
+    async fn setup() -> SampleRepository {
+        SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        }
+    }
+
+    fn noise(name: &str, seed: Option<u32>) -> GeneratedSample {
+        GeneratedSample {
+            info: SampleInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            },
+            stimulus: Stimulus {
+                signal: Signal::Noise {
+                    low_frequency: Some(500.0),
+                    high_frequency: None,
+                },
+                duration: 0.25,
+                ramp: 0.005,
+                level: -10.0,
+                sample_rate: 44100,
+                channels: 2,
+                bit_depth: 24,
+                seed,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn generate_stores_parameters() {
+        let sut = setup().await;
+
+        let created = sut.generate(noise("noise", None)).await.unwrap();
+
+        let sample = sut.info(created.sample.id).await.unwrap();
+        let generator = sample.generator.clone().unwrap();
+        assert!(generator.seed.is_some());
+        assert_eq!(generator.signal, noise("", None).stimulus.signal);
+        let format = sample.format.clone().unwrap();
+        assert_eq!(format.channels, 2);
+        assert_eq!(format.sample_rate, 44100);
+        assert_eq!(format.duration, 0.25);
+        assert_eq!(
+            sut.file_storage
+                .metadata(&sample.hash.clone().unwrap())
+                .await
+                .unwrap()
+                .size,
+            generator.wav_size()
+        );
+    }
+
+    #[tokio::test]
+    async fn generate_reproducible() {
+        let sut = setup().await;
+        let first = sut.generate(noise("first", None)).await.unwrap();
+        let seed = sut
+            .info(first.sample.id.clone())
+            .await
+            .unwrap()
+            .generator
+            .clone()
+            .unwrap()
+            .seed;
+
+        let second = sut.generate(noise("second", seed)).await.unwrap();
+
+        assert_eq!(second.duplicates, vec!["first"]);
+        assert_eq!(
+            sample_data(&sut, first.sample.id).await.unwrap(),
+            sample_data(&sut, second.sample.id).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn uploaded_without_parameters() {
+        let sut = setup().await;
+        let mut info = noise("upload", Some(1)).info;
+        info.generator = Some(Box::new(noise("", Some(1)).stimulus));
+
+        let created = create_sample(
+            &sut,
+            info,
+            crate::services::audio::tests::wav_bytes(&[1, 2, 3]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(created.generator, None);
+    }
+}
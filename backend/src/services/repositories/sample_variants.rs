@@ -0,0 +1,155 @@
+//! Canonical WAV and FLAC copies of samples, delivered instead of the uploaded files
+
+use crate::services::{
+    audio::{probe::AudioFormat, Encoding},
+    database::surreal::MapToNotFound,
+    file_storage::CachedFile,
+};
+
+use super::{sample::SampleRepository, RepoResult};
+
+impl SampleRepository {
+    /// Get a lossless copy of a sample in the given encoding, writing it if needed
+    ///
+    /// Copies are cached next to the sample file, so replacing the audio writes a new one.
+    /// Missing when the copy would lose audio or cannot hold all channels, the original is served as it is then.
+    pub async fn variant(&self, id: String, encoding: Encoding) -> RepoResult<Option<CachedFile>> {
+        let sample = self.info(id).await?;
+        let blob = sample.hash.clone().found()?;
+        let Some(bit_depth) = sample
+            .format
+            .as_ref()
+            .filter(|format| encoding.holds(format.channels as usize))
+            .and_then(lossless_bit_depth)
+        else {
+            return Ok(None);
+        };
+        let key = match encoding {
+            Encoding::Wav => "wav",
+            Encoding::Flac => "flac",
+        };
+        let cached = self
+            .derived(&blob, key, move |audio| {
+                let channels = audio.channels.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                encoding.encode(&channels, audio.sample_rate, bit_depth)
+            })
+            .await?;
+        Ok(Some(cached))
+    }
+}
+
+/// Bit depth of a copy holding every sample value of a file, missing for lossy and floating point audio
+fn lossless_bit_depth(format: &AudioFormat) -> Option<u16> {
+    if format.codec.starts_with("pcm_f") {
+        return None;
+    }
+    match format.bit_depth? {
+        1..=16 => Some(16),
+        17..=24 => Some(24),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bytes::Bytes;
+
+    use crate::services::{
+        audio::{decode::decode, probe::probe, tests::wav_bytes, wav::encode_wav, Encoding},
+        database::surreal::tests::surreal_in_memory,
+        file_storage::{cache_source, tests::memory_storage},
+        repositories::sample::{
+            tests::create_sample, tests::sample_data, SampleInfo, SampleRepository,
+        },
+    };
+
+    async fn setup() -> SampleRepository {
+        SampleRepository {
+            database: surreal_in_memory().await,
+            file_storage: memory_storage().await,
+        }
+    }
+
+    fn info(name: &str) -> SampleInfo {
+        SampleInfo {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn lossless() {
+        let sut = setup().await;
+        let noise = (0..4000)
+            .map(|i| ((i * 7919) % 65536 - 32768) as i16)
+            .collect::<Vec<_>>();
+        let sample = create_sample(&sut, info("noise.wav"), wav_bytes(&noise))
+            .await
+            .unwrap();
+        let original = sample_data(&sut, sample.id.clone()).await.unwrap();
+        let original = decode(Box::new(Cursor::new(original.to_vec()))).unwrap();
+
+        for encoding in [Encoding::Wav, Encoding::Flac] {
+            let cached = sut
+                .variant(sample.id.clone(), encoding)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(cache_source(&cached.name), sample.hash.as_deref());
+            let data = sut.file_storage.get(&cached.name).await.unwrap();
+            let format = probe(Box::new(Cursor::new(data.to_vec()))).unwrap();
+            assert_eq!(format.bit_depth, Some(16));
+            let audio = decode(Box::new(Cursor::new(data.to_vec()))).unwrap();
+            assert_eq!(audio.sample_rate, original.sample_rate);
+            assert_eq!(audio.channels, original.channels);
+        }
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        let sut = setup().await;
+        let sample = create_sample(&sut, info("sample.wav"), wav_bytes(&[1, 2, 3]))
+            .await
+            .unwrap();
+
+        let first = sut
+            .variant(sample.id.clone(), Encoding::Flac)
+            .await
+            .unwrap()
+            .unwrap();
+        let second = sut
+            .variant(sample.id.clone(), Encoding::Flac)
+            .await
+            .unwrap()
+            .unwrap();
+        let wav = sut
+            .variant(sample.id.clone(), Encoding::Wav)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(first.name, second.name);
+        assert_eq!(first.metadata.modified, second.metadata.modified);
+        assert_ne!(first.name, wav.name);
+    }
+
+    #[tokio::test]
+    async fn too_many_channels_for_flac() {
+        let sut = setup().await;
+        let channel = [0.5; 100];
+        let data = Bytes::from(encode_wav(&[&channel[..]; 9], 48000, 24));
+        let sample = create_sample(&sut, info("nine.wav"), data).await.unwrap();
+
+        let flac = sut
+            .variant(sample.id.clone(), Encoding::Flac)
+            .await
+            .unwrap();
+        let wav = sut.variant(sample.id.clone(), Encoding::Wav).await.unwrap();
+
+        assert!(flac.is_none());
+        assert!(wav.is_some());
+    }
+}
@@ -0,0 +1,377 @@
+//! Loudspeaker arrays used in free-field experiments
+
+use std::collections::HashSet;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::services::database::{
+    error::{DbError, ValidateDbResponse},
+    identified::{Identified, StringIdentified, TryIntoStringId},
+    surreal::{Database, MapToNotFound},
+};
+
+use super::{
+    experiment::ExperimentReference,
+    position::{normalize_azimuth, validate_azimuth, validate_distance, validate_elevation},
+    RepoError, RepoResult,
+};
+
+pub struct SpeakerLayoutRepository {
+    pub database: Database,
+}
+
+/// Named set of loudspeakers, speaker identifiers and channels are unique within a layout
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate, Default)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_layout"))]
+pub struct SpeakerLayout {
+    #[validate(length(min = 1, max = 63))]
+    pub name: String,
+    #[validate(length(max = 1000))]
+    #[serde(default)]
+    pub description: Option<String>,
+    #[validate(length(min = 1, max = 256))]
+    #[validate]
+    pub speakers: Vec<Speaker>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Validate, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Speaker {
+    /// Label of the speaker, for example `L3` or `12`
+    #[validate(length(min = 1, max = 63))]
+    pub id: String,
+    /// See [`position`](super::position) for the conventions
+    #[validate(custom = "validate_azimuth")]
+    pub azimuth: f32,
+    #[validate(custom = "validate_elevation")]
+    pub elevation: f32,
+    /// Meters from the listening position
+    #[validate(custom = "validate_distance")]
+    #[serde(default)]
+    pub distance: Option<f32>,
+    /// Output channel of the audio interface, counted from 1
+    #[validate(range(min = 1, max = 1024))]
+    pub channel: u32,
+}
+
+/// Speaker of a layout playing a sample
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeakerBinding {
+    pub layout_id: String,
+    pub speaker_id: String,
+}
+
+fn validate_layout(layout: &SpeakerLayout) -> Result<(), ValidationError> {
+    let mut ids = HashSet::new();
+    let mut channels = HashSet::new();
+    for speaker in &layout.speakers {
+        if !ids.insert(&speaker.id) {
+            return Err(ValidationError::new("duplicate_speaker_id"));
+        }
+        if !channels.insert(speaker.channel) {
+            return Err(ValidationError::new("duplicate_speaker_channel"));
+        }
+    }
+    Ok(())
+}
+
+impl SpeakerLayout {
+    pub fn speaker(&self, id: &str) -> Option<&Speaker> {
+        self.speakers.iter().find(|speaker| speaker.id == id)
+    }
+
+    /// Get the speaker a sample is bound to, a missing one is reported as [`RepoError::Speaker`]
+    pub fn bound_speaker(&self, binding: &SpeakerBinding) -> RepoResult<Speaker> {
+        self.speaker(&binding.speaker_id).cloned().ok_or_else(|| {
+            RepoError::Speaker(format!(
+                "Speaker layout `{}` has no speaker `{}`",
+                self.name, binding.speaker_id
+            ))
+        })
+    }
+}
+
+/// Outcome of a layout deletion, missing layouts count as deleted
+#[derive(Debug)]
+pub enum LayoutDeletion {
+    Deleted,
+    InUse(LayoutUsers),
+}
+
+/// Samples and experiments referencing a layout
+#[derive(Debug, Serialize)]
+pub struct LayoutUsers {
+    /// Names of samples bound to speakers of the layout
+    pub samples: Vec<String>,
+    pub experiments: Vec<StringIdentified<ExperimentReference>>,
+}
+
+impl SpeakerLayoutRepository {
+    /// Create a layout and return it with an identifier
+    pub async fn create(
+        &self,
+        mut layout: SpeakerLayout,
+    ) -> RepoResult<StringIdentified<SpeakerLayout>> {
+        for speaker in layout.speakers.iter_mut() {
+            speaker.azimuth = normalize_azimuth(speaker.azimuth);
+        }
+        let mut result = self
+            .database
+            .query("create only speaker_layout content $layout")
+            .bind(("layout", layout))
+            .await?
+            .validate()?;
+        let layout = result
+            .take::<Option<Identified<SpeakerLayout>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(layout)
+    }
+
+    /// List layouts ordered by name
+    pub async fn infos(&self) -> RepoResult<Vec<StringIdentified<SpeakerLayout>>> {
+        let mut result = self
+            .database
+            .query("select * from speaker_layout order by name")
+            .await?;
+        let layouts = result
+            .take::<Vec<Identified<SpeakerLayout>>>(0)?
+            .try_into_string_id()?;
+        Ok(layouts)
+    }
+
+    /// Get a layout
+    pub async fn info(&self, id: String) -> RepoResult<StringIdentified<SpeakerLayout>> {
+        let mut result = self
+            .database
+            .query("select * from only speaker_layout where record::id(id) is $layout_id limit 1")
+            .bind(("layout_id", id))
+            .await?;
+        let layout = result
+            .take::<Option<Identified<SpeakerLayout>>>(0)?
+            .found()?
+            .try_into_string_id()?;
+        Ok(layout)
+    }
+
+    /// Get a layout referenced by another record, a missing layout is reported as [`RepoError::Speaker`]
+    pub async fn referenced(&self, id: String) -> RepoResult<StringIdentified<SpeakerLayout>> {
+        match self.info(id.clone()).await {
+            Err(RepoError::Database(DbError::NotFound)) => Err(RepoError::Speaker(format!(
+                "Speaker layout `{id}` does not exist"
+            ))),
+            result => result,
+        }
+    }
+
+    /// Get the speaker a sample is bound to
+    pub async fn speaker(&self, binding: &SpeakerBinding) -> RepoResult<Speaker> {
+        let layout = self.referenced(binding.layout_id.clone()).await?;
+        layout.bound_speaker(binding)
+    }
+
+    /// Delete a layout unless samples or experiments use it
+    ///
+    /// References are checked in the same transaction as the deletion.
+    pub async fn delete(&self, id: String) -> RepoResult<LayoutDeletion> {
+        let mut result = self
+            .database
+            .query("begin")
+            .query("let $samples = select value name from sample where speaker.layoutId is $layout_id")
+            .query("let $experiments = select id, name from experiment where speaker_layout_id is $layout_id")
+            .query(
+                r"
+                if array::len($samples) == 0 and array::len($experiments) == 0 {
+                    delete speaker_layout where record::id(id) is $layout_id;
+                };
+                ",
+            )
+            .query("commit")
+            .query("return $samples")
+            .query("return $experiments")
+            .bind(("layout_id", id))
+            .await?
+            .validate()?;
+        let samples = result.take::<Vec<String>>(3)?;
+        let experiments = result
+            .take::<Vec<Identified<ExperimentReference>>>(4)?
+            .try_into_string_id()?;
+        if samples.is_empty() && experiments.is_empty() {
+            Ok(LayoutDeletion::Deleted)
+        } else {
+            Ok(LayoutDeletion::InUse(LayoutUsers {
+                samples,
+                experiments,
+            }))
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SpeakerLayoutRepository
+where
+    Database: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(_: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            database: Database::from_ref(state),
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use validator::Validate;
+
+    use crate::services::{
+        audio::tests::wav_bytes,
+        database::{
+            error::DbError,
+            surreal::{tests::surreal_in_memory, Database},
+        },
+        file_storage::tests::memory_storage,
+        repositories::{
+            sample::{tests::create_sample, SampleInfo, SampleRepository},
+            IsViolatingUnique, RepoError,
+        },
+    };
+
+    use super::{LayoutDeletion, Speaker, SpeakerBinding, SpeakerLayout, SpeakerLayoutRepository};
+
+    /// Ring of speakers `front`, `right`, `back` and `left` on channels 1 to 4
+    pub fn ring(name: &str) -> SpeakerLayout {
+        let speakers = ["front", "right", "back", "left"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| Speaker {
+                id: id.to_owned(),
+                azimuth: i as f32 * 90.0,
+                elevation: 0.0,
+                distance: Some(1.5),
+                channel: i as u32 + 1,
+            })
+            .collect();
+        SpeakerLayout {
+            name: name.to_owned(),
+            speakers,
+            ..Default::default()
+        }
+    }
+
+    pub async fn create_ring(database: &Database, name: &str) -> String {
+        SpeakerLayoutRepository {
+            database: database.clone(),
+        }
+        .create(ring(name))
+        .await
+        .unwrap()
+        .id
+    }
+
+    async fn setup() -> SpeakerLayoutRepository {
+        SpeakerLayoutRepository {
+            database: surreal_in_memory().await,
+        }
+    }
+
+    #[tokio::test]
+    async fn create() {
+        let sut = setup().await;
+        let mut layout = ring("ring");
+        layout.speakers[3].azimuth = -90.0;
+
+        let created = sut.create(layout).await.unwrap();
+
+        assert_eq!(created.speakers.len(), 4);
+        assert_eq!(created.speaker("left").unwrap().azimuth, 270.0);
+        assert_eq!(sut.info(created.id.clone()).await.unwrap(), created);
+        assert_eq!(sut.infos().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_same_name() {
+        let sut = setup().await;
+        sut.create(ring("ring")).await.unwrap();
+
+        let result = sut.create(ring("ring")).await;
+
+        assert!(result.is_violating_unique());
+    }
+
+    #[test]
+    fn duplicate_speakers() {
+        let mut same_id = ring("ring");
+        same_id.speakers[1].id = "front".to_owned();
+        let mut same_channel = ring("ring");
+        same_channel.speakers[1].channel = 1;
+
+        assert!(ring("ring").validate().is_ok());
+        assert!(same_id.validate().is_err());
+        assert!(same_channel.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn speaker() {
+        let sut = setup().await;
+        let id = create_ring(&sut.database, "ring").await;
+        let binding = |layout_id: &str, speaker_id: &str| SpeakerBinding {
+            layout_id: layout_id.to_owned(),
+            speaker_id: speaker_id.to_owned(),
+        };
+
+        let speaker = sut.speaker(&binding(&id, "back")).await.unwrap();
+        let missing_speaker = sut.speaker(&binding(&id, "top")).await;
+        let missing_layout = sut.speaker(&binding("missing", "back")).await;
+
+        assert_eq!(speaker.azimuth, 180.0);
+        assert!(matches!(missing_speaker, Err(RepoError::Speaker(_))));
+        assert!(matches!(missing_layout, Err(RepoError::Speaker(_))));
+    }
+
+    #[tokio::test]
+    async fn delete_in_use() {
+        let sut = setup().await;
+        let id = create_ring(&sut.database, "ring").await;
+        let sample_repo = SampleRepository {
+            database: sut.database.clone(),
+            file_storage: memory_storage().await,
+        };
+        let info = SampleInfo {
+            name: "bound".to_owned(),
+            speaker: Some(SpeakerBinding {
+                layout_id: id.clone(),
+                speaker_id: "left".to_owned(),
+            }),
+            ..Default::default()
+        };
+        let sample = create_sample(&sample_repo, info, wav_bytes(&[1, 2]))
+            .await
+            .unwrap();
+
+        let in_use = sut.delete(id.clone()).await.unwrap();
+        sample_repo.delete(sample.id).await.unwrap();
+        let deleted = sut.delete(id.clone()).await.unwrap();
+
+        let LayoutDeletion::InUse(users) = in_use else {
+            panic!("Layout is not in use");
+        };
+        assert_eq!(users.samples, vec!["bound".to_owned()]);
+        assert!(matches!(deleted, LayoutDeletion::Deleted));
+        assert!(matches!(
+            sut.info(id).await,
+            Err(RepoError::Database(DbError::NotFound))
+        ));
+    }
+}
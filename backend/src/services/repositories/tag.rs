@@ -0,0 +1,80 @@
+//! Free-form sample tags
+//!
+//! Tags are compared case-insensitively, so they are stored trimmed, lowercase, sorted and without duplicates.
+//! Commas separate tags in filters and cannot be part of a tag.
+
+use std::borrow::Cow;
+
+use validator::ValidationError;
+
+const MAX_TAGS: usize = 32;
+const MAX_TAG_LENGTH: usize = 32;
+
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Split a comma separated filter into normalized tags, empty entries are ignored
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    let tags = tags
+        .split(',')
+        .filter(|tag| !tag.trim().is_empty())
+        .map(str::to_owned)
+        .collect();
+    normalize_tags(tags)
+}
+
+pub fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    if tags.len() > MAX_TAGS {
+        return Err(error("At most 32 tags are allowed"));
+    }
+    for tag in tags {
+        let length = tag.trim().chars().count();
+        if length == 0 || length > MAX_TAG_LENGTH {
+            return Err(error("Tags must have between 1 and 32 characters"));
+        }
+        if tag.contains(',') {
+            return Err(error("Tags cannot contain commas"));
+        }
+    }
+    Ok(())
+}
+
+fn error(message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new("tags");
+    error.message = Some(Cow::Borrowed(message));
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_tags, parse_tags, validate_tags};
+
+    #[test]
+    fn normalize() {
+        let tags = vec![" Noise".to_owned(), "hrtf".to_owned(), "noise ".to_owned()];
+
+        assert_eq!(normalize_tags(tags), vec!["hrtf", "noise"]);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_tags("Noise, hrtf,,"), vec!["hrtf", "noise"]);
+        assert!(parse_tags("").is_empty());
+    }
+
+    #[test]
+    fn validate() {
+        assert!(validate_tags(&["noise".to_owned()]).is_ok());
+        assert!(validate_tags(&[" ".to_owned()]).is_err());
+        assert!(validate_tags(&["a,b".to_owned()]).is_err());
+        assert!(validate_tags(&["x".repeat(33)]).is_err());
+        assert!(validate_tags(&vec!["tag".to_owned(); 33]).is_err());
+    }
+}
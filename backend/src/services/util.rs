@@ -12,6 +12,7 @@ pub enum ResponseType<T = ()> {
     Data(T),
     Status(StatusCode),
     JsonErr(ValidatedJsonRejection),
+    Error(StatusCode, String),
 }
 
 impl<T: IntoResponse> IntoResponse for ResponseType<T> {
@@ -20,6 +21,7 @@ impl<T: IntoResponse> IntoResponse for ResponseType<T> {
             ResponseType::Status(r) => r.into_response(),
             ResponseType::Data(r) => r.into_response(),
             ResponseType::JsonErr(e) => e.into_response(),
+            ResponseType::Error(status, message) => (status, message).into_response(),
         }
     }
 }
@@ -12,6 +12,7 @@ import { Euler, type Mesh } from "three";
 import { OBJLoader } from "three/examples/jsm/Addons.js";
 import { deg2rad, sphericalToCartesian } from "../utils/mathUtils";
 import { SphericalCoordinates } from "schemas/coordinates";
+import { Speaker } from "schemas/speakerLayoutSchemas";
 
 const MeshHATS = ({
   position,
@@ -80,12 +81,14 @@ const StageContent = ({
   selection,
   setSelection,
   highlight,
-  currentSample
+  currentSample,
+  speakers
 }: {
   selection: SphericalCoordinates | null;
   setSelection: (selection: SphericalCoordinates) => void;
   highlight: SphericalCoordinates | null;
   currentSample: "start" | "end" | number;
+  speakers?: Speaker[];
 }): JSX.Element => {
   const DIVISIONS_AZIMUTH = 24;
   const DIVISIONS_ELEVATION = 12;
@@ -126,7 +129,39 @@ const StageContent = ({
         <meshStandardMaterial color={"orange"} />
       </Box>
 
-      {azimuthAngles.map((theta) =>
+      {speakers?.map((speaker) => (
+        <group key={`speaker:${speaker.id}`}>
+          <TargetSphere
+            position={sphericalToCartesian(
+              RADIUS,
+              speaker.azimuth + 90,
+              speaker.elevation
+            )}
+            onClick={() => {
+              !highlight &&
+                setSelection({
+                  azimuth: speaker.azimuth,
+                  elevation: speaker.elevation,
+                  speakerId: speaker.id
+                });
+            }}
+            active={selection?.speakerId === speaker.id}
+            highlight={highlight?.speakerId === speaker.id}
+            isHoverDisabled={!!highlight}
+          />
+          <Text
+            position={sphericalToCartesian(
+              RADIUS,
+              speaker.azimuth + 90,
+              Math.min(speaker.elevation + 4, 90)
+            )}
+            fontSize={0.6}
+          >
+            {speaker.id}
+          </Text>
+        </group>
+      ))}
+      {!speakers && azimuthAngles.map((theta) =>
         elevationAngles.map((phi) => {
           if ((phi === -90 || phi === 90) && theta !== 0) return null; // remove duplicate points on top and bottom
           return (
@@ -190,12 +225,15 @@ export const Stage = ({
   selection,
   setSelection,
   highlight,
-  currentSample
+  currentSample,
+  speakers
 }: {
   selection: SphericalCoordinates | null;
   setSelection: (selection: SphericalCoordinates) => void;
   highlight: SphericalCoordinates | null;
   currentSample: "start" | "end" | number;
+  // Speakers of the layout offered as the only answers, a grid of directions is shown without them
+  speakers?: Speaker[];
 }): JSX.Element => {
   return (
     <div className="flex w-full h-full bg-black">
@@ -205,6 +243,7 @@ export const Stage = ({
           setSelection={setSelection}
           highlight={highlight}
           currentSample={currentSample}
+          speakers={speakers}
         />
       </Canvas>
     </div>
@@ -1,2 +1,12 @@
-export const getAudioPath = (audioId: string): string =>
-  `${import.meta.env.VITE_BASE_API_URL}/audio/${audioId}`;
+export const getAudioPath = (
+  audioId: string,
+  format?: "original" | "wav" | "flac"
+): string =>
+  `${import.meta.env.VITE_BASE_API_URL}/audio/${audioId}` +
+  (format ? `?format=${format}` : "");
+
+export const getSpectrogramPath = (
+  audioId: string,
+  width: number,
+  height: number
+): string => `${getAudioPath(audioId)}/spectrogram?width=${width}&height=${height}`;
@@ -1,4 +1,6 @@
 export interface SphericalCoordinates {
   azimuth: number;
   elevation: number;
+  // Speaker of the layout at this position, in experiments using a speaker layout
+  speakerId?: string;
 }
@@ -5,11 +5,96 @@ export const idSchema = z.string();
 
 export type Id = z.infer<typeof idSchema>;
 
+export const experimentStateSchema = z.enum([
+  "draft",
+  "open",
+  "closed",
+  "archived",
+]);
+
+export type ExperimentState = z.infer<typeof experimentStateSchema>;
+
+// States an experiment can be moved to, see ExperimentState::can_become
+export const experimentTransitions: Record<ExperimentState, ExperimentState[]> =
+  {
+    draft: ["open", "archived"],
+    open: ["closed"],
+    closed: ["open", "archived"],
+    archived: ["closed"],
+  };
+
+export const randomizationSchema = z.enum([
+  "fixed",
+  "shuffle",
+  "within_block",
+  "no_consecutive_repeats",
+]);
+
+export type Randomization = z.infer<typeof randomizationSchema>;
+
+export const trialDesignSchema = z.object({
+  repetitions: z.number(),
+  blocks: z.number(),
+  randomization: randomizationSchema,
+  seed: z.nullish(z.number()),
+});
+
+export type TrialDesign = z.infer<typeof trialDesignSchema>;
+
+export const trialSchema = z.object({
+  sample_id: z.string(),
+  block: z.number(),
+  repetition: z.number(),
+});
+
+export type Trial = z.infer<typeof trialSchema>;
+
+export const trialSequenceSchema = z.object({
+  id: idSchema,
+  experiment_id: z.string(),
+  ordinal: z.number(),
+  seed: z.number(),
+  participant: z.nullish(z.string()),
+  trials: z.array(trialSchema),
+  created_at: z.string(),
+});
+
+export type TrialSequence = z.infer<typeof trialSequenceSchema>;
+
+export const sessionStatusSchema = z.enum(["active", "finished", "abandoned"]);
+
+export type SessionStatus = z.infer<typeof sessionStatusSchema>;
+
+export const sessionSchema = z.object({
+  id: idSchema,
+  experiment_id: z.string(),
+  sequence_id: z.string(),
+  participant: z.string(),
+  training: z.boolean(),
+  trials: z.array(trialSchema),
+  responses: sampleResultListSchema,
+  started_at: z.string(),
+  updated_at: z.string(),
+  finished_at: z.nullish(z.string()),
+  result_id: z.nullish(z.string()),
+  status: sessionStatusSchema,
+  next_trial: z.number(),
+});
+
+export type Session = z.infer<typeof sessionSchema>;
+
+export const sessionListSchema = z.array(sessionSchema);
+
 export const experimentSchema = z.object({
   id: idSchema,
   name: z.string(),
   sample_ids: z.array(z.string()),
   is_public: z.optional(z.boolean()),
+  speaker_layout_id: z.nullish(z.string()),
+  state: experimentStateSchema.default("open"),
+  opens_at: z.nullish(z.string()),
+  closes_at: z.nullish(z.string()),
+  design: z.optional(trialDesignSchema),
 });
 
 export type Experiment = z.infer<typeof experimentSchema>;
@@ -23,6 +108,7 @@ export const experimentResultSchema = z.object({
   sample_results: sampleResultListSchema,
   training: z.boolean(),
   user: z.string(),
+  sequence_id: z.nullish(z.string()),
 });
 
 export type ExperimentResult = z.infer<typeof experimentResultSchema>;
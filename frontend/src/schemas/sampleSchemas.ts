@@ -4,11 +4,35 @@ export const idSchema = z.string();
 
 export type Id = z.infer<typeof idSchema>;
 
+export const audioFormatSchema = z.object({
+  codec: z.string(),
+  sampleRate: z.number(),
+  channels: z.number(),
+  bitDepth: z.nullable(z.number()),
+  duration: z.number(),
+  loudness: z.nullish(z.object({
+    integrated: z.nullish(z.number()),
+    truePeak: z.nullish(z.number()),
+    rms: z.nullish(z.number())
+  }))
+});
+
+export type AudioFormat = z.infer<typeof audioFormatSchema>;
+
 export const sampleSchema = z.object({
   id: idSchema,
   name: z.string(),
   azimuth: z.number(),
-  elevation: z.number()
+  elevation: z.number(),
+  distance: z.nullish(z.number()),
+  tags: z.array(z.string()).default([]),
+  description: z.nullish(z.string()),
+  format: z.nullish(audioFormatSchema),
+  generator: z.nullish(z.record(z.unknown())),
+  binaural: z.nullish(z.record(z.unknown())),
+  derivation: z.nullish(z.record(z.unknown())),
+  ambisonics: z.nullish(z.object({ order: z.number() })),
+  speaker: z.nullish(z.object({ layoutId: z.string(), speakerId: z.string() }))
 });
 
 export type Sample = z.infer<typeof sampleSchema>;
@@ -17,10 +41,39 @@ export const sampleListSchema = z.array(sampleSchema);
 
 export type SampleList = z.infer<typeof sampleListSchema>;
 
+export const samplePageSchema = z.object({
+  samples: sampleListSchema,
+  nextCursor: z.nullish(z.string()),
+  medianLoudness: z.nullish(z.number()),
+  loudnessOutliers: z.array(z.string()).default([])
+});
+
+export type SamplePage = z.infer<typeof samplePageSchema>;
+
+export const peaksSchema = z.object({
+  sampleRate: z.number(),
+  framesPerPeak: z.number(),
+  channels: z.array(z.array(z.tuple([z.number(), z.number()])))
+});
+
+export type Peaks = z.infer<typeof peaksSchema>;
+
+export const sampleInUseSchema = z.object({
+  message: z.string(),
+  experiments: z.array(z.object({
+    id: idSchema,
+    name: z.string()
+  }))
+});
+
+export type SampleInUse = z.infer<typeof sampleInUseSchema>;
+
 export const sampleResultSchema = z.object({
   sample_id: z.string(),
   azimuth: z.number(),
-  elevation: z.number()
+  elevation: z.number(),
+  distance: z.nullish(z.number()),
+  speaker_id: z.nullish(z.string())
 });
 
 export type SampleResult = z.infer<typeof sampleResultSchema>;
@@ -28,3 +81,16 @@ export type SampleResult = z.infer<typeof sampleResultSchema>;
 export const sampleResultListSchema = z.array(sampleResultSchema);
 
 export type SampleResultList = z.infer<typeof sampleResultListSchema>;
+
+export const importReportSchema = z.object({
+  imported: z.boolean(),
+  rows: z.array(z.object({
+    row: z.number(),
+    file: z.string(),
+    name: z.string(),
+    status: z.enum(["created", "valid", "skipped", "failed"]),
+    reason: z.nullish(z.string())
+  }))
+});
+
+export type ImportReport = z.infer<typeof importReportSchema>;
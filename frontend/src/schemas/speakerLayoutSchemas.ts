@@ -0,0 +1,25 @@
+import { z } from "zod";
+import { idSchema } from "./experimentSchemas.ts";
+
+export const speakerSchema = z.object({
+  id: z.string(),
+  azimuth: z.number(),
+  elevation: z.number(),
+  distance: z.nullish(z.number()),
+  channel: z.number()
+});
+
+export type Speaker = z.infer<typeof speakerSchema>;
+
+export const speakerLayoutSchema = z.object({
+  id: idSchema,
+  name: z.string(),
+  description: z.nullish(z.string()),
+  speakers: z.array(speakerSchema)
+});
+
+export type SpeakerLayout = z.infer<typeof speakerLayoutSchema>;
+
+export const speakerLayoutListSchema = z.array(speakerLayoutSchema);
+
+export type SpeakerLayoutList = z.infer<typeof speakerLayoutListSchema>;
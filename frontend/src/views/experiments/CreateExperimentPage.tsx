@@ -9,11 +9,16 @@ import SamplePreviewWidget from "views/samples/SamplePreviewWidget";
 import { FrostedGlass } from "../../components/FrostedGlass.tsx";
 import { defaultRequestInit } from "utils/fetchUtils.ts";
 import { onEnterDown } from "utils/formUtils.ts";
+import { speakerLayoutListSchema } from "schemas/speakerLayoutSchemas";
+import { Randomization, TrialDesign } from "schemas/experimentSchemas";
 
 const createExperiment = async (
   name: string,
   sample_ids: string[],
   is_public: boolean,
+  speaker_layout_id: string | null,
+  state: "draft" | "open",
+  design: TrialDesign,
   callback: (success: boolean, statusCode: number) => void
 ): Promise<void> => {
   const { VITE_BASE_API_URL } = import.meta.env;
@@ -21,7 +26,14 @@ const createExperiment = async (
   const response = await fetch(`${VITE_BASE_API_URL}/experiments`, {
     ...defaultRequestInit,
     method: "POST",
-    body: JSON.stringify({ name, sample_ids, is_public })
+    body: JSON.stringify({
+      name,
+      sample_ids,
+      is_public,
+      speaker_layout_id,
+      state,
+      design
+    })
   });
 
   if (response.ok) {
@@ -38,6 +50,13 @@ const CreateExperimentPage = () => {
   const [name, setName] = useState<string>("");
   const [sampleIds, setSampleIds] = useState<Array<string>>([]);
   const [isPublic, setIsPublic] = useState<boolean>(true);
+  const [speakerLayoutId, setSpeakerLayoutId] = useState<string | null>(null);
+  const [isDraft, setIsDraft] = useState<boolean>(false);
+  const [design, setDesign] = useState<TrialDesign>({
+    repetitions: 1,
+    blocks: 1,
+    randomization: "shuffle"
+  });
 
   const addSample = (id: string) => {
     setSampleIds((prevState) => [...prevState, id]);
@@ -60,7 +79,15 @@ const CreateExperimentPage = () => {
 
   const handleCreate = async () => {
     try {
-      await createExperiment(name, sampleIds, isPublic, onCreated);
+      await createExperiment(
+        name,
+        sampleIds,
+        isPublic,
+        speakerLayoutId,
+        isDraft ? "draft" : "open",
+        design,
+        onCreated
+      );
     } catch (error) {
       console.error(error);
       fireAlert("Error occured", String(error));
@@ -95,6 +122,19 @@ const CreateExperimentPage = () => {
               onChange={() => setIsPublic((prevValue) => !prevValue)}
             />
           </div>
+          <div className="flex flex-row items-center w-full">
+            <p className="pr-md">Is draft?</p>
+            <input
+              type="checkbox"
+              checked={isDraft}
+              onChange={() => setIsDraft((prevValue) => !prevValue)}
+            />
+          </div>
+          <SpeakerLayoutSelector
+            speakerLayoutId={speakerLayoutId}
+            setSpeakerLayoutId={setSpeakerLayoutId}
+          />
+          <TrialDesignSelector design={design} setDesign={setDesign} />
         </div>
         <AudioSelector
           selectedSampleIds={sampleIds}
@@ -104,7 +144,11 @@ const CreateExperimentPage = () => {
         <div className="w-full flex flex-col">
           <ButtonSecondary
             onClick={handleCreate}
-            disabled={sampleIds.length === 0 || name.length === 0}
+            disabled={
+              sampleIds.length === 0 ||
+              name.length === 0 ||
+              design.repetitions % design.blocks !== 0
+            }
           >
             Create
           </ButtonSecondary>
@@ -114,6 +158,108 @@ const CreateExperimentPage = () => {
   );
 };
 
+const randomizations: Record<Randomization, string> = {
+  fixed: "Fixed order",
+  shuffle: "Shuffle all trials",
+  within_block: "Shuffle within blocks",
+  no_consecutive_repeats: "Shuffle within blocks, no repeats in a row"
+};
+
+const TrialDesignSelector = ({
+  design,
+  setDesign
+}: {
+  design: TrialDesign;
+  setDesign: (design: TrialDesign) => void;
+}) => {
+  const setCount = (field: "repetitions" | "blocks", value: string) =>
+    setDesign({ ...design, [field]: Math.max(1, Number(value) || 1) });
+
+  return (
+    <>
+      <div className="flex flex-row items-center w-full">
+        <p className="pr-md">Repetitions of every sample</p>
+        <input
+          className="w-16 px-2 py-1"
+          type="number"
+          min={1}
+          max={100}
+          value={design.repetitions}
+          onChange={(e) => setCount("repetitions", e.target.value)}
+        />
+      </div>
+      <div className="flex flex-row items-center w-full">
+        <p className="pr-md">Blocks (must divide the repetitions)</p>
+        <input
+          className="w-16 px-2 py-1"
+          type="number"
+          min={1}
+          max={100}
+          value={design.blocks}
+          onChange={(e) => setCount("blocks", e.target.value)}
+        />
+      </div>
+      <div className="flex flex-row items-center w-full">
+        <p className="pr-md">Trial order</p>
+        <select
+          className="flex-1 px-2 py-1"
+          value={design.randomization}
+          onChange={(e) =>
+            setDesign({
+              ...design,
+              randomization: e.target.value as Randomization
+            })
+          }
+        >
+          {Object.entries(randomizations).map(([value, label]) => (
+            <option key={value} value={value}>
+              {label}
+            </option>
+          ))}
+        </select>
+      </div>
+    </>
+  );
+};
+
+const SpeakerLayoutSelector = ({
+  speakerLayoutId,
+  setSpeakerLayoutId
+}: {
+  speakerLayoutId: string | null;
+  setSpeakerLayoutId: (id: string | null) => void;
+}) => {
+  const { VITE_BASE_API_URL } = import.meta.env;
+
+  const getSpeakerLayouts = () =>
+    fetch(`${VITE_BASE_API_URL}/speaker-layouts`, defaultRequestInit)
+      .then((res) => res.json())
+      .then((data) => speakerLayoutListSchema.parse(data));
+
+  const { data } = useQuery({
+    queryKey: ["speakerLayouts"],
+    queryFn: getSpeakerLayouts
+  });
+
+  return (
+    <div className="flex flex-row items-center w-full">
+      <p className="pr-md">Speaker layout</p>
+      <select
+        className="flex-1 px-2 py-1"
+        value={speakerLayoutId ?? ""}
+        onChange={(e) => setSpeakerLayoutId(e.target.value || null)}
+      >
+        <option value="">None, any direction</option>
+        {data?.map((layout) => (
+          <option key={layout.id} value={layout.id}>
+            {layout.name} ({layout.speakers.length} speakers)
+          </option>
+        ))}
+      </select>
+    </div>
+  );
+};
+
 const AudioSelector = ({
   selectedSampleIds,
   addSample,
@@ -2,23 +2,24 @@ import { useQuery } from "@tanstack/react-query";
 import { useParams, Link } from "@tanstack/react-router";
 import { Howl } from "howler";
 import { Stage } from "components/Stage";
-import { experimentSchema } from "schemas/experimentSchemas";
+import {
+  experimentSchema,
+  Session,
+  sessionSchema,
+  Trial
+} from "schemas/experimentSchemas";
 import { useEffect, useRef, useState } from "react";
 import { ButtonPrimary, ButtonSecondary } from "components/Buttons.tsx";
 import { getAudioPath } from "components/player/utils.ts";
 import { SphericalCoordinates } from "schemas/coordinates";
-import {
-  Sample,
-  SampleList,
-  sampleListSchema,
-  SampleResult
-} from "schemas/sampleSchemas";
+import { Sample, SampleList, sampleListSchema } from "schemas/sampleSchemas";
 import LoadingSpinner from "../../components/LoadingSpinner.tsx";
 import { FrostedGlass } from "../../components/FrostedGlass.tsx";
 import { fireAlert } from "components/AlertDialogs.tsx";
 import { defaultRequestInit } from "utils/fetchUtils.ts";
 import { FaArrowLeft } from "react-icons/fa";
 import { onEnterDown } from "utils/formUtils.ts";
+import { speakerLayoutSchema } from "schemas/speakerLayoutSchemas";
 
 const ExperimentPage = () => {
   const { VITE_BASE_API_URL } = import.meta.env;
@@ -34,26 +35,34 @@ const ExperimentPage = () => {
     queryFn: getExperiment
   });
 
-  const [audioList, setAudioList] = useState<string[]>([]);
+  const layoutId = data?.speaker_layout_id;
 
-  useEffect(() => {
-    setAudioList(
-      data?.sample_ids
-        .map((sampleId) => getAudioPath(sampleId))
-        .sort(() => Math.random() - 0.5) ?? []
-    );
-  }, [data]);
+  const getSpeakerLayout = () =>
+    fetch(`${VITE_BASE_API_URL}/speaker-layouts/${layoutId}`, defaultRequestInit)
+      .then((res) => res.json())
+      .then((data) => speakerLayoutSchema.parse(data));
+
+  const { data: speakerLayout } = useQuery({
+    queryKey: ["speakerLayout", layoutId],
+    queryFn: getSpeakerLayout,
+    enabled: !!layoutId
+  });
 
-  const [sampleCoordinatesList, setSampleCoordinatesList] = useState<
-    SphericalCoordinates[]
-  >([]);
+  // Trials drawn by the server for this participant, in presentation order
+  const [trials, setTrials] = useState<Trial[]>([]);
+  const [sessionId, setSessionId] = useState<string | null>(null);
+  // Unfinished session of this experiment started earlier in this browser
+  const [resumable, setResumable] = useState<Session | null>(null);
+
+  const [sampleCoordinates, setSampleCoordinates] = useState<
+    Record<string, SphericalCoordinates>
+  >({});
 
   const playerRef = useRef<Howl | undefined>();
 
   const [currentStep, setCurrentStep] = useState<"start" | number | "end">(
     "start"
   );
-  const results = useRef<SampleResult[]>([]);
 
   // Current location selection, selected by the user
   const [selection, setSelection] = useState<SphericalCoordinates | null>(null);
@@ -63,16 +72,31 @@ const ExperimentPage = () => {
   const [trainingMode, setTrainingMode] = useState<boolean>(false);
 
   useEffect(() => {
-    if (typeof currentStep === "number" && currentStep >= 0) {
-      playerRef.current?.stop();
-      playerRef.current = new Howl({
-        src: [audioList[currentStep]],
-        format: ["mp3"],
-        volume: 1.0,
-        loop: false,
-        autoplay: true
+    if (typeof currentStep !== "number" || currentStep < 0) return;
+    playerRef.current?.stop();
+    // The server picks the copy it delivers, its content type tells Howler the format
+    let cancelled = false;
+    let objectUrl: string | undefined;
+    fetch(getAudioPath(trials[currentStep].sample_id), {
+      credentials: "include",
+      headers: { Accept: "audio/flac, audio/wav;q=0.9, */*;q=0.1" }
+    })
+      .then((res) => res.blob())
+      .then((blob) => {
+        if (cancelled) return;
+        objectUrl = URL.createObjectURL(blob);
+        playerRef.current = new Howl({
+          src: [objectUrl],
+          format: [blob.type.split("/")[1]],
+          volume: 1.0,
+          loop: false,
+          autoplay: true
+        });
       });
-    }
+    return () => {
+      cancelled = true;
+      if (objectUrl) URL.revokeObjectURL(objectUrl);
+    };
   }, [currentStep]);
 
   useEffect(() => {
@@ -86,46 +110,98 @@ const ExperimentPage = () => {
       const responseData = await rawResponse.json();
       const allSamplesOfTheWorld: SampleList =
         sampleListSchema.parse(responseData);
-      setSampleCoordinatesList(
-        data.sample_ids.map((sampleId) => {
-          const sample: Sample | undefined = allSamplesOfTheWorld.find(
-            (sample) => sample.id === sampleId
-          );
-          if (!sample) return { azimuth: 0, elevation: 0 };
-          const coords: SphericalCoordinates = {
-            azimuth: sample.azimuth,
-            elevation: sample.elevation
-          };
-          return coords;
-        })
+      setSampleCoordinates(
+        Object.fromEntries(
+          data.sample_ids.map((sampleId) => {
+            const sample: Sample | undefined = allSamplesOfTheWorld.find(
+              (sample) => sample.id === sampleId
+            );
+            if (!sample) return [sampleId, { azimuth: 0, elevation: 0 }];
+            const coords: SphericalCoordinates = {
+              azimuth: sample.azimuth,
+              elevation: sample.elevation,
+              speakerId: sample.speaker?.speakerId
+            };
+            return [sampleId, coords];
+          })
+        )
       );
     };
 
     fetchAllSamplesOfTheWorld();
   }, [data]);
 
-  const saveResult = () => {
-    results.current = [
-      ...results.current,
+  useEffect(() => {
+    const storedId = localStorage.getItem(sessionStorageKey(id));
+    if (!storedId) return;
+
+    fetch(
+      `${VITE_BASE_API_URL}/experiments/sessions/${storedId}`,
+      defaultRequestInit
+    )
+      .then((res) => (res.ok ? res.json() : null))
+      .then((data) => {
+        const session = data && sessionSchema.parse(data);
+        if (session && session.status !== "finished") setResumable(session);
+        else localStorage.removeItem(sessionStorageKey(id));
+      });
+  }, [id]);
+
+  const continueSession = (session: Session) => {
+    localStorage.setItem(sessionStorageKey(id), session.id);
+    setTrials(session.trials);
+    setSessionId(session.id);
+    setTrainingMode(session.training);
+    if (session.next_trial === session.trials.length) setCurrentStep("end");
+    else setCurrentStep(session.next_trial === 0 ? -1 : session.next_trial);
+  };
+
+  const saveAnswer = async () => {
+    const response = await fetch(
+      `${VITE_BASE_API_URL}/experiments/sessions/${sessionId}/trials/${currentStep}`,
       {
-        sample_id: data!.sample_ids[currentStep as number],
-        azimuth: selection!.azimuth,
-        elevation: selection!.elevation
+        ...defaultRequestInit,
+        method: "PUT",
+        body: JSON.stringify({
+          azimuth: selection!.azimuth,
+          elevation: selection!.elevation,
+          speaker_id: selection!.speakerId
+        })
       }
-    ];
+    );
+    if (!response.ok) {
+      fireAlert("Could not save the answer", await response.text());
+    }
+    return response.ok;
   };
 
-  const nextSample = () => {
+  const nextSample = async () => {
+    if (!(await saveAnswer())) return;
     playerRef.current?.stop();
-    saveResult();
     setSelection(null);
     setHighlight(null);
-    if (currentStep === audioList.length - 1) setCurrentStep("end");
+    if (currentStep === trials.length - 1) setCurrentStep("end");
     else setCurrentStep((currentStep as number) + 1);
   };
 
   const showHighlight = () => {
-    setHighlight(sampleCoordinatesList[currentStep as number]);
+    setHighlight(sampleCoordinates[trials[currentStep as number].sample_id]);
+  };
+
+  const startSession = async (isTrainingMode: boolean, participant: string) => {
+    const response = await fetch(
+      `${VITE_BASE_API_URL}/experiments/${id}/sessions`,
+      {
+        ...defaultRequestInit,
+        method: "POST",
+        body: JSON.stringify({ participant, training: isTrainingMode })
+      }
+    );
+    if (!response.ok) {
+      fireAlert("Could not start the experiment", await response.text());
+      return;
+    }
+    continueSession(sessionSchema.parse(await response.json()));
   };
 
   if (isLoading || data == null) {
@@ -140,11 +216,9 @@ const ExperimentPage = () => {
     return (
       <StartInfo
         experimentName={data.name}
-        onStart={(isTrainingMode: boolean) => {
-          setTrainingMode(isTrainingMode);
-          setCurrentStep(-1);
-        }}
-        readyToStart={audioList.length > 0}
+        onStart={startSession}
+        onResume={resumable ? () => continueSession(resumable) : undefined}
+        readyToStart={!layoutId || !!speakerLayout}
       />
     );
 
@@ -152,8 +226,7 @@ const ExperimentPage = () => {
     return (
       <FinishInfo
         experimentId={id}
-        results={results.current}
-        isTraining={trainingMode}
+        sessionId={sessionId!}
       />
     );
 
@@ -163,7 +236,7 @@ const ExperimentPage = () => {
         <h1>{data.name}</h1>
         <ProgressWidget
           currentStep={currentStep}
-          totalSteps={data.sample_ids.length}
+          totalSteps={trials.length}
         />
       </div>
       <Stage
@@ -171,6 +244,7 @@ const ExperimentPage = () => {
         setSelection={currentStep >= 0 ? setSelection : () => {}}
         highlight={highlight}
         currentSample={currentStep}
+        speakers={speakerLayout?.speakers}
       />
       {currentStep === -1 && (
         <div className="absolute w-full h-full flex pointer-events-none">
@@ -190,6 +264,12 @@ const ExperimentPage = () => {
           >
             <p className="text-white font-semibold">
               Selected: <br />
+              {selection.speakerId && (
+                <>
+                  Speaker: {selection.speakerId}
+                  <br />
+                </>
+              )}
               Azimuth: {selection.azimuth}
               <br />
               Elevation: {selection.elevation}
@@ -240,15 +320,30 @@ const ProgressWidget = ({
   );
 };
 
+const sessionStorageKey = (experimentId: string) =>
+  `experiment-session-${experimentId}`;
+
 const StartInfo = ({
   experimentName,
   onStart,
+  onResume,
   readyToStart
 }: {
   experimentName: string;
-  onStart: (isTrainingMode: boolean) => void;
+  onStart: (isTrainingMode: boolean, participant: string) => void;
+  onResume?: () => void;
   readyToStart: boolean;
 }) => {
+  const [participant, setParticipant] = useState<string>("");
+
+  const start = (isTrainingMode: boolean) => {
+    if (participant.length === 0) {
+      fireAlert("Please enter your name");
+      return;
+    }
+    onStart(isTrainingMode, participant);
+  };
+
   return (
     <div className="w-full h-full flex flex-col items-center justify-center">
       <FrostedGlass className="flex flex-col items-center justify-center mx-xxl gap-xl">
@@ -262,22 +357,37 @@ const StartInfo = ({
           Training mode will show you the correct answer after each sample.
         </div>
         {readyToStart ? (
-          <div className="flex gap-xl">
-            <ButtonPrimary
-              onClick={() => onStart(true)}
-              disabled={!readyToStart}
-            >
-              Training mode
-            </ButtonPrimary>
-            <ButtonPrimary
-              onClick={() => {
-                onStart(false);
-              }}
-              disabled={!readyToStart}
-            >
-              Start experiment
-            </ButtonPrimary>
-          </div>
+          <>
+            <div className="flex flex-row items-center w-full">
+              <p className="pr-md">Name</p>
+              <input
+                className="flex-1 px-2 py-1"
+                type="text"
+                placeholder="name..."
+                onChange={(e) => setParticipant(e.target.value)}
+                onKeyDown={onEnterDown(() => start(false))}
+              />
+            </div>
+            <div className="flex gap-xl">
+              <ButtonPrimary
+                onClick={() => start(true)}
+                disabled={!readyToStart}
+              >
+                Training mode
+              </ButtonPrimary>
+              <ButtonPrimary
+                onClick={() => start(false)}
+                disabled={!readyToStart}
+              >
+                Start experiment
+              </ButtonPrimary>
+              {onResume && (
+                <ButtonSecondary onClick={onResume}>
+                  Resume previous session
+                </ButtonSecondary>
+              )}
+            </div>
+          </>
         ) : (
           <LoadingSpinner />
         )}
@@ -286,25 +396,17 @@ const StartInfo = ({
   );
 };
 
-const createResult = async (
-  experimentId: string,
-  username: string,
-  isTraining: boolean,
-  results: SampleResult[],
-  callback: (success: boolean) => void
+const finishSession = async (
+  sessionId: string,
+  callback: (success: boolean, message?: string) => void
 ): Promise<void> => {
   const { VITE_BASE_API_URL } = import.meta.env;
 
   const response = await fetch(
-    `${VITE_BASE_API_URL}/experiments/results/${experimentId}`,
+    `${VITE_BASE_API_URL}/experiments/sessions/${sessionId}/finish`,
     {
       ...defaultRequestInit,
-      method: "POST",
-      body: JSON.stringify({
-        sample_results: results,
-        training: isTraining,
-        user: username
-      })
+      method: "POST"
     }
   );
 
@@ -313,31 +415,26 @@ const createResult = async (
     return;
   }
 
-  callback(false);
+  callback(false, await response.text());
 };
 
 const FinishInfo = ({
   experimentId,
-  results,
-  isTraining: isTraining
+  sessionId
 }: {
   experimentId: string;
-  results: SampleResult[];
-  isTraining: boolean;
+  sessionId: string;
 }) => {
   const [resultSent, setResultSent] = useState<boolean>(false);
-  const [username, setUsername] = useState<string>("");
 
   const onResultsSave = () => {
-    if (username.length === 0) {
-      fireAlert("Please enter your name");
-      return;
-    }
-
-    createResult(experimentId, username, isTraining, results, (success) => {
+    finishSession(sessionId, (success, message) => {
       if (success) {
+        localStorage.removeItem(sessionStorageKey(experimentId));
         fireAlert("Results saved");
         setResultSent(true);
+      } else {
+        fireAlert("Could not save the results", message);
       }
     });
   };
@@ -354,19 +451,7 @@ const FinishInfo = ({
             <FaArrowLeft /> Return to Experiments
           </Link>
         ) : (
-          <>
-            <div className="flex flex-row items-center w-full">
-              <p className="pr-md">Name</p>
-              <input
-                className="flex-1 px-2 py-1"
-                type="text"
-                placeholder="name..."
-                onChange={(e) => setUsername(e.target.value)}
-                onKeyDown={onEnterDown(onResultsSave)}
-              />
-            </div>
-            <ButtonPrimary onClick={onResultsSave}>Save results</ButtonPrimary>
-          </>
+          <ButtonPrimary onClick={onResultsSave}>Save results</ButtonPrimary>
         )}
       </FrostedGlass>
     </div>
@@ -2,8 +2,19 @@ import { useQuery, useQueryClient } from "@tanstack/react-query";
 import { Link } from "@tanstack/react-router";
 import { fireAlert, fireConfirmationModal } from "components/AlertDialogs";
 import { ButtonSecondary } from "components/Buttons";
-import { FaTrash, FaArrowLeft, FaPlus, FaFile } from "react-icons/fa";
-import { experimentListSchema } from "schemas/experimentSchemas";
+import {
+  FaTrash,
+  FaArrowLeft,
+  FaPlus,
+  FaFile,
+  FaEye,
+  FaEyeSlash
+} from "react-icons/fa";
+import {
+  ExperimentState,
+  experimentListSchema,
+  experimentTransitions
+} from "schemas/experimentSchemas";
 import { FrostedGlass } from "../../components/FrostedGlass.tsx";
 import { defaultRequestInit } from "utils/fetchUtils.ts";
 import { useAuth } from "../../auth.ts";
@@ -28,6 +39,59 @@ const deleteExperiment = async (id: string, callback: () => void) => {
   }
 };
 
+const setExperimentPublic = async (
+  id: string,
+  isPublic: boolean,
+  callback: () => void
+) => {
+  const { VITE_BASE_API_URL } = import.meta.env;
+
+  try {
+    const response = await fetch(`${VITE_BASE_API_URL}/experiments/${id}`, {
+      ...defaultRequestInit,
+      method: "PATCH",
+      body: JSON.stringify({ is_public: isPublic })
+    });
+
+    if (response.ok) {
+      callback();
+    } else {
+      fireAlert("Could not change the experiment");
+    }
+  } catch (error) {
+    console.error(error);
+    fireAlert("Error occured", String(error));
+  }
+};
+
+const setExperimentState = async (
+  id: string,
+  state: ExperimentState,
+  callback: () => void
+) => {
+  const { VITE_BASE_API_URL } = import.meta.env;
+
+  try {
+    const response = await fetch(
+      `${VITE_BASE_API_URL}/experiments/${id}/state`,
+      {
+        ...defaultRequestInit,
+        method: "PUT",
+        body: JSON.stringify({ state })
+      }
+    );
+
+    if (response.ok) {
+      callback();
+    } else {
+      fireAlert("Could not change the experiment state", await response.text());
+    }
+  } catch (error) {
+    console.error(error);
+    fireAlert("Error occured", String(error));
+  }
+};
+
 const ExperimentsListPage = () => {
   const { VITE_BASE_API_URL } = import.meta.env;
   const { authenticated } = useAuth();
@@ -56,6 +120,18 @@ const ExperimentsListPage = () => {
     });
   };
 
+  const onTogglePublic = (id: string, isPublic: boolean) => {
+    setExperimentPublic(id, isPublic, () => {
+      queryClient.invalidateQueries({ queryKey: ["experiments"] });
+    });
+  };
+
+  const onChangeState = (id: string, state: ExperimentState) => {
+    setExperimentState(id, state, () => {
+      queryClient.invalidateQueries({ queryKey: ["experiments"] });
+    });
+  };
+
   if (isLoading) {
     return <p>Data is loading...</p>;
   }
@@ -92,7 +168,42 @@ const ExperimentsListPage = () => {
                 {experiment.name}
               </Link>
 
-              <div className="flex gap-xs">
+              <div className="flex gap-xs items-center">
+                {authenticated && (
+                  <select
+                    className="px-1 text-black"
+                    value={experiment.state}
+                    onChange={(e) =>
+                      onChangeState(
+                        experiment.id,
+                        e.target.value as ExperimentState
+                      )
+                    }
+                  >
+                    {[
+                      experiment.state,
+                      ...experimentTransitions[experiment.state]
+                    ].map((state) => (
+                      <option key={state} value={state}>
+                        {state}
+                      </option>
+                    ))}
+                  </select>
+                )}
+                {authenticated &&
+                  (experiment.is_public ? (
+                    <FaEye
+                      className="size-md text-white cursor-pointer"
+                      title="Public, click to hide"
+                      onClick={() => onTogglePublic(experiment.id, false)}
+                    />
+                  ) : (
+                    <FaEyeSlash
+                      className="size-md text-white cursor-pointer"
+                      title="Hidden, click to publish"
+                      onClick={() => onTogglePublic(experiment.id, true)}
+                    />
+                  ))}
                 {authenticated && (
                   <FaTrash
                     className="size-md text-red-500 cursor-pointer"
@@ -10,13 +10,21 @@ const createSample = async (
   name: string,
   azimuth: number,
   elevation: number,
+  tags: string[],
+  description: string,
   audio_file: File,
   callback: (success: boolean, statusCode: number) => void
 ): Promise<void> => {
   const { VITE_BASE_API_URL } = import.meta.env;
 
   const formData = new FormData();
-  formData.append("", JSON.stringify({ name, azimuth, elevation }));
+  formData.append("", JSON.stringify({
+    name,
+    azimuth,
+    elevation,
+    tags,
+    description: description === "" ? null : description
+  }));
   formData.append("", audio_file);
 
   const response = await fetch(`${VITE_BASE_API_URL}/audio`, {
@@ -40,6 +48,8 @@ const CreateSamplePage = () => {
   const [name, setName] = useState<string>("");
   const [azimuth, setAzimuth] = useState<number>(0);
   const [elevation, setElevation] = useState<number>(0);
+  const [tags, setTags] = useState<string>("");
+  const [description, setDescription] = useState<string>("");
 
   const [audioFile, setAudioFile] = useState<File>();
 
@@ -96,6 +106,8 @@ const CreateSamplePage = () => {
         name,
         validatedAzimuth,
         validatedElevation,
+        tags.split(",").map((tag) => tag.trim()).filter((tag) => tag !== ""),
+        description,
         audioFile,
         onCreated
       );
@@ -182,6 +194,33 @@ const CreateSamplePage = () => {
                 />
               </td>
             </tr>
+            <tr>
+              <td className="pr-md py-xs">
+                <p className="text-right">Tags</p>
+              </td>
+              <td>
+                <input
+                  className="w-full flex-1 px-2 py-1"
+                  type="text"
+                  placeholder="noise, hrtf..."
+                  onChange={(e) => setTags(e.target.value)}
+                  onKeyDown={onEnterDown(handleCreate)}
+                />
+              </td>
+            </tr>
+            <tr>
+              <td className="pr-md py-xs">
+                <p className="text-right">Description</p>
+              </td>
+              <td>
+                <textarea
+                  className="w-full flex-1 px-2 py-1"
+                  maxLength={1000}
+                  placeholder="description..."
+                  onChange={(e) => setDescription(e.target.value)}
+                />
+              </td>
+            </tr>
           </tbody>
         </table>
 
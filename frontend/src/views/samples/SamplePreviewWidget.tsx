@@ -1,4 +1,5 @@
 import { Sample } from "schemas/sampleSchemas";
+import { getSpectrogramPath } from "components/player/utils";
 
 const SamplePreviewWidget = ({ sample }: { sample: Sample }) => {
   return (
@@ -6,6 +7,13 @@ const SamplePreviewWidget = ({ sample }: { sample: Sample }) => {
       <p>Name: {sample.name}</p>
       <p>Azimuth: {sample.azimuth}</p>
       <p>Elevation: {sample.elevation}</p>
+      <img
+        src={getSpectrogramPath(sample.id, 240, 64)}
+        alt={`Spectrogram of ${sample.name}`}
+        width={240}
+        height={64}
+        loading="lazy"
+      />
     </div>
   );
 };
@@ -1,7 +1,21 @@
-import { useQuery, useQueryClient } from "@tanstack/react-query";
-import { sampleListSchema } from "schemas/sampleSchemas";
+import {
+  keepPreviousData,
+  useInfiniteQuery,
+  useQueryClient
+} from "@tanstack/react-query";
+import {
+  importReportSchema,
+  sampleInUseSchema,
+  samplePageSchema
+} from "schemas/sampleSchemas";
 import { Link } from "@tanstack/react-router";
-import { FaArrowLeft, FaPlus, FaTrash } from "react-icons/fa";
+import {
+  FaArrowLeft,
+  FaFileExport,
+  FaFileImport,
+  FaPlus,
+  FaTrash
+} from "react-icons/fa";
 import SamplePlayer from "components/player/SamplePlayer";
 import { getAudioPath } from "components/player/utils";
 import { FrostedGlass } from "../../components/FrostedGlass.tsx";
@@ -14,7 +28,7 @@ import {
 } from "../../components/AlertDialogs.tsx";
 import { ButtonSecondary } from "../../components/Buttons.tsx";
 
-const deleteSample = async (id: string, callback: (arg0: boolean, statusCode: number) => void) => {
+const deleteSample = async (id: string, callback: (arg0: boolean, statusCode: number, usedBy: string[]) => void) => {
   const { VITE_BASE_API_URL } = import.meta.env;
 
   try {
@@ -23,7 +37,41 @@ const deleteSample = async (id: string, callback: (arg0: boolean, statusCode: nu
       method: "DELETE"
     });
 
-    callback(response.ok, response.status);
+    const usedBy = response.status === 409
+      ? sampleInUseSchema.parse(await response.json()).experiments.map((experiment) => experiment.name)
+      : [];
+    callback(response.ok, response.status, usedBy);
+  } catch (error) {
+    console.error(error);
+    fireAlert("Error occured", String(error));
+  }
+};
+
+const importSamples = async (archive: File, onConflict: string) => {
+  const { VITE_BASE_API_URL } = import.meta.env;
+
+  const formData = new FormData();
+  formData.append("archive", archive);
+
+  try {
+    const response = await fetch(
+      `${VITE_BASE_API_URL}/audio/import?onConflict=${onConflict}`,
+      { method: "POST", body: formData, credentials: "include" }
+    );
+    if (response.status !== 200 && response.status !== 422) {
+      fireAlert("Failed to import samples", await response.text());
+      return;
+    }
+    const report = importReportSchema.parse(await response.json());
+    if (report.imported) {
+      const created = report.rows.filter((row) => row.status === "created");
+      fireAlert(`Imported ${created.length} of ${report.rows.length} samples`);
+    } else {
+      const failed = report.rows
+        .filter((row) => row.status === "failed")
+        .map((row) => `row ${row.row} (${row.file}): ${row.reason}`);
+      fireAlert("Nothing was imported", failed.join("\n"));
+    }
   } catch (error) {
     console.error(error);
     fireAlert("Error occured", String(error));
@@ -36,15 +84,40 @@ const SamplesListPage = () => {
 
   const playerRef = useRef<Howl>();
   const [playerStatus, setPlayerStatus] = useState<string | null>(null);
-  const getSamples = () =>
-    fetch(`${VITE_BASE_API_URL}/audio/all`, defaultRequestInit)
+  const [name, setName] = useState<string>("");
+  const [tags, setTags] = useState<string>("");
+  const [onConflict, setOnConflict] = useState<string>("fail");
+  const getSamples = (cursor: string | null) => {
+    const params = new URLSearchParams();
+    if (name.trim() !== "") params.set("name", name.trim());
+    if (tags.trim() !== "") params.set("tags", tags);
+    if (cursor) params.set("cursor", cursor);
+    return fetch(`${VITE_BASE_API_URL}/audio?${params}`, defaultRequestInit)
       .then((res) => res.json())
-      .then((data) => sampleListSchema.parse(data));
+      .then((data) => samplePageSchema.parse(data));
+  };
 
-  const { data, isLoading, error } = useQuery({
-    queryKey: ["samples"],
-    queryFn: getSamples
+  const {
+    data,
+    isLoading,
+    error,
+    fetchNextPage,
+    hasNextPage,
+    isFetchingNextPage
+  } = useInfiniteQuery({
+    queryKey: ["samples", name.trim(), tags],
+    queryFn: ({ pageParam }) => getSamples(pageParam),
+    initialPageParam: null as string | null,
+    getNextPageParam: (lastPage) => lastPage.nextCursor ?? null,
+    placeholderData: keepPreviousData
   });
+  const samples = data?.pages.flatMap((page) => page.samples);
+
+  const onImport = async (archive?: File) => {
+    if (!archive) return;
+    await importSamples(archive, onConflict);
+    queryClient.invalidateQueries({ queryKey: ["samples"] });
+  };
 
   const onDelete = async (id: string) => {
     await fireConfirmationModal({
@@ -52,11 +125,11 @@ const SamplesListPage = () => {
       body: "Are you sure you want to delete this sample?"
     }).then((result) => {
       if (result.isConfirmed) {
-        deleteSample(id, (success, statusCode) => {
+        deleteSample(id, (success, statusCode, usedBy) => {
           if (success) {
             fireAlert("Sample deleted successfully");
           } else if (statusCode === 409) {
-            fireAlert("Sample is used in experiments", "You can't remove it without removing those experiments");
+            fireAlert("Sample is used in experiments", `You can't remove it without removing those experiments: ${usedBy.join(", ")}`);
           } else {
             fireAlert("Failed to delete sample, check if it's used in experiments");
           }
@@ -80,15 +153,61 @@ const SamplesListPage = () => {
         <Link to="../" className="flex gap-xs items-center">
           <FaArrowLeft /> Return to Home Page
         </Link>
-        <Link to="/samples/create" className="flex gap-xs items-center">
-          <FaPlus /> Add new sample
-        </Link>
+        <div className="flex gap-md items-center">
+          <label className="flex gap-xs items-center cursor-pointer">
+            <FaFileImport /> Import ZIP
+            <input
+              type="file"
+              accept=".zip,application/zip"
+              className="hidden"
+              onChange={(e) => {
+                onImport(e.target.files?.[0]);
+                e.target.value = "";
+              }}
+            />
+          </label>
+          <select
+            value={onConflict}
+            onChange={(e) => setOnConflict(e.target.value)}
+            title="Taken sample names"
+          >
+            <option value="fail">fail on taken names</option>
+            <option value="skip">skip taken names</option>
+            <option value="rename">rename taken names</option>
+          </select>
+          <a
+            href={`${import.meta.env.VITE_BASE_API_URL}/audio/export`}
+            download="samples.zip"
+            className="flex gap-xs items-center"
+          >
+            <FaFileExport /> Export ZIP
+          </a>
+          <Link to="/samples/create" className="flex gap-xs items-center">
+            <FaPlus /> Add new sample
+          </Link>
+        </div>
       </div>
       <FrostedGlass className="flex flex-col items-center">
         <h1>Samples</h1>
+        <div className="mt-md flex gap-md">
+          <input
+            className="px-2 py-1"
+            type="text"
+            placeholder="search by name..."
+            value={name}
+            onChange={(e) => setName(e.target.value)}
+          />
+          <input
+            className="px-2 py-1"
+            type="text"
+            placeholder="tags, comma separated..."
+            value={tags}
+            onChange={(e) => setTags(e.target.value)}
+          />
+        </div>
         <ul className="mt-md grid grid-cols-4 gap-lg">
-          {data?.length === 0 && <p>No samples found.</p>}
-          {data?.map((sample) => (
+          {samples?.length === 0 && <p>No samples found.</p>}
+          {samples?.map((sample) => (
             <li key={sample.id} className="py-sm">
               <FrostedGlass
                 className="flex flex-col items-center"
@@ -107,6 +226,23 @@ const SamplesListPage = () => {
                 </div>
                 <p>Azimuth: {sample.azimuth}</p>
                 <p>Elevation: {sample.elevation}</p>
+                {sample.distance != null && <p>Distance: {sample.distance} m</p>}
+                {sample.tags.length > 0 && (
+                  <p className="text-sm">{sample.tags.join(", ")}</p>
+                )}
+                {sample.description && (
+                  <p className="text-sm max-w-48 text-center">
+                    {sample.description}
+                  </p>
+                )}
+                {sample.format && (
+                  <p className="text-sm">
+                    {sample.format.codec}, {sample.format.sampleRate} Hz,{" "}
+                    {sample.format.channels} ch
+                    {sample.format.bitDepth && `, ${sample.format.bitDepth} bit`},{" "}
+                    {sample.format.duration.toFixed(2)} s
+                  </p>
+                )}
                 <FaTrash
                   className="size-md text-red-500 cursor-pointer mt-sm"
                   onClick={() => onDelete(sample.id)}
@@ -115,6 +251,15 @@ const SamplesListPage = () => {
             </li>
           ))}
         </ul>
+        {hasNextPage && (
+          <ButtonSecondary
+            className="mt-md"
+            onClick={() => fetchNextPage()}
+            disabled={isFetchingNextPage}
+          >
+            Load more
+          </ButtonSecondary>
+        )}
         <Link to="/samples/create" className="mt-lg w-full flex flex-col">
           <ButtonSecondary>Add new sample</ButtonSecondary>
         </Link>